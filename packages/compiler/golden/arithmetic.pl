@@ -0,0 +1,6 @@
+;; Arithmetic folds the arguments from left to right
+(+ 1 2 3)
+(- 10 4 1)
+(- 3)
+(/ 4)
+(* (+ 1/2 1/4) 2.5)
@@ -0,0 +1,9 @@
+(module
+  (type (;0;) (func (result f32)))
+  (func (;0;) (type 0) (result f32)
+    (drop (f32.add (f32.add (f32.const 1) (f32.const 2)) (f32.const 3)))
+    (drop (f32.sub (f32.sub (f32.const 10) (f32.const 4)) (f32.const 1)))
+    (drop (f32.neg (f32.const 3)))
+    (drop (f32.div (f32.const 1) (f32.const 4)))
+    (f32.mul (f32.add (f32.const 0.5) (f32.const 0.25)) (f32.const 2.5)))
+  (export "run" (func 0)))
@@ -0,0 +1,4 @@
+; closures capturing a local, called through the function table
+(def adder (fn [n] #(+ % n)))
+(def twice (fn [f x] (f (f x))))
+(twice (adder 3) 1)
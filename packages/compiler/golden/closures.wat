@@ -0,0 +1,129 @@
+(module
+  (type (;0;) (func (param i32 f32) (result f32)))
+  (type (;1;) (func (param i32 f32 f32) (result f32)))
+  (type (;2;) (func (result f32)))
+  (table (;0;) 3 funcref)
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 16))
+  (global (;1;) (mut i32) (i32.const 0))
+  (global (;2;) (mut f32) (f32.const 0))
+  (global (;3;) (mut f32) (f32.const 0))
+  (func (;0;) (type 0) (param i32 f32) (result f32)
+    (local i32)
+    (global.get 0)
+    (global.set 0 (i32.add (global.get 0) (i32.const 24)))
+    (i32.gt_u (global.get 0) (i32.const 2139095040))
+    if
+      (global.set 1 (i32.const 3))
+      unreachable
+    end
+    (i32.gt_u (i32.shr_u (i32.add (global.get 0) (i32.const 65535)) (i32.const 16)) (memory.size))
+    if
+      (i32.eq (memory.grow (i32.sub (i32.shr_u (i32.add (global.get 0) (i32.const 65535)) (i32.const 16)) (memory.size))) (i32.const -1))
+      if
+        (global.set 1 (i32.const 3))
+        unreachable
+      end
+    end
+    local.set 2
+    (i32.store offset=0 align=4 (local.get 2) (i32.const 3))
+    (i32.store offset=4 align=4 (local.get 2) (i32.const 1))
+    (i32.store offset=8 align=4 (local.get 2) (i32.const 1))
+    (i32.store offset=12 align=4 (local.get 2) (i32.const 1))
+    (f32.store offset=16 align=4 (local.get 2) (local.get 1))
+    (f32.reinterpret_i32 (local.get 2)))
+  (func (;1;) (type 0) (param i32 f32) (result f32)
+    (local f32)
+    (local.set 2 (f32.load offset=16 align=4 (local.get 0)))
+    (f32.add (local.get 1) (local.get 2)))
+  (func (;2;) (type 1) (param i32 f32 f32) (result f32)
+    (local f32 i32 f32 i32)
+    (local.set 3 (local.get 1))
+    (local.set 4 (i32.reinterpret_f32 (local.get 3)))
+    (i32.eqz (i32.and (i32.ge_u (local.get 4) (i32.const 16)) (i32.le_u (local.get 4) (i32.sub (i32.shl (memory.size) (i32.const 16)) (i32.const 16)))))
+    if
+      (global.set 1 (i32.const 1))
+      unreachable
+    end
+    (i32.eqz (i32.eq (i32.load offset=0 align=4 (local.get 4)) (i32.const 3)))
+    if
+      (global.set 1 (i32.const 1))
+      unreachable
+    end
+    (i32.eqz (i32.eq (i32.load offset=12 align=4 (local.get 4)) (i32.const 1)))
+    if
+      (global.set 1 (i32.const 2))
+      unreachable
+    end
+    (local.get 4)
+    (local.set 5 (local.get 1))
+    (local.set 6 (i32.reinterpret_f32 (local.get 5)))
+    (i32.eqz (i32.and (i32.ge_u (local.get 6) (i32.const 16)) (i32.le_u (local.get 6) (i32.sub (i32.shl (memory.size) (i32.const 16)) (i32.const 16)))))
+    if
+      (global.set 1 (i32.const 1))
+      unreachable
+    end
+    (i32.eqz (i32.eq (i32.load offset=0 align=4 (local.get 6)) (i32.const 3)))
+    if
+      (global.set 1 (i32.const 1))
+      unreachable
+    end
+    (i32.eqz (i32.eq (i32.load offset=12 align=4 (local.get 6)) (i32.const 1)))
+    if
+      (global.set 1 (i32.const 2))
+      unreachable
+    end
+    (call_indirect (type 0) (local.get 6) (local.get 2) (i32.load offset=8 align=4 (local.get 6)))
+    (i32.load offset=8 align=4 (local.get 4))
+    call_indirect (type 0))
+  (func (;3;) (type 2) (result f32)
+    (local i32 i32)
+    (global.get 0)
+    (global.set 0 (i32.add (global.get 0) (i32.const 16)))
+    (i32.gt_u (global.get 0) (i32.const 2139095040))
+    if
+      (global.set 1 (i32.const 3))
+      unreachable
+    end
+    (i32.gt_u (i32.shr_u (i32.add (global.get 0) (i32.const 65535)) (i32.const 16)) (memory.size))
+    if
+      (i32.eq (memory.grow (i32.sub (i32.shr_u (i32.add (global.get 0) (i32.const 65535)) (i32.const 16)) (memory.size))) (i32.const -1))
+      if
+        (global.set 1 (i32.const 3))
+        unreachable
+      end
+    end
+    local.set 0
+    (i32.store offset=0 align=4 (local.get 0) (i32.const 3))
+    (i32.store offset=4 align=4 (local.get 0) (i32.const 0))
+    (i32.store offset=8 align=4 (local.get 0) (i32.const 0))
+    (i32.store offset=12 align=4 (local.get 0) (i32.const 1))
+    (global.set 2 (f32.reinterpret_i32 (local.get 0)))
+    (drop (global.get 2))
+    (global.get 0)
+    (global.set 0 (i32.add (global.get 0) (i32.const 16)))
+    (i32.gt_u (global.get 0) (i32.const 2139095040))
+    if
+      (global.set 1 (i32.const 3))
+      unreachable
+    end
+    (i32.gt_u (i32.shr_u (i32.add (global.get 0) (i32.const 65535)) (i32.const 16)) (memory.size))
+    if
+      (i32.eq (memory.grow (i32.sub (i32.shr_u (i32.add (global.get 0) (i32.const 65535)) (i32.const 16)) (memory.size))) (i32.const -1))
+      if
+        (global.set 1 (i32.const 3))
+        unreachable
+      end
+    end
+    local.set 1
+    (i32.store offset=0 align=4 (local.get 1) (i32.const 3))
+    (i32.store offset=4 align=4 (local.get 1) (i32.const 0))
+    (i32.store offset=8 align=4 (local.get 1) (i32.const 2))
+    (i32.store offset=12 align=4 (local.get 1) (i32.const 2))
+    (global.set 3 (f32.reinterpret_i32 (local.get 1)))
+    (drop (global.get 3))
+    (call 2 (i32.reinterpret_f32 (global.get 3)) (call 0 (i32.reinterpret_f32 (global.get 2)) (f32.const 3)) (f32.const 1)))
+  (export "run" (func 3))
+  (export "error" (global 1))
+  (export "memory" (memory 0))
+  (elem (;0;) (i32.const 0) func 0 1 2))
@@ -0,0 +1,3 @@
+;; sets are allocated when evaluated, regexes are stored in the data segment
+(def digits #"[0-9]+")
+(+ #{1 digits})
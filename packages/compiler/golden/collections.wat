@@ -0,0 +1,64 @@
+(module
+  (type (;0;) (func (result f32)))
+  (memory (;0;) 1)
+  (global (;0;) (mut f32) (f32.const 0))
+  (global (;1;) (mut i32) (i32.const 32))
+  (global (;2;) (mut i32) (i32.const 0))
+  (func (;0;) (type 0) (result f32)
+    (local i32 i32 f32 i32)
+    (global.set 0 (f32.reinterpret_i32 (i32.const 16)))
+    (drop (global.get 0))
+    (global.get 1)
+    (global.set 1 (i32.add (global.get 1) (i32.const 16)))
+    (i32.gt_u (global.get 1) (i32.const 2139095040))
+    if
+      (global.set 2 (i32.const 3))
+      unreachable
+    end
+    (i32.gt_u (i32.shr_u (i32.add (global.get 1) (i32.const 65535)) (i32.const 16)) (memory.size))
+    if
+      (i32.eq (memory.grow (i32.sub (i32.shr_u (i32.add (global.get 1) (i32.const 65535)) (i32.const 16)) (memory.size))) (i32.const -1))
+      if
+        (global.set 2 (i32.const 3))
+        unreachable
+      end
+    end
+    local.set 0
+    (local.set 1 (i32.const 0))
+    (local.set 2 (f32.const 1))
+    (local.set 3 (i32.const 0))
+    block
+      loop
+        (br_if 1 (i32.ge_u (local.get 3) (local.get 1)))
+        (br_if 1 (f32.eq (f32.load offset=8 align=4 (i32.add (local.get 0) (i32.shl (local.get 3) (i32.const 2)))) (local.get 2)))
+        (local.set 3 (i32.add (local.get 3) (i32.const 1)))
+        br 0
+      end
+    end
+    (i32.eq (local.get 3) (local.get 1))
+    if
+      (f32.store offset=8 align=4 (i32.add (local.get 0) (i32.shl (local.get 1) (i32.const 2))) (local.get 2))
+      (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+    end
+    (local.set 2 (global.get 0))
+    (local.set 3 (i32.const 0))
+    block
+      loop
+        (br_if 1 (i32.ge_u (local.get 3) (local.get 1)))
+        (br_if 1 (f32.eq (f32.load offset=8 align=4 (i32.add (local.get 0) (i32.shl (local.get 3) (i32.const 2)))) (local.get 2)))
+        (local.set 3 (i32.add (local.get 3) (i32.const 1)))
+        br 0
+      end
+    end
+    (i32.eq (local.get 3) (local.get 1))
+    if
+      (f32.store offset=8 align=4 (i32.add (local.get 0) (i32.shl (local.get 1) (i32.const 2))) (local.get 2))
+      (local.set 1 (i32.add (local.get 1) (i32.const 1)))
+    end
+    (i32.store offset=0 align=4 (local.get 0) (i32.const 1))
+    (i32.store offset=4 align=4 (local.get 0) (local.get 1))
+    (f32.reinterpret_i32 (local.get 0)))
+  (export "run" (func 0))
+  (export "error" (global 2))
+  (export "memory" (memory 0))
+  (data (;0;) (i32.const 16) "\02\00\00\00\06\00\00\00[0-9]+\00\00"))
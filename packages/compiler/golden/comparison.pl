@@ -0,0 +1,4 @@
+;; Comparisons convert the i32 result to f32
+(< 1 2)
+(not= 1 (+ 1 0))
+(= true false)
@@ -0,0 +1,7 @@
+(module
+  (type (;0;) (func (result f32)))
+  (func (;0;) (type 0) (result f32)
+    (drop (f32.convert_i32_s (f32.lt (f32.const 1) (f32.const 2))))
+    (drop (f32.convert_i32_s (f32.ne (f32.const 1) (f32.add (f32.const 1) (f32.const 0)))))
+    (f32.convert_i32_s (f32.eq (f32.const 1) (f32.const 0))))
+  (export "run" (func 0)))
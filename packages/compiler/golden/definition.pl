@@ -0,0 +1,4 @@
+;; Definitions are mutable globals
+(def x 2)
+(def y (* x 3))
+(def x (+ x y))
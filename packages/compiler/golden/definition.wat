@@ -0,0 +1,12 @@
+(module
+  (type (;0;) (func (result f32)))
+  (global (;0;) (mut f32) (f32.const 0))
+  (global (;1;) (mut f32) (f32.const 0))
+  (func (;0;) (type 0) (result f32)
+    (global.set 0 (f32.const 2))
+    (drop (global.get 0))
+    (global.set 1 (f32.mul (global.get 0) (f32.const 3)))
+    (drop (global.get 1))
+    (global.set 0 (f32.add (global.get 0) (global.get 1)))
+    (global.get 0))
+  (export "run" (func 0)))
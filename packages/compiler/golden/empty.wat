@@ -0,0 +1,5 @@
+(module
+  (type (;0;) (func (result f32)))
+  (func (;0;) (type 0) (result f32)
+    (f32.const 0))
+  (export "run" (func 0)))
@@ -0,0 +1,12 @@
+;; Comments between a reader macro and its target
+(def x
+#_ ; the old value
+1
+2)
+(list #_ ;# block #; 3 4)
+(defmacro m [y]
+  `(list '
+ ; quoted
+ y))
+(list #_#_ a   b
+(c))
@@ -0,0 +1,6 @@
+; functions calling themselves, directly and with recur
+(def fact (fn [n] (if (<= n 1) 1 (* n (fact (- n 1))))))
+(def sum (fn [n acc] (if (= n 0) acc (recur (- n 1) (+ acc n)))))
+(let [x (fact 4)]
+  (loop [i 0]
+    (if (< i x) (recur (+ i (sum 2 0))) i)))
@@ -0,0 +1,90 @@
+(module
+  (type (;0;) (func (param i32 f32) (result f32)))
+  (type (;1;) (func (param i32 f32 f32) (result f32)))
+  (type (;2;) (func (result f32)))
+  (table (;0;) 2 funcref)
+  (memory (;0;) 1)
+  (global (;0;) (mut f32) (f32.const 0))
+  (global (;1;) (mut i32) (i32.const 16))
+  (global (;2;) (mut i32) (i32.const 0))
+  (global (;3;) (mut f32) (f32.const 0))
+  (func (;0;) (type 0) (param i32 f32) (result f32)
+    (f32.ne (f32.convert_i32_s (f32.le (local.get 1) (f32.const 1))) (f32.const 0))
+    if (result f32)
+      (f32.const 1)
+    else
+      (f32.mul (local.get 1) (call 0 (i32.reinterpret_f32 (global.get 0)) (f32.sub (local.get 1) (f32.const 1))))
+    end)
+  (func (;1;) (type 1) (param i32 f32 f32) (result f32)
+    loop (result f32)
+      (f32.ne (f32.convert_i32_s (f32.eq (local.get 1) (f32.const 0))) (f32.const 0))
+      if (result f32)
+        (local.get 2)
+      else
+        (f32.sub (local.get 1) (f32.const 1))
+        (local.set 2 (f32.add (local.get 2) (local.get 1)))
+        local.set 1
+        br 1
+      end
+    end)
+  (func (;2;) (type 2) (result f32)
+    (local i32 i32 f32 f32)
+    (global.get 1)
+    (global.set 1 (i32.add (global.get 1) (i32.const 16)))
+    (i32.gt_u (global.get 1) (i32.const 2139095040))
+    if
+      (global.set 2 (i32.const 3))
+      unreachable
+    end
+    (i32.gt_u (i32.shr_u (i32.add (global.get 1) (i32.const 65535)) (i32.const 16)) (memory.size))
+    if
+      (i32.eq (memory.grow (i32.sub (i32.shr_u (i32.add (global.get 1) (i32.const 65535)) (i32.const 16)) (memory.size))) (i32.const -1))
+      if
+        (global.set 2 (i32.const 3))
+        unreachable
+      end
+    end
+    local.set 0
+    (i32.store offset=0 align=4 (local.get 0) (i32.const 3))
+    (i32.store offset=4 align=4 (local.get 0) (i32.const 0))
+    (i32.store offset=8 align=4 (local.get 0) (i32.const 0))
+    (i32.store offset=12 align=4 (local.get 0) (i32.const 1))
+    (global.set 0 (f32.reinterpret_i32 (local.get 0)))
+    (drop (global.get 0))
+    (global.get 1)
+    (global.set 1 (i32.add (global.get 1) (i32.const 16)))
+    (i32.gt_u (global.get 1) (i32.const 2139095040))
+    if
+      (global.set 2 (i32.const 3))
+      unreachable
+    end
+    (i32.gt_u (i32.shr_u (i32.add (global.get 1) (i32.const 65535)) (i32.const 16)) (memory.size))
+    if
+      (i32.eq (memory.grow (i32.sub (i32.shr_u (i32.add (global.get 1) (i32.const 65535)) (i32.const 16)) (memory.size))) (i32.const -1))
+      if
+        (global.set 2 (i32.const 3))
+        unreachable
+      end
+    end
+    local.set 1
+    (i32.store offset=0 align=4 (local.get 1) (i32.const 3))
+    (i32.store offset=4 align=4 (local.get 1) (i32.const 0))
+    (i32.store offset=8 align=4 (local.get 1) (i32.const 1))
+    (i32.store offset=12 align=4 (local.get 1) (i32.const 2))
+    (global.set 3 (f32.reinterpret_i32 (local.get 1)))
+    (drop (global.get 3))
+    (local.set 2 (call 0 (i32.reinterpret_f32 (global.get 0)) (f32.const 4)))
+    (local.set 3 (f32.const 0))
+    loop (result f32)
+      (f32.ne (f32.convert_i32_s (f32.lt (local.get 3) (local.get 2))) (f32.const 0))
+      if (result f32)
+        (local.set 3 (f32.add (local.get 3) (call 1 (i32.reinterpret_f32 (global.get 3)) (f32.const 2) (f32.const 0))))
+        br 1
+      else
+        (local.get 3)
+      end
+    end)
+  (export "run" (func 2))
+  (export "error" (global 2))
+  (export "memory" (memory 0))
+  (elem (;0;) (i32.const 0) func 0 1))
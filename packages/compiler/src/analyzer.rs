@@ -0,0 +1,900 @@
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::{first_span, ExpressionList, ExpressionNode, Program, Scope, Symbol};
+use crate::token::Span;
+
+/// Forms with their own evaluation rules, compiled by the analyzer.
+pub const SPECIAL_FORMS: &[&str] = &["def", "fn", "let", "if", "do", "loop", "recur"];
+
+/// Special forms and core functions, which cannot be redefined by macros.
+pub const BUILTINS: &[&str] = &[
+    "def", "fn", "let", "if", "do", "loop", "recur", "+", "-", "*", "/", "=", "not=", "<", ">",
+    "<=", ">=",
+];
+
+/// Tells whether the name is a special form of the language: one of [`SPECIAL_FORMS`],
+/// or `defmacro`, which the macro expander handles before the analysis.
+pub fn is_special_form(name: &str) -> bool {
+    SPECIAL_FORMS.contains(&name) || name == "defmacro"
+}
+
+/// Typed form of a program, checked by the semantic analysis and consumed by the code
+/// generator. Literals are lowered to their runtime values and the special forms have
+/// their own nodes, every other list is a call.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+    /// Number or boolean, `true` is `1` and `false` is `0`
+    Number(f32),
+    /// `#{...}`, literal elements are unique, equal values of the others are stored once
+    Set(Vec<Expression>),
+    /// `#"..."`
+    Regex(String),
+    /// Global, parameter or local binding
+    Variable(Symbol),
+    /// `(def name value)`
+    Define {
+        name: String,
+        value: Box<Expression>,
+    },
+    /// `(fn [params] body)`
+    Function(Function),
+    /// `(if test then else)`, every value except `0` is true. Without else branch the value is `0`.
+    If {
+        test: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+    /// `(do forms)` with at least two forms, the value is the value of the last form
+    Do(Vec<Expression>),
+    /// `(let [name value ...] body)`
+    Let {
+        bindings: Vec<Binding>,
+        body: Box<Expression>,
+    },
+    /// `(loop [name value ...] body)`, a `recur` in the body starts the next iteration
+    Loop {
+        bindings: Vec<Binding>,
+        body: Box<Expression>,
+    },
+    /// `(recur values)` in tail position, rebinds the names of the innermost `loop` or `fn`
+    Recur(Vec<Expression>),
+    /// Call of a core function, with the arguments checked against its arity
+    Builtin(Builtin, Vec<Expression>),
+    Call {
+        callee: Box<Expression>,
+        args: Vec<Expression>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Binding {
+    pub name: String,
+    pub value: Expression,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Box<Expression>,
+    /// The body has a `recur`, so it is repeated with new arguments
+    pub recurs: bool,
+}
+
+/// Core functions, each compiled to a few instructions.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Builtin {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Some(match name {
+            "+" => Builtin::Add,
+            "-" => Builtin::Subtract,
+            "*" => Builtin::Multiply,
+            "/" => Builtin::Divide,
+            "=" => Builtin::Equal,
+            "not=" => Builtin::NotEqual,
+            "<" => Builtin::Less,
+            ">" => Builtin::Greater,
+            "<=" => Builtin::LessOrEqual,
+            ">=" => Builtin::GreaterOrEqual,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Add => "+",
+            Builtin::Subtract => "-",
+            Builtin::Multiply => "*",
+            Builtin::Divide => "/",
+            Builtin::Equal => "=",
+            Builtin::NotEqual => "not=",
+            Builtin::Less => "<",
+            Builtin::Greater => ">",
+            Builtin::LessOrEqual => "<=",
+            Builtin::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+/// Checks the special forms and the arity of the core functions of an expanded program
+/// and returns its IR, one expression per top-level form.
+///
+/// On failure, it returns the first error of every invalid top-level form, located at
+/// the head of the innermost form, or its first symbol when the head has no location.
+pub fn analyze(program: &Program) -> Result<Vec<Expression>, Vec<Diagnostic>> {
+    let mut analyzer = Analyzer::default();
+    let mut expressions = vec![];
+    let mut diagnostics = vec![];
+    for form in program {
+        match analyzer.list(form, false) {
+            Ok(expression) => expressions.push(expression),
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(expressions)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Target of `recur`, the innermost `loop` or `fn`
+struct Target {
+    /// `None` in `#(...)` until its first `recur`, its parameters are known after the body
+    arity: Option<usize>,
+    recurs: bool,
+}
+
+#[derive(Default)]
+struct Analyzer {
+    targets: Vec<Target>,
+    /// Number of parameters used so far by the enclosing `#(...)`
+    anonymous: Option<usize>,
+    /// Location of the errors of the innermost form
+    span: Span,
+}
+
+impl Analyzer {
+    fn error(&self, code: ErrorCode, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::error(code, self.span, message)
+    }
+
+    /// Diagnostic located at the node, or at the form when the node has no location.
+    fn error_at(
+        &self,
+        node: &ExpressionNode,
+        code: ErrorCode,
+        message: impl Into<String>,
+    ) -> Diagnostic {
+        Diagnostic::error(code, node.span().unwrap_or(self.span), message)
+    }
+
+    /// `tail` tells whether the value of the expression is the value of the innermost
+    /// `loop` or `fn`, where `recur` is allowed.
+    fn expression(&mut self, node: &ExpressionNode, tail: bool) -> Result<Expression, Diagnostic> {
+        Ok(match node {
+            ExpressionNode::FunctionCall(list) => self.list(list, tail)?,
+            ExpressionNode::Identifier(symbol) => self.variable(symbol)?,
+            ExpressionNode::AnonymousFunction(list) => {
+                Expression::Function(self.anonymous_function(list)?)
+            }
+            ExpressionNode::Set(elements) => self.set(elements)?,
+            ExpressionNode::Regex(pattern, _) => Expression::Regex(pattern.clone()),
+            _ => match constant(node) {
+                Some(value) if value.is_finite() => Expression::Number(value),
+                Some(_) => {
+                    return Err(self
+                        .error_at(
+                            node,
+                            ErrorCode::InvalidNumber,
+                            format!("Number {} is out of range", node),
+                        )
+                        .with_help("numbers are stored as 32-bit floats"))
+                }
+                None => {
+                    return Err(self.error_at(
+                        node,
+                        ErrorCode::InvalidForm,
+                        format!("Unsupported expression {}", node),
+                    ))
+                }
+            },
+        })
+    }
+
+    fn expressions(&mut self, nodes: &[ExpressionNode]) -> Result<Vec<Expression>, Diagnostic> {
+        nodes
+            .iter()
+            .map(|node| self.expression(node, false))
+            .collect()
+    }
+
+    fn list(&mut self, list: &ExpressionList, tail: bool) -> Result<Expression, Diagnostic> {
+        let outer = self.span;
+        self.span = first_span(list).unwrap_or(outer);
+        let result = self.form(list, tail);
+        self.span = outer;
+        result
+    }
+
+    fn form(&mut self, list: &ExpressionList, tail: bool) -> Result<Expression, Diagnostic> {
+        let (symbol, args) = match list.split_first() {
+            Some((ExpressionNode::Identifier(symbol), args)) => (symbol, args),
+            Some((
+                callee @ (ExpressionNode::FunctionCall(_) | ExpressionNode::AnonymousFunction(_)),
+                args,
+            )) => {
+                return Ok(Expression::Call {
+                    callee: Box::new(self.expression(callee, false)?),
+                    args: self.expressions(args)?,
+                })
+            }
+            Some((callee, _)) => {
+                return Err(self.error_at(
+                    callee,
+                    ErrorCode::InvalidForm,
+                    format!("Expected a function name, but get {}", callee),
+                ))
+            }
+            None => return Err(self.error(ErrorCode::InvalidForm, "Cannot evaluate an empty list")),
+        };
+        let name = symbol.name.as_str();
+        match name {
+            "def" => self.define(args),
+            "fn" => Ok(Expression::Function(self.function(args)?)),
+            "if" => self.condition(args, tail),
+            "do" => self.body(args, tail),
+            "let" => {
+                let (bindings, body) = self.bindings(name, args)?;
+                Ok(Expression::Let {
+                    bindings,
+                    body: Box::new(self.body(body, tail)?),
+                })
+            }
+            "loop" => {
+                let (bindings, body) = self.bindings(name, args)?;
+                self.targets.push(Target {
+                    arity: Some(bindings.len()),
+                    recurs: false,
+                });
+                let body = self.body(body, true);
+                self.targets.pop();
+                Ok(Expression::Loop {
+                    bindings,
+                    body: Box::new(body?),
+                })
+            }
+            "recur" => self.recur(args, tail),
+            // a local or global shadows the core function, as found by the resolver
+            _ => match Builtin::from_name(name) {
+                Some(builtin) if matches!(symbol.scope, Scope::Builtin | Scope::Unresolved) => {
+                    self.builtin(builtin, args)
+                }
+                _ => Ok(Expression::Call {
+                    callee: Box::new(Expression::Variable(symbol.clone())),
+                    args: self.expressions(args)?,
+                }),
+            },
+        }
+    }
+
+    /// `(def name value)` stores the value in a global and returns it,
+    /// `(def name "docstring" value)` documents the definition for the tools.
+    fn define(&mut self, args: &[ExpressionNode]) -> Result<Expression, Diagnostic> {
+        let (name, value) = match args {
+            [ExpressionNode::Identifier(Symbol { name, .. }), value]
+            | [ExpressionNode::Identifier(Symbol { name, .. }), ExpressionNode::StringLiteral(..), value] => {
+                (name, value)
+            }
+            [_, _] | [_, _, _] => {
+                return Err(self.error(
+                    ErrorCode::InvalidForm,
+                    "'def' expects a name as first argument",
+                ))
+            }
+            _ => {
+                return Err(self.error(
+                    ErrorCode::ArityMismatch,
+                    format!("'def' expects 2 arguments, but get {}", args.len()),
+                ))
+            }
+        };
+        Ok(Expression::Define {
+            name: name.clone(),
+            value: Box::new(self.expression(value, false)?),
+        })
+    }
+
+    /// `(fn [params] body)`, the body is the target of its `recur` forms.
+    fn function(&mut self, args: &[ExpressionNode]) -> Result<Function, Diagnostic> {
+        let (params, body) = match args {
+            [ExpressionNode::Array(params), body @ ..] => (params, body),
+            _ => {
+                return Err(self
+                    .error(ErrorCode::InvalidForm, "'fn' expects a parameter vector")
+                    .with_help("the parameters follow `fn` in brackets, like `(fn [x] x)`"))
+            }
+        };
+        let params = params
+            .iter()
+            .map(|param| match param {
+                ExpressionNode::Identifier(symbol) => Ok(symbol.name.clone()),
+                _ => Err(self.error(
+                    ErrorCode::InvalidForm,
+                    format!("'fn' expects names as parameters, but get {}", param),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.targets.push(Target {
+            arity: Some(params.len()),
+            recurs: false,
+        });
+        let body = self.body(body, true);
+        let target = self.targets.pop().unwrap();
+        Ok(Function {
+            params,
+            body: Box::new(body?),
+            recurs: target.recurs,
+        })
+    }
+
+    /// `#(body)` is a function whose parameters are `%1`, `%2`, ... up to the highest
+    /// one used in the body, `%` is `%1`.
+    fn anonymous_function(&mut self, list: &ExpressionList) -> Result<Function, Diagnostic> {
+        if self.anonymous.is_some() {
+            return Err(Diagnostic::error(
+                ErrorCode::InvalidForm,
+                first_span(list).unwrap_or(self.span),
+                "Nested '#()' functions are not allowed",
+            )
+            .with_help("use `fn` for the inner function"));
+        }
+        self.anonymous = Some(0);
+        self.targets.push(Target {
+            arity: None,
+            recurs: false,
+        });
+        let body = self.list(list, true);
+        let target = self.targets.pop().unwrap();
+        let arity = self.anonymous.take().unwrap();
+        let body = body?;
+        if let Some(recur_arity) = target.arity.filter(|recur_arity| *recur_arity != arity) {
+            return Err(Diagnostic::error(
+                ErrorCode::ArityMismatch,
+                first_span(list).unwrap_or(self.span),
+                format!(
+                    "'recur' expects {} arguments, but get {}",
+                    arity, recur_arity
+                ),
+            ));
+        }
+        Ok(Function {
+            params: (1..=arity).map(|index| format!("%{}", index)).collect(),
+            body: Box::new(body),
+            recurs: target.recurs,
+        })
+    }
+
+    /// Parameters of `#(...)` are renamed to `%1`, `%2`, ... Special forms and core
+    /// functions are not values, they can only be called.
+    fn variable(&mut self, symbol: &Symbol) -> Result<Expression, Diagnostic> {
+        if symbol.scope == Scope::Builtin {
+            let name = &symbol.name;
+            let error = |kind| {
+                Diagnostic::error(
+                    ErrorCode::InvalidForm,
+                    symbol.span,
+                    format!("'{}' is a {} and cannot be used as a value", name, kind),
+                )
+            };
+            return Err(match is_special_form(name) {
+                true => error("special form"),
+                false => error("core function")
+                    .with_help(format!("wrap it in a function, like `#({} %1 %2)`", name)),
+            });
+        }
+        let arity = match &mut self.anonymous {
+            Some(arity) if symbol.name.starts_with('%') => arity,
+            _ => return Ok(Expression::Variable(symbol.clone())),
+        };
+        let index = match &symbol.name[1..] {
+            "" => 1,
+            "&" => {
+                return Err(Diagnostic::error(
+                    ErrorCode::InvalidForm,
+                    symbol.span,
+                    "Rest parameter '%&' is not supported",
+                ))
+            }
+            index => match index.parse::<usize>() {
+                Ok(index) if index > 0 => index,
+                _ => return Ok(Expression::Variable(symbol.clone())),
+            },
+        };
+        *arity = (*arity).max(index);
+        Ok(Expression::Variable(Symbol {
+            name: format!("%{}", index),
+            ..symbol.clone()
+        }))
+    }
+
+    fn condition(&mut self, args: &[ExpressionNode], tail: bool) -> Result<Expression, Diagnostic> {
+        let (test, then, otherwise) = match args {
+            [test, then] => (test, then, None),
+            [test, then, otherwise] => (test, then, Some(otherwise)),
+            _ => {
+                return Err(self.error(
+                    ErrorCode::ArityMismatch,
+                    format!("'if' expects 2 or 3 arguments, but get {}", args.len()),
+                ))
+            }
+        };
+        Ok(Expression::If {
+            test: Box::new(self.expression(test, false)?),
+            then: Box::new(self.expression(then, tail)?),
+            otherwise: Box::new(match otherwise {
+                Some(otherwise) => self.expression(otherwise, tail)?,
+                None => Expression::Number(0.0),
+            }),
+        })
+    }
+
+    /// Forms evaluated in order, the value of the last one is the value of the body.
+    fn body(&mut self, forms: &[ExpressionNode], tail: bool) -> Result<Expression, Diagnostic> {
+        let mut body = vec![];
+        for (index, form) in forms.iter().enumerate() {
+            body.push(self.expression(form, tail && index + 1 == forms.len())?);
+        }
+        Ok(match body.len() {
+            0 => Expression::Number(0.0),
+            1 => body.pop().unwrap(),
+            _ => Expression::Do(body),
+        })
+    }
+
+    /// Binding vector of `let` and `loop`, and the forms of the body after it.
+    fn bindings<'a>(
+        &mut self,
+        form: &str,
+        args: &'a [ExpressionNode],
+    ) -> Result<(Vec<Binding>, &'a [ExpressionNode]), Diagnostic> {
+        let (bindings, body) = match args {
+            [ExpressionNode::Array(bindings), body @ ..] => (bindings, body),
+            _ => {
+                return Err(self.error(
+                    ErrorCode::InvalidForm,
+                    format!("'{}' expects a binding vector", form),
+                ))
+            }
+        };
+        if bindings.len() % 2 != 0 {
+            return Err(self
+                .error(
+                    ErrorCode::InvalidForm,
+                    format!(
+                        "'{}' expects an even number of forms in its binding vector, but get {}",
+                        form,
+                        bindings.len()
+                    ),
+                )
+                .with_help("bindings are pairs of a name and a value, like `[x 1 y 2]`"));
+        }
+        let bindings = bindings
+            .chunks(2)
+            .map(|pair| match &pair[0] {
+                ExpressionNode::Identifier(symbol) => Ok(Binding {
+                    name: symbol.name.clone(),
+                    value: self.expression(&pair[1], false)?,
+                }),
+                other => Err(self.error(
+                    ErrorCode::InvalidForm,
+                    format!(
+                        "'{}' expects names in its binding vector, but get {}",
+                        form, other
+                    ),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((bindings, body))
+    }
+
+    fn recur(&mut self, args: &[ExpressionNode], tail: bool) -> Result<Expression, Diagnostic> {
+        let arity = match self.targets.last_mut() {
+            Some(target) if tail => *target.arity.get_or_insert(args.len()),
+            Some(_) => {
+                return Err(self.error(
+                    ErrorCode::InvalidRecur,
+                    "'recur' is only allowed in tail position",
+                ))
+            }
+            None => {
+                return Err(self.error(
+                    ErrorCode::InvalidRecur,
+                    "'recur' is only allowed inside 'loop' or 'fn'",
+                ))
+            }
+        };
+        if args.len() != arity {
+            return Err(self.error(
+                ErrorCode::ArityMismatch,
+                format!(
+                    "'recur' expects {} arguments, but get {}",
+                    arity,
+                    args.len()
+                ),
+            ));
+        }
+        let args = self.expressions(args)?;
+        self.targets.last_mut().unwrap().recurs = true;
+        Ok(Expression::Recur(args))
+    }
+
+    fn builtin(
+        &mut self,
+        builtin: Builtin,
+        args: &[ExpressionNode],
+    ) -> Result<Expression, Diagnostic> {
+        let name = builtin.name();
+        match builtin {
+            Builtin::Add | Builtin::Multiply => {}
+            Builtin::Subtract | Builtin::Divide if args.is_empty() => {
+                return Err(self.error(
+                    ErrorCode::ArityMismatch,
+                    format!("'{}' expects at least one argument", name),
+                ))
+            }
+            Builtin::Subtract | Builtin::Divide => {}
+            _ if args.len() != 2 => {
+                return Err(self.error(
+                    ErrorCode::ArityMismatch,
+                    format!("'{}' expects 2 arguments, but get {}", name, args.len()),
+                ))
+            }
+            _ => {}
+        }
+        Ok(Expression::Builtin(builtin, self.expressions(args)?))
+    }
+
+    fn set(&mut self, elements: &[ExpressionNode]) -> Result<Expression, Diagnostic> {
+        let expressions = self.expressions(elements)?;
+        // like the reader of Clojure, reject literal elements with the same value
+        let mut constants = vec![];
+        for element in elements {
+            if let Some(value) = constant(element) {
+                if constants.contains(&value) {
+                    return Err(self
+                        .error(
+                            ErrorCode::DuplicateSetElement,
+                            format!("Duplicate element {} in set literal", value),
+                        )
+                        .with_help(
+                            "elements are compared as 32-bit floats, `true` is 1 and `false` is 0",
+                        ));
+                }
+                constants.push(value);
+            }
+        }
+        Ok(Expression::Set(expressions))
+    }
+}
+
+/// Value of a literal known at compile time.
+fn constant(expression: &ExpressionNode) -> Option<f32> {
+    match expression {
+        ExpressionNode::BooleanLiteral(value, _) => Some(if *value { 1.0 } else { 0.0 }),
+        ExpressionNode::IntegerNumberLiteral(value, _) => Some(*value as f32),
+        ExpressionNode::FloatNumberLiteral(value, _) => Some(*value as f32),
+        ExpressionNode::FractionNumberLiteral(numerator, denominator, _) => {
+            Some(*numerator as f32 / *denominator as f32)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analyzer::{
+        analyze, is_special_form, Binding, Builtin, Expression, Function, BUILTINS, SPECIAL_FORMS,
+    };
+    use crate::diagnostic::ErrorCode;
+    use crate::parser::Parser;
+    use crate::resolver::resolve;
+    use crate::scanner::Scanner;
+    use crate::token::Span;
+
+    fn analyze_source(source: &str) -> Vec<Expression> {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        analyze(parser.parse().unwrap()).unwrap()
+    }
+
+    fn analyze_error(source: &str) -> String {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        analyze(parser.parse().unwrap()).unwrap_err()[0]
+            .message
+            .clone()
+    }
+
+    /// Analysis after resolving the scopes of the symbols, with the first error message.
+    fn analyze_resolved(source: &str) -> Result<Vec<Expression>, String> {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        let mut program = parser.parse().unwrap().clone();
+        resolve(&mut program).unwrap();
+        analyze(&program).map_err(|diagnostics| diagnostics[0].message.clone())
+    }
+
+    fn variable(name: &str) -> Box<Expression> {
+        Box::new(Expression::Variable(name.into()))
+    }
+
+    #[test]
+    fn analyze_calls() {
+        assert_eq!(
+            analyze_source("(+ 1/2 true) (f x)"),
+            vec![
+                Expression::Builtin(
+                    Builtin::Add,
+                    vec![Expression::Number(0.5), Expression::Number(1.0)]
+                ),
+                Expression::Call {
+                    callee: variable("f"),
+                    args: vec![*variable("x")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn call_shadowed_core_functions() {
+        let is_call = |expression: &Expression| match expression {
+            Expression::Let { body, .. } => matches!(**body, Expression::Call { .. }),
+            Expression::Call { .. } => true,
+            _ => false,
+        };
+        let shadowed = analyze_resolved("(let [+ (fn [a b] a)] (+ 1 2))").unwrap();
+        assert!(is_call(&shadowed[0]), "{:?}", shadowed);
+        let redefined = analyze_resolved("(def - (fn [a] a)) (- 1)").unwrap();
+        assert!(is_call(&redefined[1]), "{:?}", redefined);
+        let builtin = analyze_resolved("(let [x 1] (+ x 2))").unwrap();
+        assert!(!is_call(&builtin[0]), "{:?}", builtin);
+    }
+
+    #[test]
+    fn reject_builtins_as_values() {
+        assert_eq!(
+            analyze_resolved("(def f +)").unwrap_err(),
+            "'+' is a core function and cannot be used as a value"
+        );
+        assert_eq!(
+            analyze_resolved("(def f (fn [] if))").unwrap_err(),
+            "'if' is a special form and cannot be used as a value"
+        );
+        assert!(analyze_resolved("(let [+ 1] +)").is_ok());
+    }
+
+    #[test]
+    fn report_duplicate_set_elements() {
+        for source in [
+            "(+ #{1 true})",
+            "(+ #{1 1.0})",
+            "(+ #{1/2 0.5})",
+            "(+ #{16777216 16777217})",
+        ] {
+            let mut scanner = Scanner::new(source);
+            let mut parser = Parser::new(&mut scanner);
+            let diagnostic = &analyze(parser.parse().unwrap()).unwrap_err()[0];
+            assert_eq!(
+                diagnostic.code,
+                ErrorCode::DuplicateSetElement,
+                "{}",
+                source
+            );
+            assert_eq!(
+                diagnostic.help.as_deref(),
+                Some("elements are compared as 32-bit floats, `true` is 1 and `false` is 0")
+            );
+        }
+        assert!(analyze_resolved("(+ #{1 2 3})").is_ok());
+    }
+
+    #[test]
+    fn report_numbers_out_of_f32_range() {
+        assert_eq!(
+            analyze_error("(+ 1 340282366920938463463374607431768211456000.0)"),
+            "Number 340282366920938460000000000000000000000000.0 is out of range"
+        );
+        assert_eq!(
+            analyze_error("(+ #{1 -340282366920938463463374607431768211456000.0} 1)"),
+            "Number -340282366920938460000000000000000000000000.0 is out of range"
+        );
+        assert_eq!(
+            analyze_source("(+ 1 340000000000000000000000000000000000000.0)").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn analyze_special_forms() {
+        assert_eq!(
+            analyze_source("(def f \"Doc\" (fn [x] (if x (do 1 2))))"),
+            vec![Expression::Define {
+                name: "f".to_owned(),
+                value: Box::new(Expression::Function(Function {
+                    params: vec!["x".to_owned()],
+                    body: Box::new(Expression::If {
+                        test: variable("x"),
+                        then: Box::new(Expression::Do(vec![
+                            Expression::Number(1.0),
+                            Expression::Number(2.0)
+                        ])),
+                        otherwise: Box::new(Expression::Number(0.0)),
+                    }),
+                    recurs: false,
+                })),
+            }]
+        );
+        assert_eq!(
+            analyze_source("(let [x 1] (loop [] (recur)))"),
+            vec![Expression::Let {
+                bindings: vec![Binding {
+                    name: "x".to_owned(),
+                    value: Expression::Number(1.0),
+                }],
+                body: Box::new(Expression::Loop {
+                    bindings: vec![],
+                    body: Box::new(Expression::Recur(vec![])),
+                }),
+            }]
+        );
+        assert_eq!(analyze_source("(do)"), vec![Expression::Number(0.0)]);
+    }
+
+    #[test]
+    fn find_recur_targets() {
+        let function = |source| match analyze_source(source).pop() {
+            Some(Expression::Function(function)) => function,
+            other => panic!("Expected a function, but get {:?}", other),
+        };
+        assert!(function("(fn [n] (if n (recur (- n 1)) 0))").recurs);
+        assert!(!function("(fn [n] (loop [i n] (recur i)))").recurs);
+        assert!(!function("(fn [] (fn [] (recur)))").recurs);
+        assert!(function("(do #(if % (recur (- % 1)) 0))").recurs);
+    }
+
+    #[test]
+    fn analyze_anonymous_functions() {
+        assert_eq!(
+            analyze_source("(do #(+ % %3))"),
+            vec![Expression::Function(Function {
+                params: vec!["%1".to_owned(), "%2".to_owned(), "%3".to_owned()],
+                body: Box::new(Expression::Builtin(
+                    Builtin::Add,
+                    vec![*variable("%1"), *variable("%3")]
+                )),
+                recurs: false,
+            })]
+        );
+        assert_eq!(
+            analyze_source("(do #(f))"),
+            vec![Expression::Function(Function {
+                params: vec![],
+                body: Box::new(Expression::Call {
+                    callee: variable("f"),
+                    args: vec![],
+                }),
+                recurs: false,
+            })]
+        );
+    }
+
+    #[test]
+    fn list_special_forms() {
+        // the special forms cannot be redefined by macros either
+        assert!(SPECIAL_FORMS.iter().all(|name| BUILTINS.contains(name)));
+        assert!(is_special_form("defmacro"));
+        assert!(!is_special_form("defn"));
+        assert!(!is_special_form("+"));
+    }
+
+    #[test]
+    fn locate_errors() {
+        let locate = |source: &str| {
+            let mut scanner = Scanner::new(source);
+            let mut parser = Parser::new(&mut scanner);
+            analyze(parser.parse().unwrap())
+                .unwrap_err()
+                .iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.span))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            locate("(def x 1)\n(let [x 1 y] x)"),
+            vec![(ErrorCode::InvalidForm, Span::new(11, 14))]
+        );
+        // every invalid top-level form is reported, at its innermost form
+        assert_eq!(
+            locate("(if 1) (fn [] (+ 1 (recur))) (+ #{1 1})"),
+            vec![
+                (ErrorCode::ArityMismatch, Span::new(1, 3)),
+                (ErrorCode::InvalidRecur, Span::new(20, 25)),
+                (ErrorCode::DuplicateSetElement, Span::new(30, 31)),
+            ]
+        );
+        // unsupported literals are located at themselves, collections at their first element
+        assert_eq!(
+            locate("(+ 1 \"a\") (\"f\" 1) (def m {:a 1})"),
+            vec![
+                (ErrorCode::InvalidForm, Span::new(5, 8)),
+                (ErrorCode::InvalidForm, Span::new(11, 14)),
+                (ErrorCode::InvalidForm, Span::new(26, 28)),
+            ]
+        );
+        assert_eq!(
+            locate("(def f #(+ %&))"),
+            vec![(ErrorCode::InvalidForm, Span::new(11, 13))]
+        );
+    }
+
+    #[test]
+    fn report_invalid_forms() {
+        assert_eq!(
+            analyze_error("(let [x 1 y] x)"),
+            "'let' expects an even number of forms in its binding vector, but get 3"
+        );
+        assert_eq!(
+            analyze_error("(let [1 2] 1)"),
+            "'let' expects names in its binding vector, but get 1"
+        );
+        assert_eq!(analyze_error("(loop x)"), "'loop' expects a binding vector");
+        assert_eq!(analyze_error("(fn x)"), "'fn' expects a parameter vector");
+        assert_eq!(
+            analyze_error("(fn [1] 1)"),
+            "'fn' expects names as parameters, but get 1"
+        );
+        assert_eq!(
+            analyze_error("(if 1)"),
+            "'if' expects 2 or 3 arguments, but get 1"
+        );
+        assert_eq!(
+            analyze_error("(recur 1)"),
+            "'recur' is only allowed inside 'loop' or 'fn'"
+        );
+        assert_eq!(
+            analyze_error("(loop [i 0] (+ 1 (recur i)))"),
+            "'recur' is only allowed in tail position"
+        );
+        assert_eq!(
+            analyze_error("(fn [a b] (recur a))"),
+            "'recur' expects 2 arguments, but get 1"
+        );
+        assert_eq!(
+            analyze_error("(loop [] (recur) 1)"),
+            "'recur' is only allowed in tail position"
+        );
+        assert_eq!(
+            analyze_error("(do #(recur 1 2))"),
+            "'recur' expects 0 arguments, but get 2"
+        );
+        assert_eq!(
+            analyze_error("(do #(+ % #(+ %)))"),
+            "Nested '#()' functions are not allowed"
+        );
+        assert_eq!(
+            analyze_error("(do #(+ %&))"),
+            "Rest parameter '%&' is not supported"
+        );
+    }
+}
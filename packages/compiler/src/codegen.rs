@@ -0,0 +1,1369 @@
+use crate::analyzer::{self, Binding, Builtin, Expression, Function};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::emitter::{
+    self, Blocktype, ExportType, FunctionType, Instruction, ModuleBuilder, Opcodes, Valtype,
+};
+use crate::parser::{Program, Scope, Symbol};
+use std::collections::{HashMap, HashSet};
+
+/// Name of the exported function which evaluates the program and returns the value of its last form.
+pub const RUN_EXPORT: &str = "run";
+
+/// Name of the exported memory holding the objects, like sets and regexes.
+pub const MEMORY_EXPORT: &str = "memory";
+
+/// Name of the exported `i32` global holding the [`RuntimeError`] of the last trap.
+pub const ERROR_EXPORT: &str = "error";
+
+/// Address of the first object. Nothing is stored below it, so no object is at `0`.
+const DATA_START: u32 = 16;
+
+/// End of the heap. The bits of every address below it are a finite `f32`, so a
+/// reference compares equal to itself.
+const HEAP_LIMIT: u32 = 0x7f80_0000;
+
+/// Type tag of a set object, followed by the number of elements and the elements as `f32`
+pub const SET_TAG: u32 = 1;
+/// Type tag of a regex object, followed by the length and the UTF-8 bytes of the pattern
+pub const REGEX_TAG: u32 = 2;
+/// Type tag of a closure object, followed by the number of captured values, the table
+/// index and the number of parameters of its function as `i32` and the captured values
+/// as `f32`
+pub const CLOSURE_TAG: u32 = 3;
+
+/// Size of the fields of a closure before its captured values
+const CLOSURE_HEADER_SIZE: u32 = 16;
+
+const PAGE_SIZE: u32 = 65536;
+
+/// Cause of a trap. The code of a failed check of the generated code is stored in the
+/// [`ERROR_EXPORT`] global before the module traps, to tell the cause of the trap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeError {
+    NotAFunction = 1,
+    ArityMismatch = 2,
+    OutOfMemory = 3,
+    /// Trap of the engine when the calls are nested too deep, never stored in the global
+    StackOverflow = 4,
+}
+
+impl RuntimeError {
+    pub fn from_code(code: i32) -> Option<RuntimeError> {
+        match code {
+            1 => Some(RuntimeError::NotAFunction),
+            2 => Some(RuntimeError::ArityMismatch),
+            3 => Some(RuntimeError::OutOfMemory),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            RuntimeError::NotAFunction => "Cannot call a value which is not a function",
+            RuntimeError::ArityMismatch => {
+                "Cannot call a function with a different number of arguments than its parameters"
+            }
+            RuntimeError::OutOfMemory => "Cannot allocate an object, the heap is full",
+            RuntimeError::StackOverflow => "Cannot call a function, the call stack is full",
+        }
+    }
+}
+
+/// Lowers a parsed program to a WebAssembly module. Every value is an `f32`,
+/// booleans are represented by `1` and `0`. Sets and regexes are objects in the exported
+/// memory, represented by the bits of their address reinterpreted as an `f32`, which
+/// keeps every address exact. An object starts with its type tag and length as `i32`:
+/// regexes are stored when the module is instantiated, sets are allocated when their
+/// literal is evaluated. Objects are never freed, the heap ends at [`HEAP_LIMIT`].
+///
+/// Every `fn` is compiled to a WebAssembly function, which takes its closure as first
+/// parameter. The value of a `fn` is a closure object with the values of the locals it
+/// captures, allocated when it is evaluated. Functions are called directly through the
+/// name of a `def` which is not redefined, other values are called through the function
+/// table after checking that they are closures with as many parameters as arguments.
+pub fn compile(program: &Program) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    Ok(compile_module(program)?.build())
+}
+
+/// Lowers a parsed program to the module tables, before encoding them.
+pub fn compile_module(program: &Program) -> Result<ModuleBuilder, Vec<Diagnostic>> {
+    let program = analyzer::analyze(program)?;
+    let mut generator = CodeGenerator::new();
+    generator
+        .program(&program)
+        .map_err(|diagnostic| vec![diagnostic])?;
+    Ok(generator.finish())
+}
+
+/// Enclosing block of the generated instructions, the target of branches.
+enum Label {
+    /// `if`
+    Block,
+    /// `loop` with the locals rebound by `recur`
+    Loop(Vec<u32>),
+}
+
+/// Function being generated.
+#[derive(Default)]
+struct FunctionContext {
+    params: u32,
+    /// Locals declared after the parameters
+    locals: Vec<Valtype>,
+    body: Vec<Instruction>,
+    /// Local index of the parameters and the `let` and `loop` bindings in scope,
+    /// inner bindings after the outer ones
+    scopes: Vec<(String, u32)>,
+    labels: Vec<Label>,
+}
+
+impl FunctionContext {
+    fn local(&self, name: &str) -> Option<u32> {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|(local, _)| local == name)
+            .map(|(_, index)| *index)
+    }
+}
+
+struct CodeGenerator {
+    builder: ModuleBuilder,
+    /// The `run` function or the innermost `fn`
+    function: FunctionContext,
+    /// Functions enclosing the current one
+    outer: Vec<FunctionContext>,
+    /// Compiled functions, `None` while their body is generated
+    functions: Vec<Option<emitter::Function>>,
+    /// Global index of every `def`
+    globals: HashMap<String, u32>,
+    /// Globals defined once, by a top-level `def`. Every use compiled after their `def`
+    /// runs after it, and sees the value of that `def`.
+    constants: HashSet<String>,
+    /// Function index and arity of the constants defined as functions
+    definitions: HashMap<String, (u32, usize)>,
+    /// Constants defined with a value which is not a function
+    values: HashSet<String>,
+    /// Objects stored from `DATA_START` when the module is instantiated
+    data: Vec<u8>,
+    /// Global holding the address of the next allocated object, added by the first allocation
+    heap: Option<u32>,
+    /// A call through the function table was generated
+    indirect_calls: bool,
+    /// Global holding the [`RuntimeError`] of the last trap, added by the first check
+    error: Option<u32>,
+}
+
+impl CodeGenerator {
+    fn new() -> Self {
+        CodeGenerator {
+            builder: ModuleBuilder::new(),
+            function: FunctionContext::default(),
+            outer: vec![],
+            functions: vec![],
+            globals: HashMap::new(),
+            constants: HashSet::new(),
+            definitions: HashMap::new(),
+            values: HashSet::new(),
+            data: vec![],
+            heap: None,
+            indirect_calls: false,
+            error: None,
+        }
+    }
+
+    fn finish(mut self) -> ModuleBuilder {
+        // the table index of a function is its function index
+        let functions = self.functions.len() as u32;
+        if functions > 0 || self.indirect_calls {
+            self.builder.add_table(functions);
+        }
+        if functions > 0 {
+            self.builder.add_element(0, (0..functions).collect());
+        }
+        for function in self.functions.into_iter().flatten() {
+            self.builder
+                .add_function(function.type_index, function.locals, function.body);
+        }
+        let run_type = self
+            .builder
+            .add_type(FunctionType::new(vec![], vec![Valtype::F32]));
+        let run = self
+            .builder
+            .add_function(run_type, self.function.locals, self.function.body);
+        self.builder.add_export(RUN_EXPORT, ExportType::Func, run);
+        if let Some(error) = self.error {
+            self.builder
+                .add_export(ERROR_EXPORT, ExportType::Global, error);
+        }
+        if !self.data.is_empty() || self.heap.is_some() || self.indirect_calls {
+            let heap_start = align(DATA_START + self.data.len() as u32, 8);
+            let memory = self.builder.add_memory(heap_start / PAGE_SIZE + 1);
+            self.builder
+                .add_export(MEMORY_EXPORT, ExportType::Mem, memory);
+            if !self.data.is_empty() {
+                self.builder.add_data(DATA_START, self.data);
+            }
+            if let Some(heap) = self.heap {
+                let init = vec![Instruction::i32_const(heap_start as i32)];
+                self.builder.set_global_init(heap, init);
+            }
+        }
+        self.builder
+    }
+
+    fn program(&mut self, program: &[Expression]) -> Result<(), Diagnostic> {
+        let mut definitions = HashMap::new();
+        for form in program {
+            count_definitions(form, &mut definitions);
+        }
+        self.constants = program
+            .iter()
+            .filter_map(|form| match form {
+                Expression::Define { name, .. } if definitions[name] == 1 => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        if program.is_empty() {
+            self.number(0.0);
+        }
+        for (index, form) in program.iter().enumerate() {
+            self.expression(form)?;
+            // only the value of the last form is returned
+            if index + 1 < program.len() {
+                self.emit(Opcodes::Drop);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<(), Diagnostic> {
+        match expression {
+            Expression::Number(value) => self.number(*value),
+            Expression::Set(elements) => self.set(elements)?,
+            Expression::Regex(pattern) => {
+                let address = self.static_object(REGEX_TAG, pattern.as_bytes());
+                self.function
+                    .body
+                    .push(Instruction::i32_const(address as i32));
+                self.emit(Opcodes::F32ReinterpretI32);
+            }
+            Expression::Variable(symbol) => self.variable(symbol)?,
+            Expression::Define { name, value } => self.define(name, value)?,
+            Expression::Function(function) => {
+                let index = self.reserve_function();
+                self.closure(index, function)?;
+            }
+            Expression::If {
+                test,
+                then,
+                otherwise,
+            } => {
+                self.expression(test)?;
+                self.number(0.0);
+                self.emit(Opcodes::F32Ne);
+                self.block(Opcodes::If, Label::Block);
+                self.expression(then)?;
+                self.emit(Opcodes::Else);
+                self.expression(otherwise)?;
+                self.end_block();
+            }
+            Expression::Do(forms) => {
+                for (index, form) in forms.iter().enumerate() {
+                    self.expression(form)?;
+                    if index + 1 < forms.len() {
+                        self.emit(Opcodes::Drop);
+                    }
+                }
+            }
+            Expression::Let { bindings, body } => {
+                let scope = self.function.scopes.len();
+                self.bind(bindings)?;
+                self.expression(body)?;
+                self.function.scopes.truncate(scope);
+            }
+            Expression::Loop { bindings, body } => {
+                let scope = self.function.scopes.len();
+                let locals = self.bind(bindings)?;
+                self.block(Opcodes::Loop, Label::Loop(locals));
+                self.expression(body)?;
+                self.end_block();
+                self.function.scopes.truncate(scope);
+            }
+            Expression::Recur(args) => self.recur(args)?,
+            Expression::Builtin(builtin, args) => self.builtin(*builtin, args)?,
+            Expression::Call { callee, args } => self.call(callee, args)?,
+        }
+        Ok(())
+    }
+
+    /// Reads a local or a global. A global found by the resolver may be defined later
+    /// in the program, until then its value is `0`.
+    fn variable(&mut self, symbol: &Symbol) -> Result<(), Diagnostic> {
+        let name = symbol.name.as_str();
+        if let Some(local) = self.function.local(name) {
+            self.emit_with_index(Opcodes::GetLocal, local);
+        } else if self.is_global(symbol) {
+            let global = self.global(name);
+            self.emit_with_index(Opcodes::GetGlobal, global);
+        } else {
+            return Err(Diagnostic::error(
+                ErrorCode::UnboundSymbol,
+                symbol.span,
+                format!("Unknown identifier '{}'", name),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stores the value in a global and returns it. The function of a constant is known
+    /// while its body is generated, so it can call itself directly.
+    fn define(&mut self, name: &str, value: &Expression) -> Result<(), Diagnostic> {
+        let constant = self.constants.contains(name);
+        match value {
+            Expression::Function(function) => {
+                let index = self.reserve_function();
+                if constant {
+                    self.definitions
+                        .insert(name.to_owned(), (index, function.params.len()));
+                }
+                self.closure(index, function)?;
+            }
+            _ => {
+                if constant
+                    && matches!(
+                        value,
+                        Expression::Number(_)
+                            | Expression::Set(_)
+                            | Expression::Regex(_)
+                            | Expression::Builtin(..)
+                    )
+                {
+                    self.values.insert(name.to_owned());
+                }
+                self.expression(value)?;
+            }
+        }
+        let index = self.global(name);
+        self.emit_with_index(Opcodes::SetGlobal, index);
+        self.emit_with_index(Opcodes::GetGlobal, index);
+        Ok(())
+    }
+
+    fn is_global(&self, symbol: &Symbol) -> bool {
+        self.globals.contains_key(&symbol.name) || symbol.scope == Scope::Global
+    }
+
+    /// Index of the global of a `def`, added at its first use.
+    fn global(&mut self, name: &str) -> u32 {
+        if let Some(index) = self.globals.get(name) {
+            return *index;
+        }
+        let init = vec![Instruction::f32_const(0.0)];
+        let index = self.builder.add_global(Valtype::F32, true, init);
+        self.globals.insert(name.to_owned(), index);
+        index
+    }
+
+    fn reserve_function(&mut self) -> u32 {
+        self.functions.push(None);
+        (self.functions.len() - 1) as u32
+    }
+
+    /// Generates the function with the index reserved for it and allocates its closure
+    /// with the current values of the captured locals.
+    fn closure(&mut self, index: u32, function: &Function) -> Result<(), Diagnostic> {
+        let mut captures = vec![];
+        free_variables(&function.body, &mut function.params.clone(), &mut captures);
+        captures.retain(|name| self.function.local(name).is_some());
+        self.function(index, function, &captures)?;
+
+        let address = self.add_local(Valtype::I32);
+        self.allocate(CLOSURE_HEADER_SIZE + 4 * captures.len() as u32);
+        self.emit_with_index(Opcodes::SetLocal, address);
+        self.store_header(address, CLOSURE_TAG, captures.len() as u32);
+        for (offset, value) in [(8, index), (12, function.params.len() as u32)] {
+            self.emit_with_index(Opcodes::GetLocal, address);
+            self.function
+                .body
+                .push(Instruction::i32_const(value as i32));
+            self.function
+                .body
+                .push(Instruction::memory(Opcodes::I32Store, 2, offset));
+        }
+        for (offset, name) in (CLOSURE_HEADER_SIZE..).step_by(4).zip(&captures) {
+            let local = self.function.local(name).expect("Captured local");
+            self.emit_with_index(Opcodes::GetLocal, address);
+            self.emit_with_index(Opcodes::GetLocal, local);
+            self.function
+                .body
+                .push(Instruction::memory(Opcodes::F32Store, 2, offset));
+        }
+        self.emit_with_index(Opcodes::GetLocal, address);
+        self.emit(Opcodes::F32ReinterpretI32);
+        Ok(())
+    }
+
+    /// Generates the function with the index reserved for it. The closure is the first
+    /// parameter, the captured values are copied from it to locals. A body with `recur`
+    /// is wrapped in a loop, which rebinds the parameters.
+    fn function(
+        &mut self,
+        index: u32,
+        function: &Function,
+        captures: &[String],
+    ) -> Result<(), Diagnostic> {
+        let params = function.params.len() as u32;
+        let context = FunctionContext {
+            params: params + 1,
+            scopes: function.params.iter().cloned().zip(1..).collect(),
+            ..FunctionContext::default()
+        };
+        self.outer
+            .push(std::mem::replace(&mut self.function, context));
+        for (offset, name) in (CLOSURE_HEADER_SIZE..).step_by(4).zip(captures) {
+            let local = self.add_local(Valtype::F32);
+            self.emit_with_index(Opcodes::GetLocal, 0);
+            self.function
+                .body
+                .push(Instruction::memory(Opcodes::F32Load, 2, offset));
+            self.emit_with_index(Opcodes::SetLocal, local);
+            self.function.scopes.push((name.clone(), local));
+        }
+        let result = if function.recurs {
+            self.block(Opcodes::Loop, Label::Loop((1..=params).collect()));
+            let result = self.expression(&function.body);
+            self.end_block();
+            result
+        } else {
+            self.expression(&function.body)
+        };
+        let outer = self.outer.pop().unwrap();
+        let context = std::mem::replace(&mut self.function, outer);
+        result?;
+
+        let type_index = self.function_type(function.params.len());
+        self.functions[index as usize] = Some(emitter::Function {
+            type_index,
+            locals: context.locals,
+            body: context.body,
+        });
+        Ok(())
+    }
+
+    /// Type of a function with the given number of parameters after its closure.
+    fn function_type(&mut self, params: usize) -> u32 {
+        let mut param_types = vec![Valtype::I32];
+        param_types.extend(vec![Valtype::F32; params]);
+        self.builder
+            .add_type(FunctionType::new(param_types, vec![Valtype::F32]))
+    }
+
+    /// Calls the function of a constant directly, other callees are called through the
+    /// table index stored in their closure, as a global defined again may hold another
+    /// function when the call runs.
+    fn call(&mut self, callee: &Expression, args: &[Expression]) -> Result<(), Diagnostic> {
+        if let Expression::Variable(symbol) = callee {
+            let name = symbol.name.as_str();
+            if self.function.local(name).is_none() {
+                if let Some((index, arity)) = self.definitions.get(name).copied() {
+                    return self.direct_call(symbol, index, arity, args);
+                }
+                if self.values.contains(name) {
+                    return Err(Diagnostic::error(
+                        ErrorCode::NotCallable,
+                        symbol.span,
+                        format!("'{}' is not a function", name),
+                    )
+                    .with_help(format!("'{}' is defined with a value", name)));
+                }
+                if !self.is_global(symbol) {
+                    return Err(Diagnostic::error(
+                        ErrorCode::UnboundSymbol,
+                        symbol.span,
+                        format!("Unknown function '{}'", name),
+                    ));
+                }
+            }
+        }
+        // the closure is kept in a local, as the arguments are evaluated after it
+        let value = self.add_local(Valtype::F32);
+        let closure = self.add_local(Valtype::I32);
+        self.expression(callee)?;
+        self.emit_with_index(Opcodes::SetLocal, value);
+        self.check_closure(value, closure, args.len());
+        self.emit_with_index(Opcodes::GetLocal, closure);
+        for arg in args {
+            self.expression(arg)?;
+        }
+        self.emit_with_index(Opcodes::GetLocal, closure);
+        self.function
+            .body
+            .push(Instruction::memory(Opcodes::I32Load, 2, 8));
+        let type_index = self.function_type(args.len());
+        self.function
+            .body
+            .push(Instruction::call_indirect(type_index));
+        self.indirect_calls = true;
+        Ok(())
+    }
+
+    /// Checks that the value in the local is a closure with the given number of parameters
+    /// and stores its address in the `closure` local. Every number is a valid value, so
+    /// the value is checked to be an address in the memory before reading the closure.
+    fn check_closure(&mut self, value: u32, closure: u32, arity: usize) {
+        self.emit_with_index(Opcodes::GetLocal, value);
+        self.emit(Opcodes::I32ReinterpretF32);
+        self.emit_with_index(Opcodes::SetLocal, closure);
+        self.emit_with_index(Opcodes::GetLocal, closure);
+        self.function
+            .body
+            .push(Instruction::i32_const(DATA_START as i32));
+        self.emit(Opcodes::I32GeU);
+        // the memory is smaller than `HEAP_LIMIT`, so its size in bytes fits in an `i32`
+        self.emit_with_index(Opcodes::GetLocal, closure);
+        self.emit(Opcodes::MemorySize);
+        self.function
+            .body
+            .push(Instruction::i32_const(PAGE_SIZE.trailing_zeros() as i32));
+        self.emit(Opcodes::I32Shl);
+        self.function
+            .body
+            .push(Instruction::i32_const(CLOSURE_HEADER_SIZE as i32));
+        self.emit(Opcodes::I32Sub);
+        self.emit(Opcodes::I32LeU);
+        self.emit(Opcodes::I32And);
+        self.check(RuntimeError::NotAFunction);
+
+        for (offset, expected, error) in [
+            (0, CLOSURE_TAG, RuntimeError::NotAFunction),
+            (12, arity as u32, RuntimeError::ArityMismatch),
+        ] {
+            self.emit_with_index(Opcodes::GetLocal, closure);
+            self.function
+                .body
+                .push(Instruction::memory(Opcodes::I32Load, 2, offset));
+            self.function
+                .body
+                .push(Instruction::i32_const(expected as i32));
+            self.emit(Opcodes::I32Eq);
+            self.check(error);
+        }
+    }
+
+    /// Traps with the error when the condition on the stack is false.
+    fn check(&mut self, error: RuntimeError) {
+        self.emit(Opcodes::I32Eqz);
+        self.trap_if(error);
+    }
+
+    /// Traps with the error when the condition on the stack is true.
+    fn trap_if(&mut self, error: RuntimeError) {
+        let global = match self.error {
+            Some(global) => global,
+            None => {
+                let init = vec![Instruction::i32_const(0)];
+                let global = self.builder.add_global(Valtype::I32, true, init);
+                self.error = Some(global);
+                global
+            }
+        };
+        self.function
+            .body
+            .push(Instruction::block(Opcodes::If, Blocktype::Void));
+        self.function
+            .body
+            .push(Instruction::i32_const(error as i32));
+        self.emit_with_index(Opcodes::SetGlobal, global);
+        self.emit(Opcodes::Unreachable);
+        self.emit(Opcodes::End);
+    }
+
+    /// Calls the function of a `def` with its closure, the arguments are checked against
+    /// its parameters.
+    fn direct_call(
+        &mut self,
+        callee: &Symbol,
+        index: u32,
+        arity: usize,
+        args: &[Expression],
+    ) -> Result<(), Diagnostic> {
+        if args.len() != arity {
+            return Err(Diagnostic::error(
+                ErrorCode::ArityMismatch,
+                callee.span,
+                format!(
+                    "Function '{}' expects {} arguments, but get {}",
+                    callee.name,
+                    arity,
+                    args.len()
+                ),
+            ));
+        }
+        let global = self.global(&callee.name);
+        self.emit_with_index(Opcodes::GetGlobal, global);
+        self.emit(Opcodes::I32ReinterpretF32);
+        for arg in args {
+            self.expression(arg)?;
+        }
+        self.emit_with_index(Opcodes::Call, index);
+        Ok(())
+    }
+
+    /// Evaluates the bindings in order into new locals, each one is visible for the next ones.
+    fn bind(&mut self, bindings: &[Binding]) -> Result<Vec<u32>, Diagnostic> {
+        let mut locals = vec![];
+        for binding in bindings {
+            self.expression(&binding.value)?;
+            let local = self.add_local(Valtype::F32);
+            self.emit_with_index(Opcodes::SetLocal, local);
+            self.function.scopes.push((binding.name.clone(), local));
+            locals.push(local);
+        }
+        Ok(locals)
+    }
+
+    /// Stores the new values in the locals of the innermost loop and jumps to its start.
+    fn recur(&mut self, args: &[Expression]) -> Result<(), Diagnostic> {
+        for arg in args {
+            self.expression(arg)?;
+        }
+        // the analyzer checks that `recur` is inside a `loop` or `fn`
+        let (depth, locals) = self
+            .function
+            .labels
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, label)| match label {
+                Label::Loop(locals) => Some((depth, locals.clone())),
+                Label::Block => None,
+            })
+            .expect("Target of 'recur'");
+        // the values are on the stack in order, so the last local is set first
+        for local in locals.into_iter().rev() {
+            self.emit_with_index(Opcodes::SetLocal, local);
+        }
+        self.emit_with_index(Opcodes::Br, depth as u32);
+        Ok(())
+    }
+
+    fn builtin(&mut self, builtin: Builtin, args: &[Expression]) -> Result<(), Diagnostic> {
+        match (builtin, args) {
+            (Builtin::Add, _) => self.arithmetic(args, Opcodes::F32Add, 0.0)?,
+            (Builtin::Multiply, _) => self.arithmetic(args, Opcodes::F32Mul, 1.0)?,
+            (Builtin::Subtract, [arg]) => {
+                self.expression(arg)?;
+                self.emit(Opcodes::F32Neg);
+            }
+            (Builtin::Divide, [arg]) => {
+                self.number(1.0);
+                self.expression(arg)?;
+                self.emit(Opcodes::F32Div);
+            }
+            (Builtin::Subtract, _) => self.arithmetic(args, Opcodes::F32Sub, 0.0)?,
+            (Builtin::Divide, _) => self.arithmetic(args, Opcodes::F32Div, 1.0)?,
+            (Builtin::Equal, _) => self.comparison(args, Opcodes::F32Eq)?,
+            (Builtin::NotEqual, _) => self.comparison(args, Opcodes::F32Ne)?,
+            (Builtin::Less, _) => self.comparison(args, Opcodes::F32Lt)?,
+            (Builtin::Greater, _) => self.comparison(args, Opcodes::F32Gt)?,
+            (Builtin::LessOrEqual, _) => self.comparison(args, Opcodes::F32Le)?,
+            (Builtin::GreaterOrEqual, _) => self.comparison(args, Opcodes::F32Ge)?,
+        }
+        Ok(())
+    }
+
+    /// Folds the arguments from left to right with the operator, no argument results the identity.
+    fn arithmetic(
+        &mut self,
+        args: &[Expression],
+        operator: Opcodes,
+        identity: f32,
+    ) -> Result<(), Diagnostic> {
+        match args.split_first() {
+            None => self.number(identity),
+            Some((first, rest)) => {
+                self.expression(first)?;
+                for arg in rest {
+                    self.expression(arg)?;
+                    self.emit(operator);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn comparison(&mut self, args: &[Expression], operator: Opcodes) -> Result<(), Diagnostic> {
+        for arg in args {
+            self.expression(arg)?;
+        }
+        self.emit(operator);
+        // comparisons produce i32 booleans
+        self.emit(Opcodes::F32ConvertI32s);
+        Ok(())
+    }
+
+    /// Allocates a set with the values of the elements and returns its reference. The
+    /// analyzer rejects equal literals, the values of the other elements are compared at
+    /// run time and stored once, so the length is known after the elements are evaluated.
+    fn set(&mut self, elements: &[Expression]) -> Result<(), Diagnostic> {
+        // elements may allocate too, so the address is kept in a local
+        let address = self.add_local(Valtype::I32);
+        self.allocate(8 + 4 * elements.len() as u32);
+        self.emit_with_index(Opcodes::SetLocal, address);
+        if elements
+            .iter()
+            .all(|element| matches!(element, Expression::Number(_)))
+        {
+            self.store_header(address, SET_TAG, elements.len() as u32);
+            for (index, element) in elements.iter().enumerate() {
+                self.emit_with_index(Opcodes::GetLocal, address);
+                self.expression(element)?;
+                self.function.body.push(Instruction::memory(
+                    Opcodes::F32Store,
+                    2,
+                    8 + 4 * index as u32,
+                ));
+            }
+        } else {
+            let length = self.add_local(Valtype::I32);
+            let value = self.add_local(Valtype::F32);
+            let index = self.add_local(Valtype::I32);
+            // the set may be built again by a loop
+            self.function.body.push(Instruction::i32_const(0));
+            self.emit_with_index(Opcodes::SetLocal, length);
+            for element in elements {
+                self.expression(element)?;
+                self.emit_with_index(Opcodes::SetLocal, value);
+                self.add_unique(address, length, value, index);
+            }
+            let tag = Instruction::i32_const(SET_TAG as i32);
+            let length = Instruction::with_index(Opcodes::GetLocal, length);
+            for (offset, value) in [(0, tag), (4, length)] {
+                self.emit_with_index(Opcodes::GetLocal, address);
+                self.function.body.push(value);
+                self.function
+                    .body
+                    .push(Instruction::memory(Opcodes::I32Store, 2, offset));
+            }
+        }
+        self.emit_with_index(Opcodes::GetLocal, address);
+        self.emit(Opcodes::F32ReinterpretI32);
+        Ok(())
+    }
+
+    /// Stores the value in the `length` local after the elements of the set at the
+    /// address in the `address` local, unless one of them is equal to it.
+    fn add_unique(&mut self, address: u32, length: u32, value: u32, index: u32) {
+        self.function.body.push(Instruction::i32_const(0));
+        self.emit_with_index(Opcodes::SetLocal, index);
+        self.function
+            .body
+            .push(Instruction::block(Opcodes::Block, Blocktype::Void));
+        self.function
+            .body
+            .push(Instruction::block(Opcodes::Loop, Blocktype::Void));
+        self.emit_with_index(Opcodes::GetLocal, index);
+        self.emit_with_index(Opcodes::GetLocal, length);
+        self.emit(Opcodes::I32GeU);
+        self.emit_with_index(Opcodes::BrIf, 1);
+        self.element_address(address, index);
+        self.function
+            .body
+            .push(Instruction::memory(Opcodes::F32Load, 2, 8));
+        self.emit_with_index(Opcodes::GetLocal, value);
+        self.emit(Opcodes::F32Eq);
+        self.emit_with_index(Opcodes::BrIf, 1);
+        self.increment(index);
+        self.emit_with_index(Opcodes::Br, 0);
+        self.emit(Opcodes::End);
+        self.emit(Opcodes::End);
+
+        // no equal element was found before the end of the set
+        self.emit_with_index(Opcodes::GetLocal, index);
+        self.emit_with_index(Opcodes::GetLocal, length);
+        self.emit(Opcodes::I32Eq);
+        self.function
+            .body
+            .push(Instruction::block(Opcodes::If, Blocktype::Void));
+        self.element_address(address, length);
+        self.emit_with_index(Opcodes::GetLocal, value);
+        self.function
+            .body
+            .push(Instruction::memory(Opcodes::F32Store, 2, 8));
+        self.increment(length);
+        self.emit(Opcodes::End);
+    }
+
+    /// Pushes the address of the element at the index in the local, before the offset
+    /// of the elements.
+    fn element_address(&mut self, address: u32, index: u32) {
+        self.emit_with_index(Opcodes::GetLocal, address);
+        self.emit_with_index(Opcodes::GetLocal, index);
+        self.function.body.push(Instruction::i32_const(2));
+        self.emit(Opcodes::I32Shl);
+        self.emit(Opcodes::I32Add);
+    }
+
+    fn increment(&mut self, local: u32) {
+        self.emit_with_index(Opcodes::GetLocal, local);
+        self.function.body.push(Instruction::i32_const(1));
+        self.emit(Opcodes::I32Add);
+        self.emit_with_index(Opcodes::SetLocal, local);
+    }
+
+    /// Stores an object with the contents known at compile time and returns its address.
+    fn static_object(&mut self, tag: u32, content: &[u8]) -> u32 {
+        let address = DATA_START + self.data.len() as u32;
+        self.data.extend(tag.to_le_bytes());
+        self.data.extend((content.len() as u32).to_le_bytes());
+        self.data.extend(content);
+        // keep the next object aligned
+        self.data
+            .resize(align(self.data.len() as u32, 4) as usize, 0);
+        address
+    }
+
+    /// Pushes the address of a new object of the given size and moves the heap after it.
+    /// The memory grows when the object does not fit in it. It traps when the heap would
+    /// pass [`HEAP_LIMIT`] or the memory cannot grow.
+    fn allocate(&mut self, size: u32) {
+        let heap = match self.heap {
+            Some(heap) => heap,
+            None => {
+                let heap = self.builder.add_global(Valtype::I32, true, vec![]);
+                self.heap = Some(heap);
+                heap
+            }
+        };
+        self.emit_with_index(Opcodes::GetGlobal, heap);
+        self.emit_with_index(Opcodes::GetGlobal, heap);
+        self.function
+            .body
+            .push(Instruction::i32_const(align(size, 8) as i32));
+        self.emit(Opcodes::I32Add);
+        self.emit_with_index(Opcodes::SetGlobal, heap);
+        // the heap is below the limit before the allocation, so the addition cannot wrap
+        self.emit_with_index(Opcodes::GetGlobal, heap);
+        self.function
+            .body
+            .push(Instruction::i32_const(HEAP_LIMIT as i32));
+        self.emit(Opcodes::I32GtU);
+        self.trap_if(RuntimeError::OutOfMemory);
+
+        self.pages_in_use(heap);
+        self.emit(Opcodes::MemorySize);
+        self.emit(Opcodes::I32GtU);
+        self.function
+            .body
+            .push(Instruction::block(Opcodes::If, Blocktype::Void));
+        self.pages_in_use(heap);
+        self.emit(Opcodes::MemorySize);
+        self.emit(Opcodes::I32Sub);
+        self.emit(Opcodes::MemoryGrow);
+        // the previous size, or -1 when the memory cannot grow
+        self.function.body.push(Instruction::i32_const(-1));
+        self.emit(Opcodes::I32Eq);
+        self.trap_if(RuntimeError::OutOfMemory);
+        self.emit(Opcodes::End);
+    }
+
+    /// Pushes the number of pages up to the end of the heap.
+    fn pages_in_use(&mut self, heap: u32) {
+        self.emit_with_index(Opcodes::GetGlobal, heap);
+        self.function
+            .body
+            .push(Instruction::i32_const(PAGE_SIZE as i32 - 1));
+        self.emit(Opcodes::I32Add);
+        self.function
+            .body
+            .push(Instruction::i32_const(PAGE_SIZE.trailing_zeros() as i32));
+        self.emit(Opcodes::I32ShrU);
+    }
+
+    /// Writes the type tag and the length of the object at the address in the local.
+    fn store_header(&mut self, address: u32, tag: u32, length: u32) {
+        for (offset, value) in [(0, tag), (4, length)] {
+            self.emit_with_index(Opcodes::GetLocal, address);
+            self.function
+                .body
+                .push(Instruction::i32_const(value as i32));
+            self.function
+                .body
+                .push(Instruction::memory(Opcodes::I32Store, 2, offset));
+        }
+    }
+
+    fn add_local(&mut self, valtype: Valtype) -> u32 {
+        self.function.locals.push(valtype);
+        self.function.params + (self.function.locals.len() - 1) as u32
+    }
+
+    /// Opens a block with an `f32` result, closed by `end_block`.
+    fn block(&mut self, opcode: Opcodes, label: Label) {
+        self.function
+            .body
+            .push(Instruction::block(opcode, Blocktype::F32));
+        self.function.labels.push(label);
+    }
+
+    fn end_block(&mut self) {
+        self.emit(Opcodes::End);
+        self.function.labels.pop();
+    }
+
+    fn number(&mut self, value: f32) {
+        self.function.body.push(Instruction::f32_const(value));
+    }
+
+    fn emit(&mut self, opcode: Opcodes) {
+        self.function.body.push(Instruction::new(opcode));
+    }
+
+    fn emit_with_index(&mut self, opcode: Opcodes, index: u32) {
+        self.function
+            .body
+            .push(Instruction::with_index(opcode, index));
+    }
+}
+
+/// Collects the names used by the expression which are not bound in it, in order of
+/// their first use. `bound` holds the names in scope, it is restored after each form.
+fn free_variables(expression: &Expression, bound: &mut Vec<String>, free: &mut Vec<String>) {
+    match expression {
+        Expression::Number(_) | Expression::Regex(_) => {}
+        Expression::Variable(symbol) => {
+            if !bound.contains(&symbol.name) && !free.contains(&symbol.name) {
+                free.push(symbol.name.clone());
+            }
+        }
+        Expression::Define { value, .. } => free_variables(value, bound, free),
+        Expression::Function(function) => {
+            let scope = bound.len();
+            bound.extend(function.params.iter().cloned());
+            free_variables(&function.body, bound, free);
+            bound.truncate(scope);
+        }
+        Expression::If {
+            test,
+            then,
+            otherwise,
+        } => {
+            for expression in [test, then, otherwise] {
+                free_variables(expression, bound, free);
+            }
+        }
+        Expression::Set(expressions)
+        | Expression::Do(expressions)
+        | Expression::Recur(expressions)
+        | Expression::Builtin(_, expressions) => {
+            for expression in expressions {
+                free_variables(expression, bound, free);
+            }
+        }
+        Expression::Let { bindings, body } | Expression::Loop { bindings, body } => {
+            let scope = bound.len();
+            for binding in bindings {
+                free_variables(&binding.value, bound, free);
+                bound.push(binding.name.clone());
+            }
+            free_variables(body, bound, free);
+            bound.truncate(scope);
+        }
+        Expression::Call { callee, args } => {
+            free_variables(callee, bound, free);
+            for arg in args {
+                free_variables(arg, bound, free);
+            }
+        }
+    }
+}
+
+/// Counts the `def` forms of every name in the expression.
+fn count_definitions(expression: &Expression, counts: &mut HashMap<String, usize>) {
+    match expression {
+        Expression::Number(_) | Expression::Regex(_) | Expression::Variable(_) => {}
+        Expression::Define { name, value } => {
+            *counts.entry(name.clone()).or_default() += 1;
+            count_definitions(value, counts);
+        }
+        Expression::Function(function) => count_definitions(&function.body, counts),
+        Expression::If {
+            test,
+            then,
+            otherwise,
+        } => {
+            for expression in [test, then, otherwise] {
+                count_definitions(expression, counts);
+            }
+        }
+        Expression::Set(expressions)
+        | Expression::Do(expressions)
+        | Expression::Recur(expressions)
+        | Expression::Builtin(_, expressions) => {
+            for expression in expressions {
+                count_definitions(expression, counts);
+            }
+        }
+        Expression::Let { bindings, body } | Expression::Loop { bindings, body } => {
+            for binding in bindings {
+                count_definitions(&binding.value, counts);
+            }
+            count_definitions(body, counts);
+        }
+        Expression::Call { callee, args } => {
+            count_definitions(callee, counts);
+            for arg in args {
+                count_definitions(arg, counts);
+            }
+        }
+    }
+}
+
+fn align(value: u32, alignment: u32) -> u32 {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::{
+        compile, RuntimeError, CLOSURE_TAG, MEMORY_EXPORT, REGEX_TAG, RUN_EXPORT, SET_TAG,
+    };
+    use crate::parser::Parser;
+    use crate::resolver::resolve;
+    use crate::runtime::invoke_wasm_module;
+    use crate::scanner::Scanner;
+    use wasmtime::{Engine, Instance, Module, Store};
+
+    fn run(source: &str) -> f32 {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        let mut program = parser.parse().unwrap().clone();
+        resolve(&mut program).unwrap();
+        let bytes = compile(&program).unwrap();
+        invoke_wasm_module(&bytes).unwrap()
+    }
+
+    /// Result of the program with the memory after running it.
+    fn run_with_memory(source: &str) -> (f32, Vec<u8>) {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        let bytes = compile(parser.parse().unwrap()).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, bytes).unwrap();
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).unwrap();
+        let run = instance
+            .get_typed_func::<(), f32, _>(&mut store, RUN_EXPORT)
+            .unwrap();
+        let result = run.call(&mut store, ()).unwrap();
+        let memory = instance.get_memory(&mut store, MEMORY_EXPORT).unwrap();
+        (result, memory.data(&store).to_vec())
+    }
+
+    /// Words of the object referenced by the value.
+    fn words(memory: &[u8], reference: f32, count: usize) -> Vec<u32> {
+        memory[reference.to_bits() as usize..]
+            .chunks(4)
+            .take(count)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    fn compile_error(source: &str) -> String {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        let program = parser.parse().unwrap();
+        compile(program).unwrap_err()[0].message.clone()
+    }
+
+    #[test]
+    fn compile_empty_program() {
+        assert_eq!(run(""), 0.0);
+    }
+
+    #[test]
+    fn compile_arithmetic() {
+        assert_eq!(run("(+)"), 0.0);
+        assert_eq!(run("(*)"), 1.0);
+        assert_eq!(run("(+ 1 2 3)"), 6.0);
+        assert_eq!(run("(- 10 4 1)"), 5.0);
+        assert_eq!(run("(- 3)"), -3.0);
+        assert_eq!(run("(* 2 2.5)"), 5.0);
+        assert_eq!(run("(/ 9 2)"), 4.5);
+        assert_eq!(run("(/ 4)"), 0.25);
+        assert_eq!(run("(+ 1/2 1/4)"), 0.75);
+    }
+
+    #[test]
+    fn compile_nested_calls() {
+        assert_eq!(run("(* (+ 1 2) (- 10 (/ 8 2)))"), 18.0);
+    }
+
+    #[test]
+    fn compile_comparisons() {
+        assert_eq!(run("(< 1 2)"), 1.0);
+        assert_eq!(run("(> 1 2)"), 0.0);
+        assert_eq!(run("(<= 2 2)"), 1.0);
+        assert_eq!(run("(>= 1 2)"), 0.0);
+        assert_eq!(run("(= (+ 1 1) 2)"), 1.0);
+        assert_eq!(run("(not= 1 1)"), 0.0);
+        assert_eq!(run("(= true (< 1 2))"), 1.0);
+    }
+
+    #[test]
+    fn return_last_form() {
+        assert_eq!(run("(+ 1 2) (* 3 4)"), 12.0);
+    }
+
+    #[test]
+    fn compile_definitions() {
+        assert_eq!(run("(def x 2)"), 2.0);
+        assert_eq!(run("(def x 2) (def y (* x 3)) (+ x y)"), 8.0);
+        assert_eq!(run("(def x 2) (def x (+ x 1)) (+ x)"), 3.0);
+        assert_eq!(run("(def x \"The answer\" 42) (+ x)"), 42.0);
+        // resolved globals are readable before their definition
+        assert_eq!(
+            run("(def f (fn [] (+ y 1))) (def a (f)) (def y 10) (+ a (f))"),
+            12.0
+        );
+    }
+
+    #[test]
+    fn compile_special_forms() {
+        assert_eq!(run("(if (< 1 2) 10 20)"), 10.0);
+        assert_eq!(run("(if 0 10 20)"), 20.0);
+        assert_eq!(run("(if false 10)"), 0.0);
+        assert_eq!(run("(do (def x 1) (+ x 1))"), 2.0);
+        assert_eq!(run("(let [x 2 y (* x 3)] (let [x 10] (+ x y)))"), 16.0);
+        assert_eq!(
+            run("(loop [i 0 acc 1] (if (< i 5) (recur (+ i 1) (* acc 2)) acc))"),
+            32.0
+        );
+    }
+
+    #[test]
+    fn compile_functions() {
+        assert_eq!(run("(def add (fn [a b] (+ a b))) (add 1 2)"), 3.0);
+        assert_eq!(
+            run("(def fact (fn [n] (if (<= n 1) 1 (* n (fact (- n 1)))))) (fact 5)"),
+            120.0
+        );
+        assert_eq!(
+            run("(def sum (fn [n acc] (if (= n 0) acc (recur (- n 1) (+ acc n))))) (sum 100 0)"),
+            5050.0
+        );
+        assert_eq!(
+            run("(def f (fn [x] (let [y (* x x)] (loop [i 0] (if (< i y) (recur (+ i 1)) i))))) (f 3)"),
+            9.0
+        );
+    }
+
+    #[test]
+    fn compile_closures() {
+        assert_eq!(
+            run("(def adder (fn [n] (fn [x] (+ x n)))) (def add5 (adder 5)) (add5 10)"),
+            15.0
+        );
+        assert_eq!(
+            run("(def adder (fn [n] (fn [x] (+ x n)))) ((adder 1) 2)"),
+            3.0
+        );
+        assert_eq!(run("((fn [] 1))"), 1.0);
+        assert_eq!(
+            run("(def twice (fn [f x] (f (f x)))) (twice #(* % 3) 2)"),
+            18.0
+        );
+        assert_eq!(run("(#(- %2 %1) 1 5)"), 4.0);
+        // captured values are copied to the nested closures
+        assert_eq!(
+            run("(let [a 1 f (fn [b] (fn [c] (+ a b c)))] ((f 2) 3))"),
+            6.0
+        );
+        assert_eq!(
+            run("(let [step 2 f (fn [n acc] (if (= n 0) acc (recur (- n 1) (+ acc step))))] (f 3 0))"),
+            6.0
+        );
+        assert_eq!(run("(#(if (> % 0) (recur (- % 1)) 7) 3)"), 7.0);
+    }
+
+    #[test]
+    fn allocate_closures() {
+        let (closure, memory) = run_with_memory("(let [x 2 y 3] (fn [z] (+ x z)))");
+        assert_eq!(
+            words(&memory, closure, 5),
+            vec![CLOSURE_TAG, 1, 0, 1, 2f32.to_bits()]
+        );
+
+        let (closure, memory) = run_with_memory("(def f (fn [] 1)) (def g (fn [] 2)) (+ g)");
+        assert_eq!(words(&memory, closure, 4), vec![CLOSURE_TAG, 0, 1, 0]);
+
+        // every iteration allocates a closure capturing the counter
+        assert_eq!(
+            run("(loop [i 0 f (fn [] -1)] (if (< i 10000) (recur (+ i 1) (fn [] i)) (f)))"),
+            9999.0
+        );
+    }
+
+    #[test]
+    fn allocate_past_the_integers_of_f32() {
+        // 24 bytes per closure, the last ones are above 2^27, where an f32 cannot hold
+        // every address as a number
+        assert_eq!(
+            run("(loop [i 0 f (fn [] -1)] (if (< i 6000000) (recur (+ i 1) (fn [] i)) (f)))"),
+            5999999.0
+        );
+    }
+
+    #[test]
+    fn call_redefined_functions() {
+        assert_eq!(
+            run("(def g (fn [x] 1)) (def f (fn [x] (g x))) (def g (fn [x] 2)) (f 0)"),
+            2.0
+        );
+        assert_eq!(
+            run("(def g (fn [x] 1)) (def f (fn [] (g))) (def g (fn [] 2)) (f)"),
+            2.0
+        );
+        assert_eq!(run("(def g (fn [x] 1)) (def g (fn [] 2)) (g)"), 2.0);
+        assert_eq!(
+            run("(let [a 3] (def g (fn [] a))) (def k (fn [] (g))) (let [b 9 c 10] (def g (fn [] (+ b c)))) (k)"),
+            19.0
+        );
+        assert_eq!(
+            run("(def x 5) (def f (fn [] (x))) (def x (fn [] 1)) (f)"),
+            1.0
+        );
+    }
+
+    #[test]
+    fn call_shadowed_core_functions() {
+        assert_eq!(run("(let [+ (fn [a b] (- a b))] (+ 5 2))"), 3.0);
+        assert_eq!(run("(def * (fn [a b] (+ a b))) (* 5 2)"), 7.0);
+    }
+
+    #[test]
+    fn check_called_values() {
+        let error = |source: &str| {
+            let mut scanner = Scanner::new(source);
+            let mut parser = Parser::new(&mut scanner);
+            let bytes = compile(parser.parse().unwrap()).unwrap();
+            invoke_wasm_module(&bytes).unwrap_err().to_string()
+        };
+        let not_a_function = RuntimeError::NotAFunction.message();
+        assert_eq!(
+            error("(def f (fn [x] (* x 100))) (let [zero 0] (zero 5))"),
+            not_a_function
+        );
+        assert_eq!(error("(let [s #{1}] (s))"), not_a_function);
+        assert_eq!(error("(let [r #\"a\"] (r))"), not_a_function);
+        assert_eq!(error("(let [n -1] (n))"), not_a_function);
+        assert_eq!(error("(let [n 100000000] (n))"), not_a_function);
+        assert_eq!(error("(let [n (/ 0 0)] (n))"), not_a_function);
+        assert_eq!(
+            error("(let [f (fn [x] x)] (f 1 2))"),
+            RuntimeError::ArityMismatch.message()
+        );
+        assert_eq!(
+            error("(def apply (fn [f] (f 1))) (apply (fn [] 2))"),
+            RuntimeError::ArityMismatch.message()
+        );
+        assert_eq!(
+            error("(def f (fn [n] (if (= n 0) 0 (+ 1 (f (- n 1)))))) (f 1000000)"),
+            RuntimeError::StackOverflow.message()
+        );
+    }
+
+    #[test]
+    fn allocate_sets() {
+        let (set, memory) = run_with_memory("(def x 2) (+ #{1 x (+ x 1)})");
+        assert_eq!(set.to_bits(), 16);
+        assert_eq!(
+            words(&memory, set, 5),
+            vec![SET_TAG, 3, 1f32.to_bits(), 2f32.to_bits(), 3f32.to_bits()]
+        );
+
+        let (set, memory) = run_with_memory("(+ #{#{} 1})");
+        let inner = f32::from_bits(words(&memory, set, 3)[2]);
+        assert_eq!(words(&memory, inner, 2), vec![SET_TAG, 0]);
+        assert_ne!(inner, set);
+    }
+
+    #[test]
+    fn store_equal_set_elements_once() {
+        let (set, memory) = run_with_memory("(def a 2) (def b 2) (+ #{a b 3})");
+        assert_eq!(
+            words(&memory, set, 4),
+            vec![SET_TAG, 2, 2f32.to_bits(), 3f32.to_bits()]
+        );
+
+        let (set, memory) = run_with_memory("(let [x 1] #{x 1 (+ x 1)})");
+        assert_eq!(
+            words(&memory, set, 4),
+            vec![SET_TAG, 2, 1f32.to_bits(), 2f32.to_bits()]
+        );
+
+        // the length starts again from zero when a loop builds the set again
+        let (set, memory) =
+            run_with_memory("(loop [i 0 s #{}] (if (< i 3) (recur (+ i 1) #{i i}) s))");
+        assert_eq!(words(&memory, set, 3), vec![SET_TAG, 1, 2f32.to_bits()]);
+    }
+
+    #[test]
+    fn grow_memory() {
+        assert_eq!(
+            run("(loop [i 0] (if (< i 10000) (do #{i} (recur (+ i 1))) i))"),
+            10000.0
+        );
+        let (set, memory) =
+            run_with_memory("(loop [i 0 s #{}] (if (< i 10000) (recur (+ i 1) #{i 1/2}) s))");
+        assert!(memory.len() > 65536 * 2);
+        assert_eq!(
+            words(&memory, set, 4),
+            vec![SET_TAG, 2, 9999f32.to_bits(), 0.5f32.to_bits()]
+        );
+    }
+
+    #[test]
+    fn store_regexes() {
+        let (regex, memory) = run_with_memory("(def r #\"a+\") (+ r)");
+        assert_eq!(regex.to_bits(), 16);
+        assert_eq!(words(&memory, regex, 2), vec![REGEX_TAG, 2]);
+        assert_eq!(&memory[24..26], b"a+");
+
+        // the heap starts after the stored objects
+        let (set, memory) = run_with_memory("(def r #\"a+\") (+ #{r})");
+        assert_eq!(set.to_bits(), 32);
+        assert_eq!(words(&memory, set, 3), vec![SET_TAG, 1, 16]);
+    }
+
+    #[test]
+    fn report_unsupported_code() {
+        assert_eq!(compile_error("(foo 1)"), "Unknown function 'foo'");
+        assert_eq!(
+            compile_error("(+ #{1 x 1.0})"),
+            "Duplicate element 1 in set literal"
+        );
+        assert_eq!(compile_error("(+ x 1)"), "Unknown identifier 'x'");
+        assert_eq!(
+            compile_error("(def 1 2)"),
+            "'def' expects a name as first argument"
+        );
+        assert_eq!(compile_error("()"), "Cannot evaluate an empty list");
+        assert_eq!(
+            compile_error("(def f (fn [a] a)) (f)"),
+            "Function 'f' expects 1 arguments, but get 0"
+        );
+        assert_eq!(compile_error("(def x 1) (x)"), "'x' is not a function");
+        assert_eq!(
+            compile_error("(def x #{}) (def f (fn [] (x)))"),
+            "'x' is not a function"
+        );
+        assert_eq!(
+            compile_error("(1 2)"),
+            "Expected a function name, but get 1"
+        );
+        assert_eq!(compile_error("(< 1)"), "'<' expects 2 arguments, but get 1");
+        assert_eq!(compile_error("(-)"), "'-' expects at least one argument");
+        assert_eq!(compile_error("(+ 1 \"a\")"), "Unsupported expression \"a\"");
+    }
+}
@@ -0,0 +1,346 @@
+use crate::scanner::Scanner;
+use crate::token::{Span, TokenType, Trivia};
+use std::fmt::{Display, Formatter};
+
+/// Concrete syntax tree of a source file. Unlike the [`Program`](crate::parser::Program) of the
+/// parser it keeps every token with its delimiters, comments, commas and whitespace, so
+/// printing the tree gives back the exact source. It accepts the same grammar as the
+/// parser, but never fails: unmatched closing delimiters end up in `Error` nodes and
+/// forms left open at the end of the file have no closing token.
+pub fn parse(source: &str) -> Node<'_> {
+    let mut parser = CstParser::new(source);
+    let mut children = vec![];
+    while parser.current.kind != TokenType::Eof {
+        children.push(parser.element());
+    }
+    // the end of file token holds the trivia after the last form
+    children.push(Element::Token(parser.advance()));
+    Node {
+        kind: NodeKind::Root,
+        children,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum NodeKind {
+    Root,
+    /// `( ... )`
+    List,
+    /// `[ ... ]`
+    Vector,
+    /// `{ ... }`
+    Map,
+    /// `#( ... )`
+    AnonymousFunction,
+    /// `#{ ... }`
+    Set,
+    /// `#_` with the form it discards
+    Discard,
+    /// `'`, `` ` ``, `~` or `~@` with the form it applies to
+    ReaderMacro,
+    /// Closing delimiter without a matching opening one
+    Error,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxToken<'a> {
+    pub kind: TokenType,
+    pub span: Span,
+    /// Exact source text of the token, strings included with their quotes
+    pub text: &'a str,
+    /// Whitespace, commas and comments between the previous token and this one
+    pub leading_trivia: Vec<Trivia<'a>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Element<'a> {
+    Node(Node<'a>),
+    Token(SyntaxToken<'a>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node<'a> {
+    pub kind: NodeKind,
+    /// Tokens and nested nodes in source order, delimiters included
+    pub children: Vec<Element<'a>>,
+}
+
+impl<'a> Node<'a> {
+    /// Byte range from the first to the last token of the node, without the leading trivia.
+    pub fn span(&self) -> Span {
+        let mut tokens = self.tokens();
+        match tokens.next() {
+            Some(first) => Span::new(first.span.start, tokens.last().unwrap_or(first).span.end),
+            None => Span::default(),
+        }
+    }
+
+    /// Every token of the node and its descendants in source order.
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken<'a>> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens.into_iter()
+    }
+
+    fn collect_tokens<'n>(&'n self, tokens: &mut Vec<&'n SyntaxToken<'a>>) {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.collect_tokens(tokens),
+                Element::Token(token) => tokens.push(token),
+            }
+        }
+    }
+}
+
+impl Display for Node<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for token in self.tokens() {
+            for trivia in &token.leading_trivia {
+                f.write_str(trivia.text)?;
+            }
+            f.write_str(token.text)?;
+        }
+        Ok(())
+    }
+}
+
+struct CstParser<'a> {
+    source: &'a str,
+    scanner: Scanner<'a>,
+    current: SyntaxToken<'a>,
+}
+
+impl<'a> CstParser<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut parser = CstParser {
+            source,
+            scanner: Scanner::with_trivia(source),
+            current: SyntaxToken {
+                kind: TokenType::Init,
+                span: Span::default(),
+                text: "",
+                leading_trivia: vec![],
+            },
+        };
+        parser.advance();
+        parser
+    }
+
+    /// Returns the current token and scans the next one.
+    fn advance(&mut self) -> SyntaxToken<'a> {
+        let token = self.scanner.scan_token();
+        let next = SyntaxToken {
+            kind: token.kind,
+            span: token.span,
+            text: &self.source[token.span.start..token.span.end],
+            leading_trivia: self.scanner.take_trivia(),
+        };
+        std::mem::replace(&mut self.current, next)
+    }
+
+    fn element(&mut self) -> Element<'a> {
+        match self.current.kind {
+            TokenType::LeftParen => self.node(NodeKind::List, vec![], TokenType::RightParen),
+            TokenType::LeftSquare => self.node(NodeKind::Vector, vec![], TokenType::RightSquare),
+            TokenType::LeftBrace => self.node(NodeKind::Map, vec![], TokenType::RightBrace),
+            TokenType::Dispatch => {
+                let dispatch = Element::Token(self.advance());
+                match self.current.kind {
+                    TokenType::LeftParen => self.node(
+                        NodeKind::AnonymousFunction,
+                        vec![dispatch],
+                        TokenType::RightParen,
+                    ),
+                    TokenType::LeftBrace => {
+                        self.node(NodeKind::Set, vec![dispatch], TokenType::RightBrace)
+                    }
+                    _ => dispatch,
+                }
+            }
+            TokenType::Discard => {
+                let mut children = vec![Element::Token(self.advance())];
+                // in `#_ #_ a b` the outer discard takes `b` after the inner `#_ a`
+                while !self.is_closing() {
+                    let element = self.element();
+                    let nested =
+                        matches!(&element, Element::Node(node) if node.kind == NodeKind::Discard);
+                    children.push(element);
+                    if !nested {
+                        break;
+                    }
+                }
+                Element::Node(Node {
+                    kind: NodeKind::Discard,
+                    children,
+                })
+            }
+            TokenType::Quote
+            | TokenType::SyntaxQuote
+            | TokenType::Unquote
+            | TokenType::UnquoteSplicing => {
+                let mut children = vec![Element::Token(self.advance())];
+                if !self.is_closing() {
+                    children.push(self.element());
+                }
+                Element::Node(Node {
+                    kind: NodeKind::ReaderMacro,
+                    children,
+                })
+            }
+            TokenType::RightParen | TokenType::RightSquare | TokenType::RightBrace => {
+                Element::Node(Node {
+                    kind: NodeKind::Error,
+                    children: vec![Element::Token(self.advance())],
+                })
+            }
+            _ => Element::Token(self.advance()),
+        }
+    }
+
+    /// The current token ends the enclosing node.
+    fn is_closing(&self) -> bool {
+        matches!(
+            self.current.kind,
+            TokenType::Eof | TokenType::RightParen | TokenType::RightSquare | TokenType::RightBrace
+        )
+    }
+
+    /// Parses a delimited node starting at its opening token, after the given prefix tokens.
+    fn node(
+        &mut self,
+        kind: NodeKind,
+        mut children: Vec<Element<'a>>,
+        closing: TokenType,
+    ) -> Element<'a> {
+        children.push(Element::Token(self.advance()));
+        loop {
+            match self.current.kind {
+                TokenType::Eof => break,
+                next if next == closing => {
+                    children.push(Element::Token(self.advance()));
+                    break;
+                }
+                _ => children.push(self.element()),
+            }
+        }
+        Element::Node(Node { kind, children })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cst::{parse, Element, NodeKind};
+    use crate::token::{Span, TokenType, TriviaKind};
+
+    #[test]
+    fn rebuild_exact_source() {
+        let sources = [
+            "",
+            "  ; only a comment\n",
+            "(def x 1) ; one\n\n;# block\n comment #;\n(+ x, 2)\n",
+            "{:a 1, :b [1 2 3]}\t#(+ % 1)\r\n",
+            "(print \"a \\\"quoted\\\" ; string\")",
+            "(a ] b",
+            "(unclosed (list",
+            "(é @ 1/x",
+            "(a #_ #_ b [c] #_)",
+            "`(a ~b ~@ [c] '#_d e ')",
+            "(a ;# unterminated ;# comment #;",
+        ];
+        for source in sources {
+            assert_eq!(parse(source).to_string(), source);
+        }
+    }
+
+    #[test]
+    fn build_nodes() {
+        let root = parse("(f [1] {:a 2} #(g) 'h #{i} #\"j\")");
+
+        assert_eq!(root.kind, NodeKind::Root);
+        let list = match &root.children[0] {
+            Element::Node(node) => node,
+            other => panic!("Expected a node, but get {:?}", other),
+        };
+        assert_eq!(list.kind, NodeKind::List);
+        let kinds: Vec<_> = list
+            .children
+            .iter()
+            .map(|child| match child {
+                Element::Node(node) => format!("{:?}", node.kind),
+                Element::Token(token) => token.kind.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "LeftParen",
+                "Identifier",
+                "Vector",
+                "Map",
+                "AnonymousFunction",
+                "ReaderMacro",
+                "Set",
+                "Regex",
+                "RightParen"
+            ]
+        );
+        assert_eq!(list.span(), Span::new(0, 32));
+    }
+
+    #[test]
+    fn attach_trivia_to_next_token() {
+        let root = parse("(a, ; note\n b) ;# end #;");
+        let tokens: Vec<_> = root.tokens().collect();
+
+        let trivia: Vec<_> = tokens[2]
+            .leading_trivia
+            .iter()
+            .map(|trivia| (trivia.kind, trivia.text))
+            .collect();
+        assert_eq!(tokens[2].text, "b");
+        assert_eq!(
+            trivia,
+            vec![
+                (TriviaKind::Comma, ","),
+                (TriviaKind::Whitespace, " "),
+                (TriviaKind::LineComment, "; note"),
+                (TriviaKind::Whitespace, "\n "),
+            ]
+        );
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.kind, TokenType::Eof);
+        assert_eq!(eof.leading_trivia[1].kind, TriviaKind::BlockComment);
+        assert_eq!(eof.leading_trivia[1].span, Span::new(15, 24));
+    }
+
+    #[test]
+    fn group_discarded_forms() {
+        let root = parse("#_ #_ a [b] c");
+
+        let discard = match &root.children[0] {
+            Element::Node(node) => node,
+            other => panic!("Expected a node, but get {:?}", other),
+        };
+        assert_eq!(discard.kind, NodeKind::Discard);
+        assert_eq!(discard.to_string(), "#_ #_ a [b]");
+        assert!(
+            matches!(&discard.children[1], Element::Node(node) if node.kind == NodeKind::Discard)
+        );
+        assert!(matches!(&root.children[1], Element::Token(token) if token.text == "c"));
+    }
+
+    #[test]
+    fn keep_unmatched_delimiters() {
+        let root = parse(") (a ]");
+
+        assert!(matches!(&root.children[0], Element::Node(node) if node.kind == NodeKind::Error));
+        let list = match &root.children[1] {
+            Element::Node(node) => node,
+            other => panic!("Expected a node, but get {:?}", other),
+        };
+        assert_eq!(list.kind, NodeKind::List);
+        assert!(
+            matches!(list.children.last(), Some(Element::Node(node)) if node.kind == NodeKind::Error)
+        );
+    }
+}
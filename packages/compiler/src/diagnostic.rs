@@ -0,0 +1,379 @@
+use crate::token::Span;
+use serde_json::{json, Value};
+use std::fmt::{Display, Formatter};
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Severity {
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Stable identifiers of the diagnostics. Codes must never be reused or renumbered,
+/// tools and documentation refer to them.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum ErrorCode {
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidEscape,
+    UnterminatedFraction,
+    UnterminatedComment,
+    InvalidRegex,
+    /// A number literal out of the range of its type
+    InvalidNumber,
+    ExpectedToken,
+    UnexpectedToken,
+    /// The source ended inside a form, more input could complete it
+    UnexpectedEof,
+    UnboundSymbol,
+    /// A special form or an expression which cannot be compiled
+    InvalidForm,
+    ArityMismatch,
+    /// `recur` outside of a `loop` or `fn`, or not in tail position
+    InvalidRecur,
+    DuplicateSetElement,
+    /// A call of a name whose value is known not to be a function
+    NotCallable,
+    InvalidMacro,
+    /// The body of a macro failed while expanding a call
+    MacroExpansion,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "E0001",
+            ErrorCode::UnterminatedString => "E0002",
+            ErrorCode::InvalidEscape => "E0003",
+            ErrorCode::UnterminatedFraction => "E0004",
+            ErrorCode::UnterminatedComment => "E0005",
+            ErrorCode::InvalidRegex => "E0006",
+            ErrorCode::InvalidNumber => "E0007",
+            ErrorCode::ExpectedToken => "E0101",
+            ErrorCode::UnexpectedToken => "E0102",
+            ErrorCode::UnexpectedEof => "E0103",
+            ErrorCode::UnboundSymbol => "E0201",
+            ErrorCode::InvalidForm => "E0301",
+            ErrorCode::ArityMismatch => "E0302",
+            ErrorCode::InvalidRecur => "E0303",
+            ErrorCode::DuplicateSetElement => "E0304",
+            ErrorCode::NotCallable => "E0305",
+            ErrorCode::InvalidMacro => "E0401",
+            ErrorCode::MacroExpansion => "E0402",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A problem found in the source code, located by its byte span.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub span: Span,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            span,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Machine-readable form of the diagnostic for editors and CI annotators.
+    pub fn to_json(&self, file_name: &str, source: &str) -> Value {
+        let (line, column) = line_column(source, self.span.start);
+        let (end_line, end_column) = line_column(source, self.span.end);
+        json!({
+            "file": file_name,
+            "severity": self.severity.to_string(),
+            "code": self.code.as_str(),
+            "message": self.message,
+            "help": self.help,
+            "span": { "start": self.span.start, "end": self.span.end },
+            "line": line,
+            "column": column,
+            "end_line": end_line,
+            "end_column": end_column,
+        })
+    }
+}
+
+/// How the diagnostics are reported, selected by `--error-format`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!(
+                "Unknown error format '{}', expected 'human' or 'json'",
+                value
+            )),
+        }
+    }
+}
+
+/// Prints the diagnostics of a file to stderr in the requested format.
+pub fn report(diagnostics: &[Diagnostic], file_name: &str, source: &str, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Human => {
+            let renderer =
+                DiagnosticRenderer::new(file_name, source, std::io::stderr().is_terminal());
+            for diagnostic in diagnostics {
+                eprintln!("{}", renderer.render(diagnostic));
+            }
+        }
+        ErrorFormat::Json => {
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic.to_json(file_name, source));
+            }
+        }
+    }
+}
+
+/// Returns the 1-based line and column (counted in characters) of a byte offset.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+    let line = source[..line_start].matches('\n').count() + 1;
+    let column = source[line_start..offset].chars().count() + 1;
+    (line, column)
+}
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders diagnostics for the terminal with the offending source line and
+/// a caret underline below the span.
+pub struct DiagnosticRenderer<'a> {
+    file_name: &'a str,
+    source: &'a str,
+    color: bool,
+}
+
+impl<'a> DiagnosticRenderer<'a> {
+    pub fn new(file_name: &'a str, source: &'a str, color: bool) -> Self {
+        DiagnosticRenderer {
+            file_name,
+            source,
+            color,
+        }
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let start = diagnostic.span.start.min(self.source.len());
+        let (line, column) = line_column(self.source, start);
+        let line_start = self.source[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_text = self.source[line_start..]
+            .split('\n')
+            .next()
+            .unwrap_or("")
+            .trim_end_matches('\r');
+
+        // keep tabs in the padding so the carets line up with the source line
+        let padding: String = line_text
+            .chars()
+            .take(column - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let line_end = line_start + line_text.len();
+        let underline_end = diagnostic.span.end.clamp(start, line_end);
+        let width = self.source[start.min(line_end)..underline_end]
+            .chars()
+            .count()
+            .max(1);
+
+        let gutter = " ".repeat(line.to_string().len());
+        let severity_color = match diagnostic.severity {
+            Severity::Error => RED,
+        };
+
+        let mut out = String::new();
+        out += &format!(
+            "{}{}[{}]{}{}: {}{}\n",
+            self.paint(severity_color),
+            diagnostic.severity,
+            diagnostic.code,
+            self.paint(RESET),
+            self.paint(BOLD),
+            diagnostic.message,
+            self.paint(RESET),
+        );
+        out += &format!(
+            "{}{}-->{} {}:{}:{}\n",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            self.file_name,
+            line,
+            column
+        );
+        out += &format!("{} {}|{}\n", gutter, self.paint(BLUE), self.paint(RESET));
+        out += &format!(
+            "{}{} |{} {}\n",
+            self.paint(BLUE),
+            line,
+            self.paint(RESET),
+            line_text
+        );
+        out += &format!(
+            "{} {}|{} {}{}{}{}\n",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            padding,
+            self.paint(severity_color),
+            "^".repeat(width),
+            self.paint(RESET),
+        );
+        if let Some(help) = &diagnostic.help {
+            out += &format!(
+                "{} {}={} help: {}\n",
+                gutter,
+                self.paint(BLUE),
+                self.paint(RESET),
+                help
+            );
+        }
+        out
+    }
+
+    fn paint(&self, color: &'static str) -> &'static str {
+        if self.color {
+            color
+        } else {
+            ""
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::{line_column, Diagnostic, DiagnosticRenderer, ErrorCode, ErrorFormat};
+    use crate::token::Span;
+    use serde_json::json;
+
+    #[test]
+    fn compute_line_column() {
+        let source = "(a\n  é b)";
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 1), (1, 2));
+        assert_eq!(line_column(source, 3), (2, 1));
+        assert_eq!(line_column(source, 8), (2, 5));
+        assert_eq!(line_column(source, 100), (2, 7));
+    }
+
+    #[test]
+    fn render_diagnostic() {
+        let source = "(def x 1)\n(foo ] bar)\n";
+        let renderer = DiagnosticRenderer::new("main.pl", source, false);
+        let diagnostic = Diagnostic::error(
+            ErrorCode::UnexpectedToken,
+            Span::new(15, 16),
+            "Unexpected token",
+        )
+        .with_help("remove the bracket");
+
+        assert_eq!(
+            renderer.render(&diagnostic),
+            "error[E0102]: Unexpected token\n \
+              --> main.pl:2:6\n  \
+               |\n\
+             2 | (foo ] bar)\n  \
+               |      ^\n  \
+               = help: remove the bracket\n"
+        );
+    }
+
+    #[test]
+    fn render_multi_character_span() {
+        let source = "(a \"\\x\")";
+        let renderer = DiagnosticRenderer::new("main.pl", source, false);
+        let diagnostic = Diagnostic::error(ErrorCode::InvalidEscape, Span::new(3, 7), "Bad");
+
+        let rendered = renderer.render(&diagnostic);
+
+        assert!(
+            rendered.ends_with("1 | (a \"\\x\")\n  |    ^^^^\n"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn render_span_at_end_of_source() {
+        let source = "(a";
+        let renderer = DiagnosticRenderer::new("main.pl", source, false);
+        let diagnostic = Diagnostic::error(ErrorCode::ExpectedToken, Span::new(2, 2), "Expected");
+
+        let rendered = renderer.render(&diagnostic);
+
+        assert!(rendered.ends_with("1 | (a\n  |   ^\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn diagnostic_to_json() {
+        let source = "(a\n  \"\\x\")";
+        let diagnostic = Diagnostic::error(ErrorCode::InvalidEscape, Span::new(5, 9), "Bad escape")
+            .with_help("fix it");
+
+        assert_eq!(
+            diagnostic.to_json("main.pl", source),
+            json!({
+                "file": "main.pl",
+                "severity": "error",
+                "code": "E0003",
+                "message": "Bad escape",
+                "help": "fix it",
+                "span": { "start": 5, "end": 9 },
+                "line": 2,
+                "column": 3,
+                "end_line": 2,
+                "end_column": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_error_format() {
+        assert_eq!("human".parse(), Ok(ErrorFormat::Human));
+        assert_eq!("json".parse(), Ok(ErrorFormat::Json));
+        assert!("xml".parse::<ErrorFormat>().is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use crate::emitter::{
+    Blocktype, ExportType, FunctionType, Immediate, Instruction, Opcodes, Section, Valtype,
+    FUNCREF, FUNCTION_TYPE, MAGIC_MODULE_HEADER, MODULE_VERSION,
+};
+use crate::wat::{
+    data_string, export_kind, function_indices, instruction_text, signature, valtype, valtypes,
+};
+use anyhow::{anyhow, bail, Result};
+
+/// Limit of the locals of a function, the same as the one of the WebAssembly engines.
+/// The counts are read from the module, so they are checked before allocating.
+const MAX_LOCALS: usize = 50_000;
+
+/// Decodes a binary module with the tables of the emitter and lists its sections,
+/// functions and instructions. Every line of the code section starts with the
+/// offset of the instruction in the file.
+pub fn disassemble(bytes: &[u8]) -> Result<String> {
+    let mut reader = Reader::new(bytes);
+    if reader.read_bytes(4)? != MAGIC_MODULE_HEADER {
+        bail!("Not a WebAssembly module, the magic header is missing");
+    }
+    let version = reader.read_bytes(4)?;
+    if version != MODULE_VERSION {
+        bail!("Unsupported module version {:?}", version);
+    }
+
+    let mut out = String::from("module version 1\n");
+    let mut types: Vec<FunctionType> = vec![];
+    let mut function_types: Vec<u32> = vec![];
+    while !reader.is_at_end() {
+        let offset = reader.position;
+        let id = reader.read_u8()?;
+        let section = Section::from_byte(id)
+            .ok_or_else(|| anyhow!("Unknown section id {} at {:#x}", id, offset))?;
+        let size = reader.read_u32()? as usize;
+        let mut content = Reader {
+            bytes: &bytes[..(reader.position + size).min(bytes.len())],
+            position: reader.position,
+        };
+        out += &format!(
+            "section {:?} ({}) at {:#x}, {} bytes\n",
+            section, id, offset, size
+        );
+        match section {
+            Section::Type => {
+                for index in 0..content.read_u32()? {
+                    let function_type = content.read_function_type()?;
+                    out += &format!("  type {}: (func{})\n", index, signature(&function_type));
+                    types.push(function_type);
+                }
+            }
+            Section::Func => {
+                for index in 0..content.read_u32()? {
+                    let type_index = content.read_u32()?;
+                    out += &format!("  func {}: type {}\n", index, type_index);
+                    function_types.push(type_index);
+                }
+            }
+            Section::Table => {
+                for index in 0..content.read_u32()? {
+                    let offset = content.position;
+                    if content.read_u8()? != FUNCREF {
+                        bail!("Unknown table element type at {:#x}", offset);
+                    }
+                    let limits = content.read_limits()?;
+                    out += &format!("  table {}: funcref {}\n", index, limits);
+                }
+            }
+            Section::Memory => {
+                for index in 0..content.read_u32()? {
+                    let limits = content.read_limits()?;
+                    out += &format!("  memory {}: {} pages\n", index, limits);
+                }
+            }
+            Section::Global => {
+                for index in 0..content.read_u32()? {
+                    let global_type = content.read_valtype()?;
+                    let mutable = content.read_u8()? == 1;
+                    let init = content.read_init_expression()?;
+                    let global_type = if mutable {
+                        format!("(mut {})", valtype(global_type))
+                    } else {
+                        valtype(global_type).to_owned()
+                    };
+                    out += &format!("  global {}: {} {}\n", index, global_type, init);
+                }
+            }
+            Section::Export => {
+                for _ in 0..content.read_u32()? {
+                    let name = content.read_name()?;
+                    let offset = content.position;
+                    let kind = content.read_u8()?;
+                    let kind = ExportType::from_byte(kind)
+                        .ok_or_else(|| anyhow!("Unknown export kind {} at {:#x}", kind, offset))?;
+                    let index = content.read_u32()?;
+                    out += &format!("  export {:?}: {} {}\n", name, export_kind(kind), index);
+                }
+            }
+            Section::Code => {
+                for index in 0..content.read_u32()? {
+                    let size = content.read_u32()? as usize;
+                    let end = content.position + size;
+                    let mut locals = vec![];
+                    for _ in 0..content.read_u32()? {
+                        let offset = content.position;
+                        let count = content.read_u32()?;
+                        if locals.len() + count as usize > MAX_LOCALS {
+                            bail!("Too many locals in function {} at {:#x}", index, offset);
+                        }
+                        let valtype = content.read_valtype()?;
+                        locals.extend(std::iter::repeat_n(valtype, count as usize));
+                    }
+                    let signature = function_types
+                        .get(index as usize)
+                        .and_then(|type_index| types.get(*type_index as usize))
+                        .map(signature)
+                        .unwrap_or_default();
+                    out += &format!("  func {}:{}\n", index, signature);
+                    if !locals.is_empty() {
+                        out += &format!("    (local{})\n", valtypes(&locals));
+                    }
+                    // the closing `end` of the body is at depth 0
+                    let mut depth = 1;
+                    while content.position < end {
+                        let offset = content.position;
+                        let instruction = content.read_instruction()?;
+                        if matches!(instruction.opcode, Opcodes::End | Opcodes::Else) {
+                            depth -= 1;
+                        }
+                        out += &format!(
+                            "    {:#06x}: {}{}\n",
+                            offset,
+                            "  ".repeat(depth.max(1) - 1),
+                            instruction_text(&instruction)
+                        );
+                        if matches!(
+                            instruction.opcode,
+                            Opcodes::Block | Opcodes::Loop | Opcodes::If | Opcodes::Else
+                        ) {
+                            depth += 1;
+                        }
+                    }
+                }
+            }
+            Section::Element => {
+                for index in 0..content.read_u32()? {
+                    let offset = content.position;
+                    if content.read_u32()? != 0 {
+                        bail!("Unsupported element segment kind at {:#x}", offset);
+                    }
+                    let init = content.read_init_expression()?;
+                    let functions = (0..content.read_u32()?)
+                        .map(|_| content.read_u32())
+                        .collect::<Result<Vec<_>>>()?;
+                    out += &format!(
+                        "  elem {}: table 0 {} func{}\n",
+                        index,
+                        init,
+                        function_indices(&functions)
+                    );
+                }
+            }
+            Section::Data => {
+                for index in 0..content.read_u32()? {
+                    let memory = content.read_u32()?;
+                    let init = content.read_init_expression()?;
+                    let length = content.read_u32()? as usize;
+                    let bytes = content.read_bytes(length)?;
+                    out += &format!(
+                        "  data {}: memory {} {} {}\n",
+                        index,
+                        memory,
+                        init,
+                        data_string(bytes)
+                    );
+                }
+            }
+            Section::Custom => {
+                let name = content.read_name()?;
+                out += &format!("  name {:?}\n", name);
+            }
+            _ => out += "  (not decoded)\n",
+        }
+        reader.position += size;
+        if reader.position > bytes.len() {
+            bail!(
+                "Section {:?} at {:#x} ends after the end of the module",
+                section,
+                offset
+            );
+        }
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.position + count > self.bytes.len() {
+            bail!("Unexpected end of module at {:#x}", self.position);
+        }
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let offset = self.position;
+        let mut rest = &self.bytes[self.position..];
+        let value = leb128::read::unsigned(&mut rest)
+            .map_err(|_| anyhow!("Invalid integer at {:#x}", offset))?;
+        self.position = self.bytes.len() - rest.len();
+        u32::try_from(value).map_err(|_| anyhow!("Integer out of range at {:#x}", offset))
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        let offset = self.position;
+        let mut rest = &self.bytes[self.position..];
+        let value = leb128::read::signed(&mut rest)
+            .map_err(|_| anyhow!("Invalid integer at {:#x}", offset))?;
+        self.position = self.bytes.len() - rest.len();
+        i32::try_from(value).map_err(|_| anyhow!("Integer out of range at {:#x}", offset))
+    }
+
+    fn read_f32(&mut self) -> Result<f32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_name(&mut self) -> Result<String> {
+        let offset = self.position;
+        let length = self.read_u32()? as usize;
+        let bytes = self.read_bytes(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| anyhow!("Invalid name at {:#x}", offset))
+    }
+
+    fn read_valtype(&mut self) -> Result<Valtype> {
+        let offset = self.position;
+        let byte = self.read_u8()?;
+        Valtype::from_byte(byte)
+            .ok_or_else(|| anyhow!("Unknown value type {:#04x} at {:#x}", byte, offset))
+    }
+
+    fn read_valtypes(&mut self) -> Result<Vec<Valtype>> {
+        (0..self.read_u32()?).map(|_| self.read_valtype()).collect()
+    }
+
+    fn read_function_type(&mut self) -> Result<FunctionType> {
+        let offset = self.position;
+        if self.read_u8()? != FUNCTION_TYPE {
+            bail!("Expected a function type at {:#x}", offset);
+        }
+        let params = self.read_valtypes()?;
+        let results = self.read_valtypes()?;
+        Ok(FunctionType::new(params, results))
+    }
+
+    // https://webassembly.github.io/spec/core/binary/types.html#limits
+    fn read_limits(&mut self) -> Result<String> {
+        let offset = self.position;
+        Ok(match self.read_u8()? {
+            0x00 => format!("min {}", self.read_u32()?),
+            0x01 => format!("min {}, max {}", self.read_u32()?, self.read_u32()?),
+            flag => bail!("Unknown limits flag {:#04x} at {:#x}", flag, offset),
+        })
+    }
+
+    /// Reads a constant expression, like the initializer of a global, in folded text form.
+    fn read_init_expression(&mut self) -> Result<String> {
+        let mut init = vec![];
+        loop {
+            let instruction = self.read_instruction()?;
+            if instruction.opcode == Opcodes::End {
+                return Ok(init.join(" "));
+            }
+            init.push(format!("({})", instruction_text(&instruction)));
+        }
+    }
+
+    // https://webassembly.github.io/spec/core/binary/instructions.html
+    fn read_instruction(&mut self) -> Result<Instruction> {
+        let offset = self.position;
+        let byte = self.read_u8()?;
+        let opcode = Opcodes::from_byte(byte)
+            .ok_or_else(|| anyhow!("Unknown opcode {:#04x} at {:#x}", byte, offset))?;
+        let immediate = match opcode {
+            Opcodes::Block | Opcodes::Loop | Opcodes::If => {
+                let byte = self.read_u8()?;
+                let blocktype = Blocktype::from_byte(byte)
+                    .ok_or_else(|| anyhow!("Unknown block type {:#04x} at {:#x}", byte, offset))?;
+                Immediate::Block(blocktype)
+            }
+            Opcodes::Br
+            | Opcodes::BrIf
+            | Opcodes::Call
+            | Opcodes::GetLocal
+            | Opcodes::SetLocal
+            | Opcodes::GetGlobal
+            | Opcodes::SetGlobal => Immediate::Index(self.read_u32()?),
+            Opcodes::CallIndirect => {
+                let type_index = self.read_u32()?;
+                if self.read_u32()? != 0 {
+                    bail!("Unsupported table index of call_indirect at {:#x}", offset);
+                }
+                Immediate::Indirect(type_index)
+            }
+            Opcodes::MemorySize | Opcodes::MemoryGrow => {
+                if self.read_u8()? != 0 {
+                    bail!(
+                        "Unsupported memory index of {} at {:#x}",
+                        opcode.name(),
+                        offset
+                    );
+                }
+                Immediate::None
+            }
+            Opcodes::I32Const => Immediate::I32(self.read_i32()?),
+            Opcodes::F32Const => Immediate::F32(self.read_f32()?),
+            Opcodes::I32Load
+            | Opcodes::F32Load
+            | Opcodes::I32Store
+            | Opcodes::F32Store
+            | Opcodes::I32Store8 => {
+                // the alignment is an exponent, it is printed as the power of two
+                let align = self.read_u32()?;
+                if 1u32.checked_shl(align).is_none() {
+                    bail!(
+                        "Invalid alignment 2^{} of {} at {:#x}",
+                        align,
+                        opcode.name(),
+                        offset
+                    );
+                }
+                Immediate::Memory {
+                    align,
+                    offset: self.read_u32()?,
+                }
+            }
+            _ => Immediate::None,
+        };
+        Ok(Instruction { opcode, immediate })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::compile;
+    use crate::disasm::disassemble;
+    use crate::emitter::{
+        Blocktype, ExportType, FunctionType, Immediate, Instruction, ModuleBuilder, Opcodes,
+        Valtype,
+    };
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn disassemble_compiled_program() {
+        let mut scanner = Scanner::new("(def x 2) (< x 3)");
+        let mut parser = Parser::new(&mut scanner);
+        let bytes = compile(parser.parse().unwrap()).unwrap();
+
+        assert_eq!(
+            disassemble(&bytes).unwrap(),
+            "module version 1
+section Type (1) at 0x8, 5 bytes
+  type 0: (func (result f32))
+section Func (3) at 0xf, 2 bytes
+  func 0: type 0
+section Global (6) at 0x13, 9 bytes
+  global 0: (mut f32) (f32.const 0)
+section Export (7) at 0x1e, 7 bytes
+  export \"run\": func 0
+section Code (10) at 0x27, 23 bytes
+  func 0: (result f32)
+    0x002c: f32.const 2
+    0x0031: global.set 0
+    0x0033: global.get 0
+    0x0035: drop
+    0x0036: global.get 0
+    0x0038: f32.const 3
+    0x003d: f32.lt
+    0x003e: f32.convert_i32_s
+    0x003f: end
+"
+        );
+    }
+
+    #[test]
+    fn disassemble_blocks_and_locals() {
+        let mut builder = ModuleBuilder::new();
+        let type_index = builder.add_type(FunctionType::new(vec![Valtype::I32], vec![]));
+        builder.add_function(
+            type_index,
+            vec![Valtype::F32, Valtype::F32],
+            vec![
+                Instruction {
+                    opcode: Opcodes::Loop,
+                    immediate: Immediate::Block(Blocktype::Void),
+                },
+                Instruction::with_index(Opcodes::GetLocal, 0),
+                Instruction::with_index(Opcodes::BrIf, 0),
+                Instruction::new(Opcodes::End),
+            ],
+        );
+
+        let listing = disassemble(&builder.build()).unwrap();
+
+        assert!(
+            listing.ends_with(
+                "  func 0: (param i32)
+    (local f32 f32)
+    0x001a: loop
+    0x001c:   local.get 0
+    0x001e:   br_if 0
+    0x0020: end
+    0x0021: end
+"
+            ),
+            "{}",
+            listing
+        );
+    }
+
+    #[test]
+    fn disassemble_memory_and_data() {
+        let mut builder = ModuleBuilder::new();
+        let memory = builder.add_memory(2);
+        builder.add_data(16, b"\x02ab\"".to_vec());
+        builder.add_export("memory", ExportType::Mem, memory);
+
+        assert_eq!(
+            disassemble(&builder.build()).unwrap(),
+            "module version 1
+section Memory (5) at 0x8, 3 bytes
+  memory 0: min 2 pages
+section Export (7) at 0xd, 10 bytes
+  export \"memory\": memory 0
+section Data (11) at 0x19, 10 bytes
+  data 0: memory 0 (i32.const 16) \"\\02ab\\\"\"
+"
+        );
+    }
+
+    #[test]
+    fn disassemble_table_and_elements() {
+        let mut builder = ModuleBuilder::new();
+        let type_index = builder.add_type(FunctionType::new(vec![], vec![]));
+        let body = vec![
+            Instruction::i32_const(0),
+            Instruction::call_indirect(type_index),
+        ];
+        let function = builder.add_function(type_index, vec![], body);
+        builder.add_table(1);
+        builder.add_element(0, vec![function]);
+
+        assert_eq!(
+            disassemble(&builder.build()).unwrap(),
+            "module version 1
+section Type (1) at 0x8, 4 bytes
+  type 0: (func)
+section Func (3) at 0xe, 2 bytes
+  func 0: type 0
+section Table (4) at 0x12, 4 bytes
+  table 0: funcref min 1
+section Element (9) at 0x18, 7 bytes
+  elem 0: table 0 (i32.const 0) func 0
+section Code (10) at 0x21, 9 bytes
+  func 0:
+    0x0026: i32.const 0
+    0x0028: call_indirect (type 0)
+    0x002b: end
+"
+        );
+    }
+
+    #[test]
+    fn report_invalid_modules() {
+        assert_eq!(
+            disassemble(b"\0asm").unwrap_err().to_string(),
+            "Unexpected end of module at 0x4"
+        );
+        assert_eq!(
+            disassemble(b"ELF\0\x01\0\0\0").unwrap_err().to_string(),
+            "Not a WebAssembly module, the magic header is missing"
+        );
+        assert_eq!(
+            disassemble(b"\0asm\x01\0\0\0\x0a\x05\x01\x03\x00\xff\x0b")
+                .unwrap_err()
+                .to_string(),
+            "Unknown opcode 0xff at 0xd"
+        );
+        assert_eq!(
+            disassemble(b"\0asm\x01\0\0\0\x0a\x0a\x01\x08\x01\xff\xff\xff\xff\x0f\x7d\x0b")
+                .unwrap_err()
+                .to_string(),
+            "Too many locals in function 0 at 0xd"
+        );
+    }
+
+    #[test]
+    fn report_malformed_immediates() {
+        assert_eq!(
+            disassemble(b"\0asm\x01\0\0\0\x0a\x09\x01\x07\x00\x41\x00\x28\x20\x00\x0b")
+                .unwrap_err()
+                .to_string(),
+            "Invalid alignment 2^32 of i32.load at 0xf"
+        );
+        assert_eq!(
+            disassemble(b"\0asm\x01\0\0\0\x0a\x06\x01\x04\x00\x41\x00\x28")
+                .unwrap_err()
+                .to_string(),
+            "Invalid integer at 0x10"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::cst::{self, Element, Node};
+use crate::parser::{ExpressionNode, Program};
+use crate::scanner::Scanner;
+use crate::token::TokenType;
+use std::str::FromStr;
+
+/// Compiler stage printed by `compile --emit`.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum EmitStage {
+    /// One token per line
+    Tokens,
+    /// Concrete syntax tree with comments and whitespace
+    Cst,
+    /// Syntax tree of the program
+    Ast,
+    /// Source of the program after macro expansion
+    Expanded,
+    /// WebAssembly text format
+    Wat,
+    /// Binary WebAssembly module
+    #[default]
+    Wasm,
+}
+
+impl EmitStage {
+    /// Binary stages are written to a file instead of the standard output.
+    pub fn is_binary(&self) -> bool {
+        *self == EmitStage::Wasm
+    }
+}
+
+impl FromStr for EmitStage {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tokens" => Ok(EmitStage::Tokens),
+            "cst" => Ok(EmitStage::Cst),
+            "ast" => Ok(EmitStage::Ast),
+            "expanded" => Ok(EmitStage::Expanded),
+            "wat" => Ok(EmitStage::Wat),
+            "wasm" => Ok(EmitStage::Wasm),
+            _ => Err(format!(
+                "Unknown stage '{}', expected 'tokens', 'cst', 'ast', 'expanded', 'wat' or 'wasm'",
+                value
+            )),
+        }
+    }
+}
+
+/// Lists the tokens of the source as `line:column start..end Kind "text"`, error
+/// tokens included, so the output can be diffed line by line.
+pub fn tokens(source: &str) -> String {
+    let mut scanner = Scanner::new(source);
+    let mut out = String::new();
+    loop {
+        let token = scanner.scan_token();
+        out += &format!(
+            "{}:{} {}..{} {} {:?}\n",
+            token.line, token.column, token.span.start, token.span.end, token.kind, token.src
+        );
+        if token.kind == TokenType::Eof {
+            return out;
+        }
+    }
+}
+
+/// Lists the nodes of the concrete syntax tree indented by depth, with every token
+/// and trivia on its own line.
+pub fn cst(source: &str) -> String {
+    let mut out = String::new();
+    cst_node(&cst::parse(source), 0, &mut out);
+    out
+}
+
+fn cst_node(node: &Node, depth: usize, out: &mut String) {
+    let span = node.span();
+    *out += &format!(
+        "{}{:?} {}..{}\n",
+        "  ".repeat(depth),
+        node.kind,
+        span.start,
+        span.end
+    );
+    for child in &node.children {
+        match child {
+            Element::Node(child) => cst_node(child, depth + 1, out),
+            Element::Token(token) => {
+                let indent = "  ".repeat(depth + 1);
+                for trivia in &token.leading_trivia {
+                    *out += &format!(
+                        "{}{:?} {}..{} {:?}\n",
+                        indent, trivia.kind, trivia.span.start, trivia.span.end, trivia.text
+                    );
+                }
+                *out += &format!(
+                    "{}{} {}..{} {:?}\n",
+                    indent, token.kind, token.span.start, token.span.end, token.text
+                );
+            }
+        }
+    }
+}
+
+/// Pretty-printed syntax tree, one node per line.
+pub fn ast(program: &Program) -> String {
+    format!("{:#?}\n", program)
+}
+
+/// Forms of the program after macro expansion, one per line.
+pub fn expanded(program: &Program) -> String {
+    program
+        .iter()
+        .map(|form| format!("{}\n", ExpressionNode::FunctionCall(form.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emit::{ast, cst, expanded, tokens, EmitStage};
+    use crate::parser::ExpressionNode;
+    use crate::token::Span;
+
+    #[test]
+    fn parse_emit_stage() {
+        assert_eq!("tokens".parse(), Ok(EmitStage::Tokens));
+        assert_eq!("cst".parse(), Ok(EmitStage::Cst));
+        assert_eq!("ast".parse(), Ok(EmitStage::Ast));
+        assert_eq!("expanded".parse(), Ok(EmitStage::Expanded));
+        assert_eq!("wat".parse(), Ok(EmitStage::Wat));
+        assert_eq!("wasm".parse(), Ok(EmitStage::Wasm));
+        assert!("llvm".parse::<EmitStage>().is_err());
+    }
+
+    #[test]
+    fn emit_tokens() {
+        assert_eq!(
+            tokens("(def x\n  \"a\")"),
+            "1:1 0..1 LeftParen \"(\"
+1:2 1..4 Identifier \"def\"
+1:6 5..6 Identifier \"x\"
+2:3 9..12 String \"a\"
+2:6 12..13 RightParen \")\"
+2:7 13..13 Eof \"\"
+"
+        );
+    }
+
+    #[test]
+    fn emit_error_tokens() {
+        assert!(tokens("(@)").contains("1:2 1..2 Error \"Unexpected character.\"\n"));
+    }
+
+    #[test]
+    fn emit_cst() {
+        assert_eq!(
+            cst("(a ; b\n 1)"),
+            "Root 0..10
+  List 0..10
+    LeftParen 0..1 \"(\"
+    Identifier 1..2 \"a\"
+    Whitespace 2..3 \" \"
+    LineComment 3..6 \"; b\"
+    Whitespace 6..8 \"\\n \"
+    IntegerNumber 8..9 \"1\"
+    RightParen 9..10 \")\"
+  Eof 10..10 \"\"
+"
+        );
+    }
+
+    #[test]
+    fn emit_ast() {
+        let program = vec![vec![
+            ExpressionNode::Identifier("+".into()),
+            ExpressionNode::IntegerNumberLiteral(1, Span::default()),
+        ]];
+
+        assert_eq!(
+            ast(&program),
+            "[
+    [
+        Identifier(
+            Symbol {
+                name: \"+\",
+                span: Span {
+                    start: 0,
+                    end: 0,
+                },
+                scope: Unresolved,
+            },
+        ),
+        IntegerNumberLiteral(
+            1,
+            Span {
+                start: 0,
+                end: 0,
+            },
+        ),
+    ],
+]
+"
+        );
+    }
+
+    #[test]
+    fn emit_expanded() {
+        let program = vec![
+            vec![
+                ExpressionNode::Identifier("def".into()),
+                ExpressionNode::Identifier("x".into()),
+                ExpressionNode::FloatNumberLiteral(1.5, Span::default()),
+            ],
+            vec![ExpressionNode::Identifier("x".into())],
+        ];
+
+        assert_eq!(expanded(&program), "(def x 1.5)\n(x)\n");
+    }
+}
@@ -1,7 +1,6 @@
-use leb128;
-
 // https://webassembly.github.io/spec/core/binary/modules.html#sections
-enum Section {
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Section {
     Custom = 0,
     Type = 1,
     Import = 2,
@@ -16,61 +15,421 @@ enum Section {
     Data = 11,
 }
 
+impl Section {
+    pub fn from_byte(byte: u8) -> Option<Section> {
+        [
+            Section::Custom,
+            Section::Type,
+            Section::Import,
+            Section::Func,
+            Section::Table,
+            Section::Memory,
+            Section::Global,
+            Section::Export,
+            Section::Start,
+            Section::Element,
+            Section::Code,
+            Section::Data,
+        ]
+        .into_iter()
+        .find(|section| *section as u8 == byte)
+    }
+}
+
 // https://webassembly.github.io/spec/core/binary/types.html
-enum Valtype {
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Valtype {
     I32 = 0x7f,
     F32 = 0x7d,
 }
 
+impl Valtype {
+    pub fn from_byte(byte: u8) -> Option<Valtype> {
+        [Valtype::I32, Valtype::F32]
+            .into_iter()
+            .find(|valtype| *valtype as u8 == byte)
+    }
+}
+
 // https://webassembly.github.io/spec/core/binary/types.html#binary-blocktype
-enum Blocktype {
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Blocktype {
     Void = 0x40,
+    /// The block leaves an `f32` on the stack
+    F32 = 0x7d,
+}
+
+impl Blocktype {
+    pub fn from_byte(byte: u8) -> Option<Blocktype> {
+        [Blocktype::Void, Blocktype::F32]
+            .into_iter()
+            .find(|blocktype| *blocktype as u8 == byte)
+    }
 }
 
 // https://webassembly.github.io/spec/core/binary/instructions.html
-enum Opcodes {
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Opcodes {
+    Unreachable = 0x00,
     Block = 0x02,
     Loop = 0x03,
+    If = 0x04,
+    Else = 0x05,
     Br = 0x0c,
     BrIf = 0x0d,
     End = 0x0b,
     Call = 0x10,
+    CallIndirect = 0x11,
+    Drop = 0x1a,
     GetLocal = 0x20,
     SetLocal = 0x21,
+    GetGlobal = 0x23,
+    SetGlobal = 0x24,
+    I32Load = 0x28,
+    F32Load = 0x2a,
+    I32Store = 0x36,
+    F32Store = 0x38,
     I32Store8 = 0x3a,
+    MemorySize = 0x3f,
+    MemoryGrow = 0x40,
     I32Const = 0x41,
     F32Const = 0x43,
     I32Eqz = 0x45,
     I32Eq = 0x46,
+    I32GtU = 0x4b,
+    I32LeU = 0x4d,
+    I32GeU = 0x4f,
     F32Eq = 0x5b,
+    F32Ne = 0x5c,
     F32Lt = 0x5d,
     F32Gt = 0x5e,
+    F32Le = 0x5f,
+    F32Ge = 0x60,
+    I32Add = 0x6a,
+    I32Sub = 0x6b,
     I32And = 0x71,
+    I32Shl = 0x74,
+    I32ShrU = 0x76,
+    F32Neg = 0x8c,
     F32Add = 0x92,
     F32Sub = 0x93,
     F32Mul = 0x94,
     F32Div = 0x95,
     I32truncF32s = 0xa8,
+    F32ConvertI32s = 0xb2,
+    I32ReinterpretF32 = 0xbc,
+    F32ReinterpretI32 = 0xbe,
+}
+
+const OPCODES: [Opcodes; 49] = [
+    Opcodes::Unreachable,
+    Opcodes::Block,
+    Opcodes::Loop,
+    Opcodes::If,
+    Opcodes::Else,
+    Opcodes::Br,
+    Opcodes::BrIf,
+    Opcodes::End,
+    Opcodes::Call,
+    Opcodes::CallIndirect,
+    Opcodes::Drop,
+    Opcodes::GetLocal,
+    Opcodes::SetLocal,
+    Opcodes::GetGlobal,
+    Opcodes::SetGlobal,
+    Opcodes::I32Load,
+    Opcodes::F32Load,
+    Opcodes::I32Store,
+    Opcodes::F32Store,
+    Opcodes::I32Store8,
+    Opcodes::MemorySize,
+    Opcodes::MemoryGrow,
+    Opcodes::I32Const,
+    Opcodes::F32Const,
+    Opcodes::I32Eqz,
+    Opcodes::I32Eq,
+    Opcodes::I32GtU,
+    Opcodes::I32LeU,
+    Opcodes::I32GeU,
+    Opcodes::F32Eq,
+    Opcodes::F32Ne,
+    Opcodes::F32Lt,
+    Opcodes::F32Gt,
+    Opcodes::F32Le,
+    Opcodes::F32Ge,
+    Opcodes::I32Add,
+    Opcodes::I32Sub,
+    Opcodes::I32And,
+    Opcodes::I32Shl,
+    Opcodes::I32ShrU,
+    Opcodes::F32Neg,
+    Opcodes::F32Add,
+    Opcodes::F32Sub,
+    Opcodes::F32Mul,
+    Opcodes::F32Div,
+    Opcodes::I32truncF32s,
+    Opcodes::F32ConvertI32s,
+    Opcodes::I32ReinterpretF32,
+    Opcodes::F32ReinterpretI32,
+];
+
+impl Opcodes {
+    pub fn from_byte(byte: u8) -> Option<Opcodes> {
+        OPCODES
+            .iter()
+            .find(|opcode| **opcode as u8 == byte)
+            .copied()
+    }
+
+    // https://webassembly.github.io/spec/core/text/instructions.html
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcodes::Unreachable => "unreachable",
+            Opcodes::Block => "block",
+            Opcodes::Loop => "loop",
+            Opcodes::If => "if",
+            Opcodes::Else => "else",
+            Opcodes::Br => "br",
+            Opcodes::BrIf => "br_if",
+            Opcodes::End => "end",
+            Opcodes::Call => "call",
+            Opcodes::CallIndirect => "call_indirect",
+            Opcodes::Drop => "drop",
+            Opcodes::GetLocal => "local.get",
+            Opcodes::SetLocal => "local.set",
+            Opcodes::GetGlobal => "global.get",
+            Opcodes::SetGlobal => "global.set",
+            Opcodes::I32Load => "i32.load",
+            Opcodes::F32Load => "f32.load",
+            Opcodes::I32Store => "i32.store",
+            Opcodes::F32Store => "f32.store",
+            Opcodes::I32Store8 => "i32.store8",
+            Opcodes::MemorySize => "memory.size",
+            Opcodes::MemoryGrow => "memory.grow",
+            Opcodes::I32Const => "i32.const",
+            Opcodes::F32Const => "f32.const",
+            Opcodes::I32Eqz => "i32.eqz",
+            Opcodes::I32Eq => "i32.eq",
+            Opcodes::I32GtU => "i32.gt_u",
+            Opcodes::I32LeU => "i32.le_u",
+            Opcodes::I32GeU => "i32.ge_u",
+            Opcodes::F32Eq => "f32.eq",
+            Opcodes::F32Ne => "f32.ne",
+            Opcodes::F32Lt => "f32.lt",
+            Opcodes::F32Gt => "f32.gt",
+            Opcodes::F32Le => "f32.le",
+            Opcodes::F32Ge => "f32.ge",
+            Opcodes::I32Add => "i32.add",
+            Opcodes::I32Sub => "i32.sub",
+            Opcodes::I32And => "i32.and",
+            Opcodes::I32Shl => "i32.shl",
+            Opcodes::I32ShrU => "i32.shr_u",
+            Opcodes::F32Neg => "f32.neg",
+            Opcodes::F32Add => "f32.add",
+            Opcodes::F32Sub => "f32.sub",
+            Opcodes::F32Mul => "f32.mul",
+            Opcodes::F32Div => "f32.div",
+            Opcodes::I32truncF32s => "i32.trunc_f32_s",
+            Opcodes::F32ConvertI32s => "f32.convert_i32_s",
+            Opcodes::I32ReinterpretF32 => "i32.reinterpret_f32",
+            Opcodes::F32ReinterpretI32 => "f32.reinterpret_i32",
+        }
+    }
+
+    /// Number of values popped from and pushed to the operand stack, `None` for
+    /// control instructions and calls whose effect depends on their target.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        match self {
+            Opcodes::Unreachable
+            | Opcodes::Block
+            | Opcodes::Loop
+            | Opcodes::If
+            | Opcodes::Else
+            | Opcodes::Br
+            | Opcodes::End
+            | Opcodes::Call
+            | Opcodes::CallIndirect => None,
+            Opcodes::GetLocal
+            | Opcodes::GetGlobal
+            | Opcodes::MemorySize
+            | Opcodes::I32Const
+            | Opcodes::F32Const => Some((0, 1)),
+            Opcodes::BrIf | Opcodes::Drop | Opcodes::SetLocal | Opcodes::SetGlobal => Some((1, 0)),
+            Opcodes::I32Store | Opcodes::F32Store | Opcodes::I32Store8 => Some((2, 0)),
+            Opcodes::I32Load
+            | Opcodes::F32Load
+            | Opcodes::MemoryGrow
+            | Opcodes::I32Eqz
+            | Opcodes::F32Neg
+            | Opcodes::I32truncF32s
+            | Opcodes::F32ConvertI32s
+            | Opcodes::I32ReinterpretF32
+            | Opcodes::F32ReinterpretI32 => Some((1, 1)),
+            Opcodes::I32Eq
+            | Opcodes::I32GtU
+            | Opcodes::I32LeU
+            | Opcodes::I32GeU
+            | Opcodes::F32Eq
+            | Opcodes::F32Ne
+            | Opcodes::F32Lt
+            | Opcodes::F32Gt
+            | Opcodes::F32Le
+            | Opcodes::F32Ge
+            | Opcodes::I32Add
+            | Opcodes::I32Sub
+            | Opcodes::I32And
+            | Opcodes::I32Shl
+            | Opcodes::I32ShrU
+            | Opcodes::F32Add
+            | Opcodes::F32Sub
+            | Opcodes::F32Mul
+            | Opcodes::F32Div => Some((2, 1)),
+        }
+    }
+}
+
+/// Immediate argument of an instruction, encoded after the opcode
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Immediate {
+    None,
+    /// Local, global, function or label index
+    Index(u32),
+    I32(i32),
+    F32(f32),
+    Block(Blocktype),
+    /// Type index of `call_indirect`, which calls through table 0
+    Indirect(u32),
+    Memory {
+        align: u32,
+        offset: u32,
+    },
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Instruction {
+    pub opcode: Opcodes,
+    pub immediate: Immediate,
+}
+
+impl Instruction {
+    pub fn new(opcode: Opcodes) -> Self {
+        Instruction {
+            opcode,
+            immediate: Immediate::None,
+        }
+    }
+
+    pub fn with_index(opcode: Opcodes, index: u32) -> Self {
+        Instruction {
+            opcode,
+            immediate: Immediate::Index(index),
+        }
+    }
+
+    pub fn f32_const(value: f32) -> Self {
+        Instruction {
+            opcode: Opcodes::F32Const,
+            immediate: Immediate::F32(value),
+        }
+    }
+
+    pub fn i32_const(value: i32) -> Self {
+        Instruction {
+            opcode: Opcodes::I32Const,
+            immediate: Immediate::I32(value),
+        }
+    }
+
+    /// `block`, `loop` or `if` with the type of its result.
+    pub fn block(opcode: Opcodes, blocktype: Blocktype) -> Self {
+        Instruction {
+            opcode,
+            immediate: Immediate::Block(blocktype),
+        }
+    }
+
+    /// Calls the function of table 0 at the index on the top of the stack, its type
+    /// must be the given one.
+    pub fn call_indirect(type_index: u32) -> Self {
+        Instruction {
+            opcode: Opcodes::CallIndirect,
+            immediate: Immediate::Indirect(type_index),
+        }
+    }
+
+    /// Load or store with the alignment as a power of two and a constant address offset.
+    pub fn memory(opcode: Opcodes, align: u32, offset: u32) -> Self {
+        Instruction {
+            opcode,
+            immediate: Immediate::Memory { align, offset },
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let immediate = match self.immediate {
+            // the index of memory 0, the only one
+            Immediate::None if matches!(self.opcode, Opcodes::MemorySize | Opcodes::MemoryGrow) => {
+                vec![0x00]
+            }
+            Immediate::None => vec![],
+            Immediate::Index(index) => unsigned_led128(index as u64),
+            Immediate::I32(value) => signed_led128(value as i64),
+            Immediate::F32(value) => encode_f32(value),
+            Immediate::Block(blocktype) => vec![blocktype as u8],
+            Immediate::Indirect(type_index) => {
+                [unsigned_led128(type_index as u64), vec![0x00]].concat()
+            }
+            Immediate::Memory { align, offset } => [
+                unsigned_led128(align as u64),
+                unsigned_led128(offset as u64),
+            ]
+            .concat(),
+        };
+        [vec![self.opcode as u8], immediate].concat()
+    }
+}
+
+fn encode_instructions(instructions: &[Instruction]) -> Vec<u8> {
+    instructions
+        .iter()
+        .flat_map(Instruction::encode)
+        .chain([Opcodes::End as u8])
+        .collect()
 }
 
 // http://webassembly.github.io/spec/core/binary/modules.html#export-section
-enum ExportType {
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ExportType {
     Func = 0x00,
     Table = 0x01,
     Mem = 0x02,
     Global = 0x03,
 }
 
+impl ExportType {
+    pub fn from_byte(byte: u8) -> Option<ExportType> {
+        [
+            ExportType::Func,
+            ExportType::Table,
+            ExportType::Mem,
+            ExportType::Global,
+        ]
+        .into_iter()
+        .find(|kind| *kind as u8 == byte)
+    }
+}
+
 // http://webassembly.github.io/spec/core/binary/types.html#function-types
-const FUNCTION_TYPE: usize = 0x60;
+pub const FUNCTION_TYPE: u8 = 0x60;
 
-const EMPTY_ARRAY: usize = 0x0;
+// https://webassembly.github.io/spec/core/binary/types.html#reference-types
+pub const FUNCREF: u8 = 0x70;
 
 // https://webassembly.github.io/spec/core/binary/modules.html#binary-module
-const MAGIC_MODULE_HEADER: [usize; 4] = [0x00, 0x61, 0x73, 0x6d];
-const MODULE_VERSION: [usize; 4] = [0x01, 0x00, 0x00, 0x00];
+pub const MAGIC_MODULE_HEADER: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+pub const MODULE_VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
 
-fn unsigned_led128(value: u64) -> Vec<u8> {
+pub fn unsigned_led128(value: u64) -> Vec<u8> {
     let mut result = vec![];
     leb128::write::unsigned(&mut result, value).expect("Should write number");
     result
@@ -78,8 +437,553 @@ fn unsigned_led128(value: u64) -> Vec<u8> {
 
 // https://webassembly.github.io/spec/core/binary/conventions.html#binary-vec
 // Vectors are encoded with their length followed by their element sequence
-fn encode_vector(data: Vec<u8>) -> Vec<u8> {
+pub fn encode_vector(data: Vec<u8>) -> Vec<u8> {
     [unsigned_led128(data.len() as u64), data].concat()
 }
 
-// fn create_section<T>(section_type: Section, data: T) -> Vec<u8> {}
+pub fn signed_led128(value: i64) -> Vec<u8> {
+    let mut result = vec![];
+    leb128::write::signed(&mut result, value).expect("Should write number");
+    result
+}
+
+// https://webassembly.github.io/spec/core/binary/values.html#floating-point
+pub fn encode_f32(value: f32) -> Vec<u8> {
+    value.to_le_bytes().to_vec()
+}
+
+// Vector of encoded items, prefixed by the number of items instead of the byte length
+fn encode_items(items: Vec<Vec<u8>>) -> Vec<u8> {
+    [unsigned_led128(items.len() as u64), items.concat()].concat()
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    encode_vector(name.as_bytes().to_vec())
+}
+
+// https://webassembly.github.io/spec/core/binary/modules.html#sections
+// Sections are encoded with their id followed by the byte size of their content
+pub fn create_section(section_type: Section, data: Vec<u8>) -> Vec<u8> {
+    [vec![section_type as u8], encode_vector(data)].concat()
+}
+
+// https://webassembly.github.io/spec/core/binary/types.html#function-types
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FunctionType {
+    pub params: Vec<Valtype>,
+    pub results: Vec<Valtype>,
+}
+
+impl FunctionType {
+    pub fn new(params: Vec<Valtype>, results: Vec<Valtype>) -> Self {
+        FunctionType { params, results }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let valtypes = |types: &[Valtype]| types.iter().map(|t| *t as u8).collect::<Vec<u8>>();
+        [
+            vec![FUNCTION_TYPE],
+            encode_vector(valtypes(&self.params)),
+            encode_vector(valtypes(&self.results)),
+        ]
+        .concat()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub type_index: u32,
+    /// Locals declared after the parameters
+    pub locals: Vec<Valtype>,
+    /// Instructions of the function, without the closing `end`
+    pub body: Vec<Instruction>,
+}
+
+impl Function {
+    // https://webassembly.github.io/spec/core/binary/modules.html#code-section
+    fn encode_code(&self) -> Vec<u8> {
+        // consecutive locals with the same type are declared together
+        let mut groups: Vec<(u32, Valtype)> = vec![];
+        for local in &self.locals {
+            match groups.last_mut() {
+                Some((count, valtype)) if valtype == local => *count += 1,
+                _ => groups.push((1, *local)),
+            }
+        }
+        let locals = groups
+            .into_iter()
+            .map(|(count, valtype)| [unsigned_led128(count as u64), vec![valtype as u8]].concat())
+            .collect();
+        encode_vector([encode_items(locals), encode_instructions(&self.body)].concat())
+    }
+}
+
+// https://webassembly.github.io/spec/core/binary/modules.html#global-section
+#[derive(Debug, Clone)]
+pub struct Global {
+    pub valtype: Valtype,
+    pub mutable: bool,
+    /// Constant initializer expression, without the closing `end`
+    pub init: Vec<Instruction>,
+}
+
+impl Global {
+    fn encode(&self) -> Vec<u8> {
+        [
+            vec![self.valtype as u8, self.mutable as u8],
+            encode_instructions(&self.init),
+        ]
+        .concat()
+    }
+}
+
+// https://webassembly.github.io/spec/core/binary/modules.html#data-section
+#[derive(Debug, Clone)]
+pub struct Data {
+    /// Address of the first byte in the memory
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Data {
+    fn encode(&self) -> Vec<u8> {
+        [
+            // memory 0
+            vec![0x00],
+            encode_instructions(&[Instruction::i32_const(self.offset as i32)]),
+            encode_vector(self.bytes.clone()),
+        ]
+        .concat()
+    }
+}
+
+// https://webassembly.github.io/spec/core/binary/modules.html#element-section
+/// Functions copied into table 0 at the given index when the module is instantiated.
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub offset: u32,
+    pub functions: Vec<u32>,
+}
+
+impl Element {
+    fn encode(&self) -> Vec<u8> {
+        let functions = self
+            .functions
+            .iter()
+            .map(|function| unsigned_led128(*function as u64))
+            .collect();
+        [
+            // active segment of table 0 with function indices
+            vec![0x00],
+            encode_instructions(&[Instruction::i32_const(self.offset as i32)]),
+            encode_items(functions),
+        ]
+        .concat()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExportType,
+    pub index: u32,
+}
+
+/// Assembles a binary WebAssembly module from its sections.
+#[derive(Debug, Default)]
+pub struct ModuleBuilder {
+    types: Vec<FunctionType>,
+    functions: Vec<Function>,
+    /// Minimum size of the function table, a module has at most one table
+    table: Option<u32>,
+    /// Minimum size of the memory in pages, a module has at most one memory
+    memory: Option<u32>,
+    globals: Vec<Global>,
+    exports: Vec<Export>,
+    elements: Vec<Element>,
+    data: Vec<Data>,
+}
+
+impl ModuleBuilder {
+    pub fn new() -> Self {
+        ModuleBuilder::default()
+    }
+
+    /// Adds a function type and returns its index. Equal types are stored only once.
+    pub fn add_type(&mut self, function_type: FunctionType) -> u32 {
+        if let Some(index) = self.types.iter().position(|t| *t == function_type) {
+            return index as u32;
+        }
+        self.types.push(function_type);
+        (self.types.len() - 1) as u32
+    }
+
+    /// Adds a function and returns its index.
+    pub fn add_function(
+        &mut self,
+        type_index: u32,
+        locals: Vec<Valtype>,
+        body: Vec<Instruction>,
+    ) -> u32 {
+        self.functions.push(Function {
+            type_index,
+            locals,
+            body,
+        });
+        (self.functions.len() - 1) as u32
+    }
+
+    /// Adds a global variable and returns its index.
+    pub fn add_global(&mut self, valtype: Valtype, mutable: bool, init: Vec<Instruction>) -> u32 {
+        self.globals.push(Global {
+            valtype,
+            mutable,
+            init,
+        });
+        (self.globals.len() - 1) as u32
+    }
+
+    /// Replaces the initializer of a global, for values known only at the end of the compilation.
+    pub fn set_global_init(&mut self, index: u32, init: Vec<Instruction>) {
+        self.globals[index as usize].init = init;
+    }
+
+    /// Adds the function table of the module with its minimum size and returns its index.
+    pub fn add_table(&mut self, min_size: u32) -> u32 {
+        self.table = Some(min_size);
+        0
+    }
+
+    /// Adds functions copied into the table at the given index when the module is instantiated.
+    pub fn add_element(&mut self, offset: u32, functions: Vec<u32>) {
+        self.elements.push(Element { offset, functions });
+    }
+
+    /// Adds the memory of the module with its minimum size in 64 KiB pages and returns its index.
+    pub fn add_memory(&mut self, min_pages: u32) -> u32 {
+        self.memory = Some(min_pages);
+        0
+    }
+
+    /// Adds bytes copied into the memory at the given address when the module is instantiated.
+    pub fn add_data(&mut self, offset: u32, bytes: Vec<u8>) {
+        self.data.push(Data { offset, bytes });
+    }
+
+    pub fn add_export(&mut self, name: &str, kind: ExportType, index: u32) {
+        self.exports.push(Export {
+            name: name.to_owned(),
+            kind,
+            index,
+        });
+    }
+
+    pub fn types(&self) -> &[FunctionType] {
+        &self.types
+    }
+
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    pub fn table(&self) -> Option<u32> {
+        self.table
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    pub fn memory(&self) -> Option<u32> {
+        self.memory
+    }
+
+    pub fn globals(&self) -> &[Global] {
+        &self.globals
+    }
+
+    pub fn exports(&self) -> &[Export] {
+        &self.exports
+    }
+
+    pub fn data(&self) -> &[Data] {
+        &self.data
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut module = [MAGIC_MODULE_HEADER, MODULE_VERSION].concat();
+
+        if !self.types.is_empty() {
+            let types = self.types.iter().map(FunctionType::encode).collect();
+            module.extend(create_section(Section::Type, encode_items(types)));
+        }
+
+        if !self.functions.is_empty() {
+            let functions = self
+                .functions
+                .iter()
+                .map(|function| unsigned_led128(function.type_index as u64))
+                .collect();
+            module.extend(create_section(Section::Func, encode_items(functions)));
+        }
+
+        if let Some(min_size) = self.table {
+            // https://webassembly.github.io/spec/core/binary/types.html#table-types
+            let table = [vec![FUNCREF, 0x00], unsigned_led128(min_size as u64)].concat();
+            module.extend(create_section(Section::Table, encode_items(vec![table])));
+        }
+
+        if let Some(min_pages) = self.memory {
+            // https://webassembly.github.io/spec/core/binary/types.html#limits
+            let limits = [vec![0x00], unsigned_led128(min_pages as u64)].concat();
+            module.extend(create_section(Section::Memory, encode_items(vec![limits])));
+        }
+
+        if !self.globals.is_empty() {
+            let globals = self.globals.iter().map(Global::encode).collect();
+            module.extend(create_section(Section::Global, encode_items(globals)));
+        }
+
+        if !self.exports.is_empty() {
+            let exports = self
+                .exports
+                .iter()
+                .map(|export| {
+                    [
+                        encode_name(&export.name),
+                        vec![export.kind as u8],
+                        unsigned_led128(export.index as u64),
+                    ]
+                    .concat()
+                })
+                .collect();
+            module.extend(create_section(Section::Export, encode_items(exports)));
+        }
+
+        if !self.elements.is_empty() {
+            let elements = self.elements.iter().map(Element::encode).collect();
+            module.extend(create_section(Section::Element, encode_items(elements)));
+        }
+
+        if !self.functions.is_empty() {
+            let codes = self.functions.iter().map(Function::encode_code).collect();
+            module.extend(create_section(Section::Code, encode_items(codes)));
+        }
+
+        if !self.data.is_empty() {
+            let data = self.data.iter().map(Data::encode).collect();
+            module.extend(create_section(Section::Data, encode_items(data)));
+        }
+
+        module
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emitter::{
+        ExportType, FunctionType, Immediate, Instruction, ModuleBuilder, Opcodes, Valtype,
+    };
+    use wasmtime::{Engine, Instance, Module, Store};
+
+    fn instantiate(bytes: &[u8]) -> (Store<()>, Instance) {
+        let engine = Engine::default();
+        let module = Module::new(&engine, bytes).expect("Valid wasm module");
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).expect("Module instance");
+        (store, instance)
+    }
+
+    #[test]
+    fn build_empty_module() {
+        let bytes = ModuleBuilder::new().build();
+
+        assert_eq!(bytes, vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+        instantiate(&bytes);
+    }
+
+    #[test]
+    fn build_exported_function() {
+        let mut builder = ModuleBuilder::new();
+        let type_index = builder.add_type(FunctionType::new(vec![], vec![Valtype::I32]));
+        let body = vec![Instruction::i32_const(-42)];
+        let function = builder.add_function(type_index, vec![], body);
+        builder.add_export("run", ExportType::Func, function);
+
+        let (mut store, instance) = instantiate(&builder.build());
+        let run = instance
+            .get_typed_func::<(), i32, _>(&mut store, "run")
+            .unwrap();
+
+        assert_eq!(run.call(&mut store, ()).unwrap(), -42);
+    }
+
+    #[test]
+    fn build_function_with_params_and_locals() {
+        let mut builder = ModuleBuilder::new();
+        let type_index = builder.add_type(FunctionType::new(
+            vec![Valtype::F32, Valtype::F32],
+            vec![Valtype::F32],
+        ));
+        // local 2 = param 0 + param 1; local 2 * param 1
+        let body = vec![
+            Instruction::with_index(Opcodes::GetLocal, 0),
+            Instruction::with_index(Opcodes::GetLocal, 1),
+            Instruction::new(Opcodes::F32Add),
+            Instruction::with_index(Opcodes::SetLocal, 2),
+            Instruction::with_index(Opcodes::GetLocal, 2),
+            Instruction::with_index(Opcodes::GetLocal, 1),
+            Instruction::new(Opcodes::F32Mul),
+        ];
+        let function = builder.add_function(type_index, vec![Valtype::F32], body);
+        builder.add_export("calc", ExportType::Func, function);
+
+        let (mut store, instance) = instantiate(&builder.build());
+        let calc = instance
+            .get_typed_func::<(f32, f32), f32, _>(&mut store, "calc")
+            .unwrap();
+
+        assert_eq!(calc.call(&mut store, (1.5, 2.0)).unwrap(), 7.0);
+    }
+
+    #[test]
+    fn deduplicate_function_types() {
+        let mut builder = ModuleBuilder::new();
+        let first = builder.add_type(FunctionType::new(vec![Valtype::F32], vec![]));
+        let second = builder.add_type(FunctionType::new(vec![], vec![Valtype::F32]));
+        let third = builder.add_type(FunctionType::new(vec![Valtype::F32], vec![]));
+
+        assert_eq!((first, second, third), (0, 1, 0));
+        assert_eq!(builder.types().len(), 2);
+    }
+
+    #[test]
+    fn call_between_functions() {
+        let mut builder = ModuleBuilder::new();
+        let type_index = builder.add_type(FunctionType::new(vec![], vec![Valtype::I32]));
+        let seven = builder.add_function(type_index, vec![], vec![Instruction::i32_const(7)]);
+        let call = Instruction::with_index(Opcodes::Call, seven);
+        let run = builder.add_function(type_index, vec![], vec![call]);
+        builder.add_export("run", ExportType::Func, run);
+
+        let (mut store, instance) = instantiate(&builder.build());
+        let run = instance
+            .get_typed_func::<(), i32, _>(&mut store, "run")
+            .unwrap();
+
+        assert_eq!(run.call(&mut store, ()).unwrap(), 7);
+    }
+
+    #[test]
+    fn build_mutable_global() {
+        let mut builder = ModuleBuilder::new();
+        let counter = builder.add_global(Valtype::F32, true, vec![Instruction::f32_const(1.5)]);
+        let type_index = builder.add_type(FunctionType::new(vec![], vec![Valtype::F32]));
+        let body = vec![
+            Instruction::with_index(Opcodes::GetGlobal, counter),
+            Instruction::with_index(Opcodes::GetGlobal, counter),
+            Instruction::new(Opcodes::F32Add),
+            Instruction::with_index(Opcodes::SetGlobal, counter),
+            Instruction::with_index(Opcodes::GetGlobal, counter),
+        ];
+        let function = builder.add_function(type_index, vec![], body);
+        builder.add_export("run", ExportType::Func, function);
+
+        let (mut store, instance) = instantiate(&builder.build());
+        let run = instance
+            .get_typed_func::<(), f32, _>(&mut store, "run")
+            .unwrap();
+
+        assert_eq!(run.call(&mut store, ()).unwrap(), 3.0);
+        assert_eq!(run.call(&mut store, ()).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn build_memory_with_data() {
+        let mut builder = ModuleBuilder::new();
+        let memory = builder.add_memory(1);
+        builder.add_data(8, vec![1, 2, 3]);
+        builder.add_export("memory", ExportType::Mem, memory);
+        let type_index = builder.add_type(FunctionType::new(vec![], vec![Valtype::I32]));
+        // store 7 after the data, then load the first 4 bytes of the data
+        let body = vec![
+            Instruction::i32_const(8),
+            Instruction::i32_const(7),
+            Instruction::memory(Opcodes::I32Store8, 0, 3),
+            Instruction::i32_const(4),
+            Instruction::memory(Opcodes::I32Load, 2, 4),
+        ];
+        let function = builder.add_function(type_index, vec![], body);
+        builder.add_export("run", ExportType::Func, function);
+
+        let (mut store, instance) = instantiate(&builder.build());
+        let run = instance
+            .get_typed_func::<(), i32, _>(&mut store, "run")
+            .unwrap();
+
+        assert_eq!(run.call(&mut store, ()).unwrap(), 0x07030201);
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        assert_eq!(memory.data_size(&store), 65536);
+    }
+
+    #[test]
+    fn build_table_with_elements() {
+        let mut builder = ModuleBuilder::new();
+        let constant = builder.add_type(FunctionType::new(vec![], vec![Valtype::I32]));
+        let one = builder.add_function(constant, vec![], vec![Instruction::i32_const(1)]);
+        let two = builder.add_function(constant, vec![], vec![Instruction::i32_const(2)]);
+        builder.add_table(3);
+        builder.add_element(1, vec![one, two]);
+        let select = builder.add_type(FunctionType::new(vec![Valtype::I32], vec![Valtype::I32]));
+        let body = vec![
+            Instruction::with_index(Opcodes::GetLocal, 0),
+            Instruction::call_indirect(constant),
+        ];
+        let function = builder.add_function(select, vec![], body);
+        builder.add_export("run", ExportType::Func, function);
+
+        let (mut store, instance) = instantiate(&builder.build());
+        let run = instance
+            .get_typed_func::<i32, i32, _>(&mut store, "run")
+            .unwrap();
+
+        assert_eq!(run.call(&mut store, 1).unwrap(), 1);
+        assert_eq!(run.call(&mut store, 2).unwrap(), 2);
+        // the first entry of the table is empty
+        assert!(run.call(&mut store, 0).is_err());
+    }
+
+    #[test]
+    fn encode_instructions() {
+        assert_eq!(Instruction::new(Opcodes::Drop).encode(), vec![0x1a]);
+        assert_eq!(
+            Instruction::with_index(Opcodes::GetGlobal, 200).encode(),
+            vec![0x23, 0xc8, 0x01]
+        );
+        assert_eq!(Instruction::i32_const(-1).encode(), vec![0x41, 0x7f]);
+        assert_eq!(
+            Instruction::f32_const(1.0).encode(),
+            vec![0x43, 0x00, 0x00, 0x80, 0x3f]
+        );
+        let store = Instruction {
+            opcode: Opcodes::I32Store8,
+            immediate: Immediate::Memory {
+                align: 0,
+                offset: 4,
+            },
+        };
+        assert_eq!(store.encode(), vec![0x3a, 0x00, 0x04]);
+        assert_eq!(
+            Instruction::call_indirect(3).encode(),
+            vec![0x11, 0x03, 0x00]
+        );
+    }
+
+    #[test]
+    fn opcode_names() {
+        assert_eq!(Opcodes::from_byte(0x23), Some(Opcodes::GetGlobal));
+        assert_eq!(Opcodes::from_byte(0xff), None);
+        assert_eq!(Opcodes::GetGlobal.name(), "global.get");
+        assert_eq!(Opcodes::F32ConvertI32s.name(), "f32.convert_i32_s");
+    }
+}
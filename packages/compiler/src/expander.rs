@@ -0,0 +1,658 @@
+use crate::analyzer::BUILTINS;
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::{first_span, ExpressionList, ExpressionNode, Program, Symbol};
+use crate::token::Span;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// Nesting limit of macros expanding to macro calls, reached by macros calling themselves.
+const MAX_EXPANSION_DEPTH: usize = 256;
+
+/// Expands the macro calls of a parsed program, between parsing and code generation.
+/// A `(defmacro name "doc" [params] body)` form defines a macro for the rest of the
+/// program and is removed from it.
+///
+/// A macro receives its arguments as unevaluated forms and its body returns the form
+/// replacing the call. The body is evaluated at compile time with `quote`, `if`, `do`,
+/// `let` and the functions of [`MACRO_FUNCTIONS`] working on forms. Inside a syntax-quoted
+/// template `~x` inserts a value, `~@xs` splices a list, and every `name#` symbol is
+/// replaced by the same generated symbol, so the names of the template cannot capture
+/// the names of the caller.
+///
+/// Errors are located at the `defmacro` form or at the macro call in the program,
+/// even when they happen in the expansion of a macro called by another macro. The
+/// symbols and literals a macro body produces are located at the call too, the
+/// arguments keep their own locations.
+pub fn expand(program: &Program) -> Result<Program, Diagnostic> {
+    let mut expander = Expander::default();
+    let mut expanded = vec![];
+    for form in program {
+        let span = first_span(form).unwrap_or_default();
+        if is_macro_definition(form) {
+            expander.define(&form[1..]).map_err(|error| {
+                Diagnostic::error(ErrorCode::InvalidMacro, span, format!("{:#}", error))
+            })?;
+            continue;
+        }
+        match expander.expand(&ExpressionNode::FunctionCall(form.clone()), None)? {
+            ExpressionNode::FunctionCall(list) => expanded.push(list),
+            other => {
+                return Err(Diagnostic::error(
+                    ErrorCode::MacroExpansion,
+                    span,
+                    format!("Top-level forms must expand to lists, but get {}", other),
+                ))
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// Call of a macro in the program, whose expansion is expanded further.
+#[derive(Copy, Clone)]
+struct CallSite {
+    span: Span,
+    depth: usize,
+}
+
+/// Functions available in macro bodies.
+pub const MACRO_FUNCTIONS: &[&str] = &[
+    "list", "vector", "cons", "concat", "first", "rest", "nth", "count", "empty?", "=", "gensym",
+];
+
+pub fn is_macro_definition(form: &[ExpressionNode]) -> bool {
+    matches!(form.first(), Some(ExpressionNode::Identifier(symbol)) if symbol.name == "defmacro")
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    /// Parameter after `&`, bound to the list of the remaining arguments
+    rest: Option<String>,
+    body: ExpressionList,
+}
+
+type Environment = HashMap<String, ExpressionNode>;
+
+#[derive(Default)]
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// Number of the next generated symbol
+    next_symbol: usize,
+    /// Location of the macro call being evaluated, given to the symbols of its body
+    span: Span,
+}
+
+impl Expander {
+    /// `(defmacro name [params] body)`, with an optional docstring after the name.
+    fn define(&mut self, args: &[ExpressionNode]) -> Result<()> {
+        let (name, rest) = match args {
+            [ExpressionNode::Identifier(Symbol { name, .. }), ExpressionNode::StringLiteral(..), rest @ ..]
+            | [ExpressionNode::Identifier(Symbol { name, .. }), rest @ ..] => (name, rest),
+            _ => bail!("'defmacro' expects a name as first argument"),
+        };
+        if BUILTINS.contains(&name.as_str()) || name == "defmacro" {
+            bail!("Cannot define macro '{}', it is a built-in", name);
+        }
+        let (params, body) = match rest {
+            [ExpressionNode::Array(params), body @ ..] if !body.is_empty() => (params, body),
+            [ExpressionNode::Array(_)] => bail!("Macro '{}' has no body", name),
+            _ => bail!("'defmacro' expects a parameter vector after the name"),
+        };
+        let mut names = vec![];
+        let mut rest = None;
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match (param, params.as_slice()) {
+                (
+                    ExpressionNode::Identifier(and),
+                    [ExpressionNode::Identifier(Symbol { name, .. })],
+                ) if and.name == "&" => {
+                    rest = Some(name.clone());
+                    break;
+                }
+                (ExpressionNode::Identifier(Symbol { name, .. }), _) if name != "&" => {
+                    names.push(name.clone())
+                }
+                _ => bail!(
+                    "Invalid parameter {} of macro '{}', expected names and an optional `& rest`",
+                    param,
+                    name
+                ),
+            }
+        }
+        self.macros.insert(
+            name.clone(),
+            Macro {
+                params: names,
+                rest,
+                body: body.to_vec(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Expands the macro calls of the node. `site` is the call in the program whose
+    /// expansion contains the node, `None` for the forms of the program.
+    fn expand(
+        &mut self,
+        node: &ExpressionNode,
+        site: Option<CallSite>,
+    ) -> Result<ExpressionNode, Diagnostic> {
+        Ok(match node {
+            ExpressionNode::FunctionCall(list) => {
+                if let Some(ExpressionNode::Identifier(symbol)) = list.first() {
+                    let name = &symbol.name;
+                    let span = site.map_or(symbol.span, |site| site.span);
+                    if name == "defmacro" {
+                        return Err(Diagnostic::error(
+                            ErrorCode::InvalidMacro,
+                            span,
+                            "'defmacro' is only allowed at the top level",
+                        ));
+                    }
+                    if self.macros.contains_key(name) {
+                        let site = CallSite {
+                            span,
+                            depth: site.map_or(0, |site| site.depth + 1),
+                        };
+                        if site.depth >= MAX_EXPANSION_DEPTH {
+                            return Err(Diagnostic::error(
+                                ErrorCode::MacroExpansion,
+                                span,
+                                format!("Expansion of macro '{}' is nested too deep", name),
+                            ));
+                        }
+                        self.span = span;
+                        let expansion = self.call(name, &list[1..]).map_err(|error| {
+                            Diagnostic::error(
+                                ErrorCode::MacroExpansion,
+                                span,
+                                format!("{:#}", error),
+                            )
+                        })?;
+                        return self.expand(&expansion, Some(site));
+                    }
+                }
+                ExpressionNode::FunctionCall(self.expand_all(list, site)?)
+            }
+            ExpressionNode::AnonymousFunction(list) => {
+                ExpressionNode::AnonymousFunction(self.expand_all(list, site)?)
+            }
+            ExpressionNode::Array(list) => ExpressionNode::Array(self.expand_all(list, site)?),
+            ExpressionNode::Map(list) => ExpressionNode::Map(self.expand_all(list, site)?),
+            ExpressionNode::Set(list) => ExpressionNode::Set(self.expand_all(list, site)?),
+            // quoted forms are data
+            _ => node.clone(),
+        })
+    }
+
+    fn expand_all(
+        &mut self,
+        list: &[ExpressionNode],
+        site: Option<CallSite>,
+    ) -> Result<ExpressionList, Diagnostic> {
+        list.iter().map(|node| self.expand(node, site)).collect()
+    }
+
+    /// Evaluates the body of the macro with the arguments bound to its parameters.
+    fn call(&mut self, name: &str, args: &[ExpressionNode]) -> Result<ExpressionNode> {
+        let definition = self.macros[name].clone();
+        let count = definition.params.len();
+        match definition.rest {
+            None if args.len() != count => {
+                bail!(
+                    "Macro '{}' expects {} arguments, but get {}",
+                    name,
+                    count,
+                    args.len()
+                )
+            }
+            Some(_) if args.len() < count => bail!(
+                "Macro '{}' expects at least {} arguments, but get {}",
+                name,
+                count,
+                args.len()
+            ),
+            _ => {}
+        }
+        let mut env: Environment = definition
+            .params
+            .iter()
+            .cloned()
+            .zip(args.iter().cloned())
+            .collect();
+        if let Some(rest) = definition.rest {
+            env.insert(rest, ExpressionNode::FunctionCall(args[count..].to_vec()));
+        }
+        self.eval_body(&definition.body, &env)
+            .map_err(|error| error.context(format!("In the expansion of macro '{}'", name)))
+    }
+
+    fn eval_body(&mut self, body: &[ExpressionNode], env: &Environment) -> Result<ExpressionNode> {
+        let mut value = self.boolean(false);
+        for form in body {
+            value = self.eval(form, env)?;
+        }
+        Ok(value)
+    }
+
+    fn eval(&mut self, node: &ExpressionNode, env: &Environment) -> Result<ExpressionNode> {
+        Ok(match node {
+            ExpressionNode::Identifier(Symbol { name, .. }) => match env.get(name) {
+                Some(value) => value.clone(),
+                None => bail!("Unknown identifier '{}' in macro body", name),
+            },
+            ExpressionNode::Quote(form) => relocate(form, self.span),
+            ExpressionNode::SyntaxQuote(form) => self.template(form, env, &mut HashMap::new())?,
+            ExpressionNode::Unquote(_) | ExpressionNode::UnquoteSplicing(_) => {
+                bail!("Unquote outside of a syntax-quote: {}", node)
+            }
+            ExpressionNode::Array(list) => ExpressionNode::Array(self.eval_all(list, env)?),
+            ExpressionNode::Map(list) => ExpressionNode::Map(self.eval_all(list, env)?),
+            ExpressionNode::Set(list) => ExpressionNode::Set(self.eval_all(list, env)?),
+            ExpressionNode::AnonymousFunction(_) => {
+                bail!("Anonymous functions are not supported in macro bodies")
+            }
+            ExpressionNode::FunctionCall(list) => self.eval_call(list, env)?,
+            // literals evaluate to themselves, located at the macro call
+            _ => relocate(node, self.span),
+        })
+    }
+
+    fn eval_all(&mut self, list: &[ExpressionNode], env: &Environment) -> Result<ExpressionList> {
+        list.iter().map(|node| self.eval(node, env)).collect()
+    }
+
+    fn eval_call(&mut self, list: &ExpressionList, env: &Environment) -> Result<ExpressionNode> {
+        let (symbol, args) = match list.split_first() {
+            Some((ExpressionNode::Identifier(symbol), args)) => (symbol, args),
+            Some((callee, _)) => bail!("Expected a function name, but get {}", callee),
+            None => bail!("Cannot evaluate an empty list"),
+        };
+        let name = symbol.name.as_str();
+        match (name, args) {
+            ("quote", [form]) => return Ok(relocate(form, self.span)),
+            ("if", [test, then]) | ("if", [test, then, _]) => {
+                return match (self.eval(test, env)?, args.get(2)) {
+                    (ExpressionNode::BooleanLiteral(false, _), Some(otherwise)) => {
+                        self.eval(otherwise, env)
+                    }
+                    (ExpressionNode::BooleanLiteral(false, _), None) => Ok(self.boolean(false)),
+                    _ => self.eval(then, env),
+                };
+            }
+            ("do", body) => return self.eval_body(body, env),
+            ("let", [ExpressionNode::Array(bindings), body @ ..]) if bindings.len() % 2 == 0 => {
+                let mut env = env.clone();
+                for pair in bindings.chunks(2) {
+                    match &pair[0] {
+                        ExpressionNode::Identifier(Symbol { name, .. }) => {
+                            let value = self.eval(&pair[1], &env)?;
+                            env.insert(name.clone(), value);
+                        }
+                        other => bail!("'let' expects names in its bindings, but get {}", other),
+                    }
+                }
+                return self.eval_body(body, &env);
+            }
+            ("quote" | "if" | "let", _) => bail!("Invalid '{}' form in macro body", name),
+            _ => {}
+        }
+
+        let args = self.eval_all(args, env)?;
+        Ok(match (name, args.as_slice()) {
+            ("list", _) => ExpressionNode::FunctionCall(args),
+            ("vector", _) => ExpressionNode::Array(args),
+            ("cons", [head, tail]) => {
+                let mut list = vec![head.clone()];
+                list.extend(items(name, tail)?.iter().cloned());
+                ExpressionNode::FunctionCall(list)
+            }
+            ("concat", _) => {
+                let mut list = vec![];
+                for arg in &args {
+                    list.extend(items(name, arg)?.iter().cloned());
+                }
+                ExpressionNode::FunctionCall(list)
+            }
+            ("first", [list]) => items(name, list)?
+                .first()
+                .cloned()
+                .unwrap_or_else(|| self.boolean(false)),
+            ("rest", [list]) => {
+                ExpressionNode::FunctionCall(items(name, list)?.iter().skip(1).cloned().collect())
+            }
+            ("nth", [list, ExpressionNode::IntegerNumberLiteral(index, _)]) => {
+                match items(name, list)?.get(*index as usize) {
+                    Some(item) if *index >= 0 => item.clone(),
+                    _ => bail!("Index {} is out of bounds of {}", index, list),
+                }
+            }
+            ("count", [list]) => {
+                ExpressionNode::IntegerNumberLiteral(items(name, list)?.len() as i64, self.span)
+            }
+            ("empty?", [list]) => self.boolean(items(name, list)?.is_empty()),
+            ("=", [left, right]) => self.boolean(left == right),
+            ("gensym", []) => self.gensym_symbol("G__"),
+            ("gensym", [ExpressionNode::StringLiteral(prefix, _)]) => self.gensym_symbol(prefix),
+            _ if MACRO_FUNCTIONS.contains(&name) => {
+                bail!("Invalid arguments of '{}' in macro body", name)
+            }
+            _ => bail!("Unknown function '{}' in macro body", name),
+        })
+    }
+
+    /// Expands a syntax-quoted form. `symbols` maps the `name#` symbols of the template
+    /// to their generated names.
+    fn template(
+        &mut self,
+        node: &ExpressionNode,
+        env: &Environment,
+        symbols: &mut HashMap<String, String>,
+    ) -> Result<ExpressionNode> {
+        Ok(match node {
+            ExpressionNode::Unquote(form) => self.eval(form, env)?,
+            ExpressionNode::UnquoteSplicing(_) => {
+                bail!("Unquote-splicing is only allowed in a list: {}", node)
+            }
+            ExpressionNode::SyntaxQuote(_) => bail!("Nested syntax-quotes are not supported"),
+            ExpressionNode::Identifier(symbol)
+                if symbol.name.len() > 1 && symbol.name.ends_with('#') =>
+            {
+                let name = &symbol.name;
+                let stem = &name[..name.len() - 1];
+                if !symbols.contains_key(name) {
+                    let symbol = self.gensym(&format!("{}__", stem));
+                    symbols.insert(name.clone(), format!("{}__auto__", symbol));
+                }
+                ExpressionNode::Identifier(Symbol::new(symbols[name].clone(), self.span))
+            }
+            ExpressionNode::Identifier(symbol) => {
+                ExpressionNode::Identifier(Symbol::new(symbol.name.clone(), self.span))
+            }
+            ExpressionNode::Quote(form) => {
+                ExpressionNode::Quote(Box::new(self.template(form, env, symbols)?))
+            }
+            ExpressionNode::FunctionCall(list) => {
+                ExpressionNode::FunctionCall(self.template_list(list, env, symbols)?)
+            }
+            ExpressionNode::AnonymousFunction(list) => {
+                ExpressionNode::AnonymousFunction(self.template_list(list, env, symbols)?)
+            }
+            ExpressionNode::Array(list) => {
+                ExpressionNode::Array(self.template_list(list, env, symbols)?)
+            }
+            ExpressionNode::Map(list) => {
+                ExpressionNode::Map(self.template_list(list, env, symbols)?)
+            }
+            ExpressionNode::Set(list) => {
+                ExpressionNode::Set(self.template_list(list, env, symbols)?)
+            }
+            _ => relocate(node, self.span),
+        })
+    }
+
+    fn template_list(
+        &mut self,
+        list: &[ExpressionNode],
+        env: &Environment,
+        symbols: &mut HashMap<String, String>,
+    ) -> Result<ExpressionList> {
+        let mut result = vec![];
+        for node in list {
+            match node {
+                ExpressionNode::UnquoteSplicing(form) => {
+                    let value = self.eval(form, env)?;
+                    result.extend(items("~@", &value)?.iter().cloned());
+                }
+                _ => result.push(self.template(node, env, symbols)?),
+            }
+        }
+        Ok(result)
+    }
+
+    /// A new symbol at the location of the macro call.
+    fn gensym_symbol(&mut self, prefix: &str) -> ExpressionNode {
+        ExpressionNode::Identifier(Symbol::new(self.gensym(prefix), self.span))
+    }
+
+    /// A new symbol name, which cannot clash with the other generated ones.
+    fn gensym(&mut self, prefix: &str) -> String {
+        self.next_symbol += 1;
+        format!("{}{}", prefix, self.next_symbol)
+    }
+
+    /// A boolean computed by the macro body, at the location of the macro call.
+    fn boolean(&self, value: bool) -> ExpressionNode {
+        ExpressionNode::BooleanLiteral(value, self.span)
+    }
+}
+
+/// The form with all its symbols and literals located at the span.
+fn relocate(node: &ExpressionNode, span: Span) -> ExpressionNode {
+    let all = |list: &ExpressionList| list.iter().map(|node| relocate(node, span)).collect();
+    match node {
+        ExpressionNode::Empty => ExpressionNode::Empty,
+        ExpressionNode::BooleanLiteral(value, _) => ExpressionNode::BooleanLiteral(*value, span),
+        ExpressionNode::IntegerNumberLiteral(value, _) => {
+            ExpressionNode::IntegerNumberLiteral(*value, span)
+        }
+        ExpressionNode::FloatNumberLiteral(value, _) => {
+            ExpressionNode::FloatNumberLiteral(*value, span)
+        }
+        ExpressionNode::FractionNumberLiteral(numerator, denominator, _) => {
+            ExpressionNode::FractionNumberLiteral(*numerator, *denominator, span)
+        }
+        ExpressionNode::StringLiteral(value, _) => {
+            ExpressionNode::StringLiteral(value.clone(), span)
+        }
+        ExpressionNode::Keyword(name, _) => ExpressionNode::Keyword(name.clone(), span),
+        ExpressionNode::Regex(pattern, _) => ExpressionNode::Regex(pattern.clone(), span),
+        ExpressionNode::Identifier(symbol) => {
+            ExpressionNode::Identifier(Symbol::new(symbol.name.clone(), span))
+        }
+        ExpressionNode::FunctionCall(list) => ExpressionNode::FunctionCall(all(list)),
+        ExpressionNode::AnonymousFunction(list) => ExpressionNode::AnonymousFunction(all(list)),
+        ExpressionNode::Array(list) => ExpressionNode::Array(all(list)),
+        ExpressionNode::Map(list) => ExpressionNode::Map(all(list)),
+        ExpressionNode::Set(list) => ExpressionNode::Set(all(list)),
+        ExpressionNode::Quote(form) => ExpressionNode::Quote(Box::new(relocate(form, span))),
+        ExpressionNode::SyntaxQuote(form) => {
+            ExpressionNode::SyntaxQuote(Box::new(relocate(form, span)))
+        }
+        ExpressionNode::Unquote(form) => ExpressionNode::Unquote(Box::new(relocate(form, span))),
+        ExpressionNode::UnquoteSplicing(form) => {
+            ExpressionNode::UnquoteSplicing(Box::new(relocate(form, span)))
+        }
+    }
+}
+
+/// Elements of a list or vector argument.
+fn items<'a>(function: &str, value: &'a ExpressionNode) -> Result<&'a ExpressionList> {
+    match value {
+        ExpressionNode::FunctionCall(list) | ExpressionNode::Array(list) => Ok(list),
+        _ => bail!("'{}' expects a list, but get {}", function, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::ErrorCode;
+    use crate::expander::expand;
+    use crate::parser::{ExpressionNode, Parser};
+    use crate::scanner::Scanner;
+    use crate::token::Span;
+
+    /// Expands the source and prints the resulting forms on separate lines.
+    fn expand_source(source: &str) -> String {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        let program = parser.parse().unwrap();
+        let expanded = expand(program).unwrap();
+        expanded
+            .into_iter()
+            .map(|form| crate::parser::ExpressionNode::FunctionCall(form).to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn expand_error(source: &str) -> String {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        let program = parser.parse().unwrap();
+        expand(program).unwrap_err().message
+    }
+
+    #[test]
+    fn expand_templates() {
+        assert_eq!(
+            expand_source(
+                "(defmacro unless \"Inverted if\" [test then else]
+                   `(if ~test ~else ~then))
+                 (unless (< 1 2) 10 20)"
+            ),
+            "(if (< 1 2) 20 10)"
+        );
+        assert_eq!(
+            expand_source(
+                "(defmacro sum [& xs] `(+ 0 ~@xs))
+                 (def x (sum 1 (sum) [2 3]))"
+            ),
+            "(def x (+ 0 1 (+ 0) [2 3]))"
+        );
+    }
+
+    #[test]
+    fn expand_nested_macro_calls() {
+        assert_eq!(
+            expand_source(
+                "(defmacro twice [x] `(+ ~x ~x))
+                 (defmacro quadruple [x] `(twice (twice ~x)))
+                 (quadruple 1)"
+            ),
+            "(+ (+ 1 1) (+ 1 1))"
+        );
+    }
+
+    #[test]
+    fn evaluate_macro_bodies() {
+        assert_eq!(
+            expand_source(
+                "(defmacro call-reversed [& form]
+                   (let [f (first form)
+                         args (rest form)]
+                     (if (empty? args)
+                       (list f)
+                       (cons f (concat (rest args) (list (first args)))))))
+                 (call-reversed - 1 2)
+                 (call-reversed +)"
+            ),
+            "(- 2 1)\n(+)"
+        );
+        assert_eq!(
+            expand_source("(defmacro size [& xs] (count xs)) (+ (size a b c) 'x)"),
+            "(+ 3 'x)"
+        );
+    }
+
+    #[test]
+    fn generate_hygienic_symbols() {
+        assert_eq!(
+            expand_source(
+                "(defmacro swap [a b] `(let [tmp# ~a] (def ~a ~b) (def ~b tmp#)))
+                 (swap x y)
+                 (swap tmp y)"
+            ),
+            "(let [tmp__1__auto__ x] (def x y) (def y tmp__1__auto__))
+(let [tmp__2__auto__ tmp] (def tmp y) (def y tmp__2__auto__))"
+        );
+        assert_eq!(
+            expand_source("(defmacro m [] (list 'def (gensym) (gensym \"n\"))) (m)"),
+            "(def G__1 n2)"
+        );
+    }
+
+    #[test]
+    fn report_invalid_macros() {
+        assert_eq!(
+            expand_error("(defmacro m [x] x) (m)"),
+            "Macro 'm' expects 1 arguments, but get 0"
+        );
+        assert_eq!(
+            expand_error("(defmacro m [x & xs] x) (m)"),
+            "Macro 'm' expects at least 1 arguments, but get 0"
+        );
+        assert_eq!(
+            expand_error("(defmacro m [] `(a ~y)) (m)"),
+            "In the expansion of macro 'm': Unknown identifier 'y' in macro body"
+        );
+        assert_eq!(
+            expand_error("(defmacro m [] (list 'm)) (m)"),
+            "Expansion of macro 'm' is nested too deep"
+        );
+        assert_eq!(
+            expand_error("(defmacro m [] 1) (m)"),
+            "Top-level forms must expand to lists, but get 1"
+        );
+        assert_eq!(
+            expand_error("(def x (defmacro m [] 1))"),
+            "'defmacro' is only allowed at the top level"
+        );
+        assert_eq!(
+            expand_error("(defmacro def [] 1)"),
+            "Cannot define macro 'def', it is a built-in"
+        );
+        assert_eq!(
+            expand_error("(defmacro m [a &] 1)"),
+            "Invalid parameter & of macro 'm', expected names and an optional `& rest`"
+        );
+        assert_eq!(
+            expand_error("(defmacro m [] ~x) (m)"),
+            "In the expansion of macro 'm': Unquote outside of a syntax-quote: ~x"
+        );
+    }
+
+    #[test]
+    fn locate_errors_at_macro_calls() {
+        let locate = |source: &str| {
+            let mut scanner = Scanner::new(source);
+            let mut parser = Parser::new(&mut scanner);
+            let diagnostic = expand(parser.parse().unwrap()).unwrap_err();
+            (diagnostic.code, diagnostic.span)
+        };
+        assert_eq!(
+            locate("(defmacro m [x] x)\n(+ 1 (m))"),
+            (ErrorCode::MacroExpansion, Span::new(25, 26))
+        );
+        // errors of the inner macro are reported at the call in the program
+        assert_eq!(
+            locate("(defmacro inner [] `(a ~y)) (defmacro outer [] `(inner)) (outer)"),
+            (ErrorCode::MacroExpansion, Span::new(58, 63))
+        );
+        assert_eq!(
+            locate("(+ 1) (defmacro [] 1)"),
+            (ErrorCode::InvalidMacro, Span::new(7, 15))
+        );
+    }
+
+    #[test]
+    fn locate_expanded_symbols_at_macro_calls() {
+        let mut scanner = Scanner::new("(defmacro m [x] `(+ ~x y# 'q)) (m z)");
+        let mut parser = Parser::new(&mut scanner);
+        let program = expand(parser.parse().unwrap()).unwrap();
+        let spans: Vec<Span> = program[0]
+            .iter()
+            .map(|node| match node {
+                ExpressionNode::Identifier(symbol) => symbol.span,
+                ExpressionNode::Quote(form) => match form.as_ref() {
+                    ExpressionNode::Identifier(symbol) => symbol.span,
+                    other => panic!("Unexpected {}", other),
+                },
+                other => panic!("Unexpected {}", other),
+            })
+            .collect();
+        let call = Span::new(32, 33);
+        assert_eq!(spans, vec![call, Span::new(34, 35), call, call]);
+    }
+}
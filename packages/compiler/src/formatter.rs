@@ -0,0 +1,459 @@
+use crate::analyzer::is_special_form;
+use crate::cst::{self, Element, Node, NodeKind, SyntaxToken};
+use crate::token::{TokenType, TriviaKind};
+
+/// Forms whose arguments are indented by two spaces instead of aligned with the first
+/// argument: the special forms, except `recur`, whose arguments are values.
+fn has_body(name: &str) -> bool {
+    is_special_form(name) && name != "recur"
+}
+
+/// Formats Pocket Lisp source code. Line breaks and comments of the source are kept,
+/// the formatter only changes the horizontal layout:
+///
+/// - continuation lines of `def`, `fn`, `let`, `if` and the other special forms are
+///   indented by two spaces, arguments of other calls are aligned with the first argument
+///   when it is on the line of the function name, otherwise with the function name,
+/// - elements of vectors and maps are aligned with the first element,
+/// - the values of a map with every key on its own line are aligned into a column,
+/// - closing delimiters are moved to the end of the previous line,
+/// - tokens on the same line are separated by a single space, `#_` and quotes are glued
+///   to their form unless a comment is between them, then the comment and the form
+///   start new lines,
+/// - at most one empty line is kept and the file ends with a line break.
+pub fn format_source(source: &str) -> String {
+    let root = cst::parse(source);
+    let mut formatter = Formatter::default();
+    for child in &root.children {
+        formatter.element(child, 0, Spacing::Space);
+    }
+    while formatter.out.ends_with('\n') {
+        formatter.out.pop();
+    }
+    if !formatter.out.is_empty() {
+        formatter.out.push('\n');
+    }
+    formatter.out
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Spacing {
+    /// Directly after the previous token, like after an opening delimiter
+    None,
+    Space,
+    /// Closing delimiter, kept on the line of the previous token
+    Closing,
+    /// Spaces aligning a map value
+    Pad(usize),
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    /// Column of the end of the output, counted in characters
+    column: usize,
+    /// The next token must start on a new line
+    after_line_comment: bool,
+    /// The next token follows a `#`, `#_` or quoting reader macro without space
+    after_dispatch: bool,
+}
+
+impl Formatter {
+    fn element(&mut self, element: &Element, indent: usize, spacing: Spacing) {
+        match element {
+            Element::Token(token) => self.token(token, indent, spacing),
+            Element::Node(node) => match node.kind {
+                NodeKind::Root | NodeKind::Error => {
+                    for child in &node.children {
+                        self.element(child, indent, spacing);
+                    }
+                }
+                NodeKind::Discard | NodeKind::ReaderMacro => {
+                    for (index, child) in node.children.iter().enumerate() {
+                        let spacing = if index == 0 { spacing } else { Spacing::Space };
+                        self.element(child, indent, spacing);
+                    }
+                }
+                _ => self.form(node, indent, spacing),
+            },
+        }
+    }
+
+    /// Prints a delimited form, `indent` is used when it starts on a new line.
+    fn form(&mut self, node: &Node, indent: usize, spacing: Spacing) {
+        let mut children = node.children.iter().peekable();
+        // `#` of anonymous functions, then the opening delimiter
+        if let Some(Element::Token(dispatch)) = children.peek() {
+            if dispatch.kind == TokenType::Dispatch {
+                self.token(dispatch, indent, spacing);
+                children.next();
+            }
+        }
+        match children.next() {
+            Some(Element::Token(open)) => self.token(open, indent, spacing),
+            Some(element) => self.element(element, indent, spacing),
+            None => return,
+        }
+        let open_column = self.column - 1;
+
+        let elements: Vec<&Element> = children.take_while(|child| !is_closing(child)).collect();
+        let closing = node.children.last().filter(|child| is_closing(child));
+        let padding = match node.kind {
+            NodeKind::Map => map_padding(&elements),
+            _ => None,
+        };
+
+        let is_call = matches!(node.kind, NodeKind::List | NodeKind::AnonymousFunction);
+        let mut is_body_form = false;
+        let mut body_indent = open_column + 1;
+        for (index, element) in elements.iter().enumerate() {
+            let spacing = match (index, &padding) {
+                (0, _) => Spacing::None,
+                (index, Some(padding)) if index % 2 == 1 => Spacing::Pad(padding[index / 2]),
+                _ => Spacing::Space,
+            };
+            if index == 1
+                && is_call
+                && !is_body_form
+                && !starts_line(element)
+                && !self.after_line_comment
+            {
+                // arguments are aligned with the first one, if it is on the line of the head
+                body_indent = self.column + 1;
+            }
+            let indent = if index == 0 {
+                open_column + 1
+            } else {
+                body_indent
+            };
+            self.element(element, indent, spacing);
+            if index == 0 && is_call {
+                is_body_form = matches!(element, Element::Token(head) if has_body(head.text));
+                if is_body_form {
+                    body_indent = open_column + 2;
+                }
+            }
+        }
+        if let Some(closing) = closing {
+            self.element(closing, body_indent, Spacing::Closing);
+        }
+    }
+
+    fn token(&mut self, token: &SyntaxToken, indent: usize, spacing: Spacing) {
+        let mut newlines = 0;
+        for trivia in &token.leading_trivia {
+            match trivia.kind {
+                TriviaKind::Whitespace => newlines += trivia.text.matches('\n').count(),
+                TriviaKind::Comma => {
+                    if self.after_line_comment || newlines > 0 {
+                        self.separate(newlines, indent, Spacing::Space);
+                        newlines = 0;
+                    }
+                    self.write(",");
+                }
+                TriviaKind::LineComment | TriviaKind::BlockComment => {
+                    // a comment after a reader macro gets its own line, so does the target
+                    let after_dispatch = std::mem::take(&mut self.after_dispatch);
+                    if after_dispatch {
+                        newlines = newlines.max(1);
+                    }
+                    self.separate(newlines, indent, Spacing::Space);
+                    newlines = 0;
+                    self.write(trivia.text.trim_end());
+                    self.after_line_comment =
+                        after_dispatch || trivia.kind == TriviaKind::LineComment;
+                }
+            }
+        }
+        if token.kind == TokenType::Eof {
+            return;
+        }
+        self.separate(newlines, indent, spacing);
+        self.write(token.text);
+        self.after_dispatch = matches!(
+            token.kind,
+            TokenType::Dispatch
+                | TokenType::Discard
+                | TokenType::Quote
+                | TokenType::SyntaxQuote
+                | TokenType::Unquote
+                | TokenType::UnquoteSplicing
+        );
+    }
+
+    /// Writes the line breaks or spaces before a token or comment.
+    fn separate(&mut self, newlines: usize, indent: usize, spacing: Spacing) {
+        let after_line_comment = std::mem::take(&mut self.after_line_comment);
+        if self.out.is_empty() || std::mem::take(&mut self.after_dispatch) {
+            return;
+        }
+        if after_line_comment || (newlines > 0 && spacing != Spacing::Closing) {
+            // keep at most one empty line
+            self.out += &"\n".repeat(newlines.clamp(1, 2));
+            self.out += &" ".repeat(indent);
+            self.column = indent;
+            return;
+        }
+        match spacing {
+            Spacing::None | Spacing::Closing => {}
+            Spacing::Space => self.write(" "),
+            Spacing::Pad(width) => self.write(&" ".repeat(width)),
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        self.out += text;
+        self.column = match text.rfind('\n') {
+            Some(pos) => text[pos + 1..].chars().count(),
+            None => self.column + text.chars().count(),
+        };
+    }
+}
+
+fn is_closing(element: &Element) -> bool {
+    matches!(
+        element,
+        Element::Token(token) if matches!(
+            token.kind,
+            TokenType::RightParen | TokenType::RightSquare | TokenType::RightBrace
+        )
+    )
+}
+
+fn first_token<'e, 'a>(element: &'e Element<'a>) -> Option<&'e SyntaxToken<'a>> {
+    match element {
+        Element::Token(token) => Some(token),
+        Element::Node(node) => node.children.first().and_then(first_token),
+    }
+}
+
+/// The element is preceded by a line break or a line comment.
+fn starts_line(element: &Element) -> bool {
+    first_token(element).is_some_and(|token| {
+        token
+            .leading_trivia
+            .iter()
+            .any(|trivia| trivia.kind == TriviaKind::LineComment || trivia.text.contains('\n'))
+    })
+}
+
+/// Spaces before the value of every pair, when the map has every key on its own line and
+/// each value on the line of its key. Keys must be single tokens, like keywords.
+fn map_padding(elements: &[&Element]) -> Option<Vec<usize>> {
+    if elements.len() < 4 || !elements.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut widths = vec![];
+    for (index, pair) in elements.chunks(2).enumerate() {
+        let key = match pair[0] {
+            Element::Token(key) if index == 0 || starts_line(pair[0]) => key,
+            _ => return None,
+        };
+        if starts_line(pair[1]) || key.text.contains('\n') {
+            return None;
+        }
+        widths.push(key.text.chars().count());
+    }
+    let max = widths.iter().copied().max().unwrap_or(0);
+    Some(widths.into_iter().map(|width| max - width + 1).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::formatter::format_source;
+    use std::fs;
+    use std::path::Path;
+
+    fn assert_format(source: &str, expected: &str) {
+        let formatted = format_source(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format_source(&formatted),
+            expected,
+            "format is not idempotent"
+        );
+    }
+
+    #[test]
+    fn normalize_spaces() {
+        assert_format("(  +   1\t2 )", "(+ 1 2)\n");
+        assert_format("[ 1  2 ]  { :a  1 }", "[1 2] {:a 1}\n");
+        assert_format("#(  + % 1 )", "#(+ % 1)\n");
+        assert_format(
+            "(re-find  #\"a  b\"   #{ 1  2 })",
+            "(re-find #\"a  b\" #{1 2})\n",
+        );
+        assert_format("", "");
+        assert_format("\n\n", "");
+    }
+
+    #[test]
+    fn keep_line_breaks_and_one_empty_line() {
+        assert_format(
+            "\n\n(def a 1)\n\n\n\n(def b 2)\n(def c 3)   \n\n",
+            "(def a 1)\n\n(def b 2)\n(def c 3)\n",
+        );
+    }
+
+    #[test]
+    fn indent_body_forms() {
+        assert_format(
+            "(def x\n(let [a 1\nb 2]\n        (if (< a b)\n    a\n  b)))",
+            "(def x
+  (let [a 1
+        b 2]
+    (if (< a b)
+      a
+      b)))
+",
+        );
+        assert_format(
+            "(fn [x]\n(* x x))",
+            "(fn [x]
+  (* x x))
+",
+        );
+        // names which are not special forms are called like functions
+        assert_format(
+            "(when x\ny)\n(recur 1\n2)",
+            "(when x
+      y)
+(recur 1
+       2)
+",
+        );
+    }
+
+    #[test]
+    fn align_call_arguments() {
+        assert_format(
+            "(+ 1\n2\n      3)",
+            "(+ 1
+   2
+   3)
+",
+        );
+        assert_format(
+            "(foo\n  1\n 2)",
+            "(foo
+ 1
+ 2)
+",
+        );
+        assert_format(
+            "(list (+ 1\n2) [1\n2])",
+            "(list (+ 1
+         2) [1
+             2])
+",
+        );
+    }
+
+    #[test]
+    fn gather_closing_delimiters() {
+        assert_format(
+            "(def x\n  (+ 1 2)\n)\n",
+            "(def x
+  (+ 1 2))
+",
+        );
+    }
+
+    #[test]
+    fn align_map_values() {
+        assert_format(
+            "{:a 1\n:longkey 2\n   :bc {:d 3}}",
+            "{:a       1
+ :longkey 2
+ :bc      {:d 3}}
+",
+        );
+        // not aligned when a pair is split or shares a line
+        assert_format("{:a 1 :bb 2}", "{:a 1 :bb 2}\n");
+        assert_format(
+            "{:a 1 :bb 2\n:c 3}",
+            "{:a 1 :bb 2
+ :c 3}
+",
+        );
+    }
+
+    #[test]
+    fn preserve_comments() {
+        assert_format(
+            ";; header\n(def x ; the answer\n42)   ;# block #;\n; trailing  \n",
+            ";; header
+(def x ; the answer
+  42) ;# block #;
+; trailing
+",
+        );
+        assert_format(
+            "(+ 1 ; one\n)",
+            "(+ 1 ; one
+   )
+",
+        );
+        assert_format(";# multi\n  line #;\n(a)", ";# multi\n  line #;\n(a)\n");
+    }
+
+    #[test]
+    fn glue_discarded_forms() {
+        assert_format("(a #_  (b c)  d)", "(a #_(b c) d)\n");
+        assert_format("#_ #_ a   b\n(c)", "#_#_a b\n(c)\n");
+        assert_format(
+            "(def x\n#_ 1\n2)",
+            "(def x
+  #_1
+  2)
+",
+        );
+    }
+
+    /// Compares the formatted form of every `golden/fmt/*.pl` with the `.fmt` file next to
+    /// it. Run the tests with `UPDATE_GOLDEN=1` to accept the new output.
+    #[test]
+    fn golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/fmt");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut sources: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pl"))
+            .collect();
+        sources.sort();
+        assert!(!sources.is_empty());
+
+        for source_path in sources {
+            let formatted = format_source(&fs::read_to_string(&source_path).unwrap());
+            let expected_path = source_path.with_extension("fmt");
+            if update {
+                fs::write(&expected_path, &formatted).unwrap();
+            } else {
+                let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+                assert_eq!(formatted, expected, "{}", expected_path.display());
+                assert_eq!(
+                    format_source(&formatted),
+                    expected,
+                    "format is not idempotent"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn glue_quoted_forms() {
+        assert_format(
+            "(defmacro m [x]\n` (+ ~ x ~@  [1 2]  ' y))",
+            "(defmacro m [x]
+  `(+ ~x ~@[1 2] 'y))
+",
+        );
+    }
+
+    #[test]
+    fn keep_commas_and_strings() {
+        assert_format("{:a 1 ,  :b 2}", "{:a 1, :b 2}\n");
+        assert_format("(print   \"a  ; b\n  c\")", "(print \"a  ; b\n  c\")\n");
+    }
+}
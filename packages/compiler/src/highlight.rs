@@ -0,0 +1,408 @@
+use crate::analyzer::{is_special_form, BUILTINS};
+use crate::scanner::Scanner;
+use crate::token::{Span, TokenType, TriviaKind};
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum HighlightKind {
+    /// `(`, `)`, `[`, `]`, `{` and `}`
+    Delimiter,
+    /// `#` and the quoting reader macros, like `'` and `~@`
+    Dispatch,
+    /// Name of a special form in the head of a list, like `def` in `(def x 1)`
+    SpecialForm,
+    /// Name of a built-in function, like `+`
+    Builtin,
+    Identifier,
+    Keyword,
+    String,
+    Regex,
+    Number,
+    Boolean,
+    Comment,
+    Error,
+}
+
+impl HighlightKind {
+    pub fn css_class(&self) -> &'static str {
+        match self {
+            HighlightKind::Delimiter => "pl-delimiter",
+            HighlightKind::Dispatch => "pl-dispatch",
+            HighlightKind::SpecialForm => "pl-special-form",
+            HighlightKind::Builtin => "pl-builtin",
+            HighlightKind::Identifier => "pl-identifier",
+            HighlightKind::Keyword => "pl-keyword",
+            HighlightKind::String => "pl-string",
+            HighlightKind::Regex => "pl-regex",
+            HighlightKind::Number => "pl-number",
+            HighlightKind::Boolean => "pl-boolean",
+            HighlightKind::Comment => "pl-comment",
+            HighlightKind::Error => "pl-error",
+        }
+    }
+
+    /// SGR escape sequence of the kind, `None` for the kinds printed without colour.
+    pub fn ansi_color(&self) -> Option<&'static str> {
+        match self {
+            HighlightKind::Delimiter | HighlightKind::Identifier => None,
+            HighlightKind::Dispatch => Some("\x1b[1;36m"),
+            HighlightKind::SpecialForm => Some("\x1b[1;35m"),
+            HighlightKind::Builtin => Some("\x1b[34m"),
+            HighlightKind::Keyword => Some("\x1b[36m"),
+            HighlightKind::String => Some("\x1b[32m"),
+            HighlightKind::Regex => Some("\x1b[31m"),
+            HighlightKind::Number => Some("\x1b[33m"),
+            HighlightKind::Boolean => Some("\x1b[35m"),
+            HighlightKind::Comment => Some("\x1b[90m"),
+            HighlightKind::Error => Some("\x1b[4;31m"),
+        }
+    }
+}
+
+/// Output of the `highlight` subcommand.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum HighlightFormat {
+    /// Terminal colours
+    #[default]
+    Ansi,
+    /// Standalone HTML page
+    Html,
+}
+
+impl FromStr for HighlightFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ansi" => Ok(HighlightFormat::Ansi),
+            "html" => Ok(HighlightFormat::Html),
+            _ => Err(format!(
+                "Unknown highlight format '{}', expected 'ansi' or 'html'",
+                value
+            )),
+        }
+    }
+}
+
+/// Classifies the tokens and comments of the source in source order. Whitespace and
+/// commas are left out, they are printed as they are. Forms discarded by `#_` are
+/// highlighted as comments.
+pub fn classify(source: &str) -> Vec<(HighlightKind, Span)> {
+    let mut scanner = Scanner::with_trivia(source);
+    let mut result = vec![];
+    let mut list_head = false;
+    let mut discard = Discard::default();
+    loop {
+        let token = scanner.scan_token();
+        for trivia in scanner.take_trivia() {
+            if matches!(
+                trivia.kind,
+                TriviaKind::LineComment | TriviaKind::BlockComment
+            ) {
+                result.push((HighlightKind::Comment, trivia.span));
+            }
+        }
+        let kind = match token.kind {
+            TokenType::Eof => return result,
+            _ if discard.skip(token.kind) => HighlightKind::Comment,
+            TokenType::LeftParen
+            | TokenType::RightParen
+            | TokenType::LeftBrace
+            | TokenType::RightBrace
+            | TokenType::LeftSquare
+            | TokenType::RightSquare => HighlightKind::Delimiter,
+            TokenType::Dispatch
+            | TokenType::Quote
+            | TokenType::SyntaxQuote
+            | TokenType::Unquote
+            | TokenType::UnquoteSplicing => HighlightKind::Dispatch,
+            TokenType::Discard => unreachable!("Discard tokens are skipped"),
+            TokenType::True | TokenType::False => HighlightKind::Boolean,
+            TokenType::Identifier if list_head && is_special_form(token.src) => {
+                HighlightKind::SpecialForm
+            }
+            TokenType::Identifier
+                if BUILTINS.contains(&token.src) && !is_special_form(token.src) =>
+            {
+                HighlightKind::Builtin
+            }
+            TokenType::Identifier => HighlightKind::Identifier,
+            TokenType::Keyword => HighlightKind::Keyword,
+            TokenType::String => HighlightKind::String,
+            TokenType::Regex => HighlightKind::Regex,
+            TokenType::IntegerNumber | TokenType::FloatNumber | TokenType::FractionNumber => {
+                HighlightKind::Number
+            }
+            TokenType::Init | TokenType::Error => HighlightKind::Error,
+        };
+        list_head = token.kind == TokenType::LeftParen;
+        result.push((kind, token.span));
+    }
+}
+
+/// Tracks the forms discarded by `#_` while scanning.
+#[derive(Default)]
+struct Discard {
+    /// `#_` macros waiting for the start of their form
+    pending: usize,
+    /// Nesting level inside the discarded form
+    depth: usize,
+}
+
+impl Discard {
+    /// Returns true if the token is a `#_` or part of a discarded form.
+    fn skip(&mut self, kind: TokenType) -> bool {
+        match kind {
+            TokenType::Discard => {
+                if self.depth == 0 {
+                    self.pending += 1;
+                }
+                true
+            }
+            _ if self.pending == 0 && self.depth == 0 => false,
+            TokenType::LeftParen | TokenType::LeftSquare | TokenType::LeftBrace => {
+                if self.depth == 0 {
+                    self.pending -= 1;
+                }
+                self.depth += 1;
+                true
+            }
+            TokenType::RightParen | TokenType::RightSquare | TokenType::RightBrace => {
+                // a closing delimiter right after `#_` belongs to the enclosing form
+                if self.depth == 0 {
+                    self.pending = 0;
+                    return false;
+                }
+                self.depth -= 1;
+                true
+            }
+            // prefixes are part of the form started by the next token
+            TokenType::Dispatch
+            | TokenType::Quote
+            | TokenType::SyntaxQuote
+            | TokenType::Unquote
+            | TokenType::UnquoteSplicing => true,
+            _ => {
+                if self.depth == 0 {
+                    self.pending -= 1;
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Colours the source with ANSI escape sequences. The byte at `emphasis`, like a
+/// matching bracket in the REPL, is printed in bold.
+pub fn to_ansi(source: &str, emphasis: Option<usize>) -> String {
+    render(source, |out, kind, text, span| {
+        let bold = emphasis.is_some_and(|at| span.start == at && text.len() == 1);
+        match (bold, kind.and_then(|kind| kind.ansi_color())) {
+            (true, _) => *out += &format!("\x1b[1;4m{}\x1b[0m", text),
+            (false, Some(color)) => *out += &format!("{}{}\x1b[0m", color, text),
+            (false, None) => *out += text,
+        }
+    })
+}
+
+/// Highlighted source as a `<pre>` element with a CSS class on every token.
+pub fn to_html_fragment(source: &str) -> String {
+    let code = render(source, |out, kind, text, _| match kind {
+        Some(kind) => {
+            *out += &format!(
+                "<span class=\"{}\">{}</span>",
+                kind.css_class(),
+                escape_html(text)
+            )
+        }
+        None => *out += &escape_html(text),
+    });
+    format!("<pre class=\"pocket-lisp\"><code>{}</code></pre>", code)
+}
+
+const HTML_STYLE: &str = "pre.pocket-lisp { background: #fafafa; color: #383a42; padding: 1em; }
+.pl-dispatch { color: #0184bc; font-weight: bold; }
+.pl-special-form { color: #a626a4; font-weight: bold; }
+.pl-builtin { color: #4078f2; }
+.pl-keyword { color: #0184bc; }
+.pl-string { color: #50a14f; }
+.pl-regex { color: #c18401; }
+.pl-number { color: #986801; }
+.pl-boolean { color: #a626a4; }
+.pl-comment { color: #a0a1a7; font-style: italic; }
+.pl-error { color: #e45649; text-decoration: underline wavy; }";
+
+/// Standalone HTML page with the highlighted source and its style sheet.
+pub fn to_html_document(source: &str, title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{}</title>
+<style>
+{}
+</style>
+</head>
+<body>
+{}
+</body>
+</html>
+",
+        escape_html(title),
+        HTML_STYLE,
+        to_html_fragment(source)
+    )
+}
+
+/// Calls `write` for every classified piece and for the plain text between them.
+fn render(source: &str, write: impl Fn(&mut String, Option<HighlightKind>, &str, Span)) -> String {
+    let mut out = String::new();
+    let mut position = 0;
+    for (kind, span) in classify(source) {
+        if span.start > position {
+            let gap = Span::new(position, span.start);
+            write(&mut out, None, &source[gap.start..gap.end], gap);
+        }
+        write(&mut out, Some(kind), &source[span.start..span.end], span);
+        position = span.end;
+    }
+    if position < source.len() {
+        let gap = Span::new(position, source.len());
+        write(&mut out, None, &source[position..], gap);
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highlight::{classify, to_ansi, to_html_document, to_html_fragment, HighlightKind};
+
+    #[test]
+    fn classify_tokens() {
+        use HighlightKind::*;
+        let source = "(def x (+ 1/2 y)) ; note\n#(if true :k \"s\" #\"r\" @ '~@z)";
+        let kinds: Vec<_> = classify(source)
+            .into_iter()
+            .map(|(kind, span)| (kind, &source[span.start..span.end]))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                (Delimiter, "("),
+                (SpecialForm, "def"),
+                (Identifier, "x"),
+                (Delimiter, "("),
+                (Builtin, "+"),
+                (Number, "1/2"),
+                (Identifier, "y"),
+                (Delimiter, ")"),
+                (Delimiter, ")"),
+                (Comment, "; note"),
+                (Dispatch, "#"),
+                (Delimiter, "("),
+                (SpecialForm, "if"),
+                (Boolean, "true"),
+                (Keyword, ":k"),
+                (String, "\"s\""),
+                (Regex, "#\"r\""),
+                (Error, "@"),
+                (Dispatch, "'"),
+                (Dispatch, "~@"),
+                (Identifier, "z"),
+                (Delimiter, ")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn special_forms_only_in_head_position() {
+        let kinds: Vec<_> = classify("(f def)")
+            .into_iter()
+            .map(|(kind, _)| kind)
+            .collect();
+
+        assert_eq!(kinds[2], HighlightKind::Identifier);
+
+        let kinds: Vec<_> = ["(defn f)", "(quote x)", "(defmacro m)"]
+            .iter()
+            .map(|source| classify(source)[1].0)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                HighlightKind::Identifier,
+                HighlightKind::Identifier,
+                HighlightKind::SpecialForm
+            ]
+        );
+    }
+
+    #[test]
+    fn discarded_forms_as_comments() {
+        use HighlightKind::*;
+        let source = "(a #_ #_ b #(c [d]) e) (#_)";
+        let kinds: Vec<_> = classify(source)
+            .into_iter()
+            .map(|(kind, span)| (kind, &source[span.start..span.end]))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                (Delimiter, "("),
+                (Identifier, "a"),
+                (Comment, "#_"),
+                (Comment, "#_"),
+                (Comment, "b"),
+                (Comment, "#"),
+                (Comment, "("),
+                (Comment, "c"),
+                (Comment, "["),
+                (Comment, "d"),
+                (Comment, "]"),
+                (Comment, ")"),
+                (Identifier, "e"),
+                (Delimiter, ")"),
+                (Delimiter, "("),
+                (Comment, "#_"),
+                (Delimiter, ")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_ansi() {
+        assert_eq!(
+            to_ansi("(def x 1) ;c", None),
+            "(\x1b[1;35mdef\x1b[0m x \x1b[33m1\x1b[0m) \x1b[90m;c\x1b[0m"
+        );
+        assert_eq!(to_ansi("(x)", Some(2)), "(x\x1b[1;4m)\x1b[0m");
+    }
+
+    #[test]
+    fn render_html() {
+        assert_eq!(
+            to_html_fragment("(< a \"&\")"),
+            "<pre class=\"pocket-lisp\"><code>\
+             <span class=\"pl-delimiter\">(</span>\
+             <span class=\"pl-builtin\">&lt;</span> \
+             <span class=\"pl-identifier\">a</span> \
+             <span class=\"pl-string\">&quot;&amp;&quot;</span>\
+             <span class=\"pl-delimiter\">)</span>\
+             </code></pre>"
+        );
+        let page = to_html_document("(a)\n", "<main.pl>");
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<title>&lt;main.pl&gt;</title>"));
+        assert!(page.contains(".pl-special-form {"));
+    }
+}
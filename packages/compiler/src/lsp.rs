@@ -0,0 +1,993 @@
+use crate::analyzer::{is_special_form, BUILTINS};
+use crate::cst::{self, Element, Node, NodeKind};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::parser::Parser;
+use crate::scanner::{is_symbol, unescape, Scanner};
+use crate::token::{Span, TokenType};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// https://www.jsonrpc.org/specification#error_object
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Largest `Content-Length` accepted, so a bad header cannot allocate unbounded memory.
+const MAX_MESSAGE_LENGTH: usize = 64 * 1024 * 1024;
+
+// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#symbolKind
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+// https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#completionItemKind
+const COMPLETION_FUNCTION: u32 = 3;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_KEYWORD: u32 = 14;
+
+/// Runs the language server on stdin and stdout until the client sends `exit`,
+/// returns the exit code of the process.
+pub fn start() -> i32 {
+    match serve(io::stdin().lock(), io::stdout().lock()) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            1
+        }
+    }
+}
+
+/// Reads JSON-RPC messages framed by `Content-Length` headers and writes the responses
+/// and notifications of the server in the same framing.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(content) = read_message(&mut input)? {
+        let outgoing = match serde_json::from_str::<Value>(&content) {
+            Ok(message) => server.handle(&message),
+            Err(error) => vec![error_response(Value::Null, PARSE_ERROR, error.to_string())],
+        };
+        for message in outgoing {
+            write_message(&mut output, &message)?;
+        }
+        if let Some(code) = server.exit_code {
+            return Ok(code);
+        }
+    }
+    // the client closed the stream without `exit`
+    Ok(1)
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or_default();
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Message of {} bytes is longer than the limit of {} bytes",
+                length, MAX_MESSAGE_LENGTH
+            ),
+        ));
+    }
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    String::from_utf8(content)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+type RequestResult = Result<Value, (i64, String)>;
+
+#[derive(Default)]
+struct Server {
+    /// Open documents by URI
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    exit_code: Option<i32>,
+}
+
+impl Server {
+    /// Handles a message of the client and returns the messages to send back.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) if !method.is_empty() => {
+                let response = match self.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, error)) => error_response(id.clone(), code, error),
+                };
+                vec![response]
+            }
+            // responses to server requests, the server never sends any
+            Some(_) => vec![],
+            None => self.notification(method, params),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // incremental changes
+                    "textDocumentSync": { "openClose": true, "change": 2 },
+                    "documentSymbolProvider": true,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": [":"] },
+                },
+                "serverInfo": { "name": "pocket-lisp", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/documentSymbol" => Ok(self.document(params)?.1.symbols()),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_owned(), Document::new(text));
+            }
+            "textDocument/didChange" => {
+                let document = match self.documents.get_mut(uri) {
+                    Some(document) => document,
+                    None => return vec![],
+                };
+                let changes = params["contentChanges"].as_array().cloned();
+                for change in changes.unwrap_or_default() {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    if change["range"].is_object() {
+                        let start = document.offset(&change["range"]["start"]);
+                        let end = document.offset(&change["range"]["end"]);
+                        document.edit(start, end.max(start), text);
+                    } else {
+                        *document = Document::new(text);
+                    }
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, vec![])];
+            }
+            "exit" => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                return vec![];
+            }
+            _ => return vec![],
+        }
+        match self.documents.get(uri) {
+            Some(document) => vec![publish_diagnostics(uri, document.lsp_diagnostics())],
+            None => vec![],
+        }
+    }
+
+    fn document(&self, params: &Value) -> Result<(&str, &Document), (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get_key_value(uri) {
+            Some((uri, document)) => Ok((uri, document)),
+            None => Err((INVALID_PARAMS, format!("Unknown document '{}'", uri))),
+        }
+    }
+
+    /// Finds a top-level definition, first in the given document, then in the other open ones.
+    fn find_definition(&self, uri: &str, name: &str) -> Option<(&str, &Document, &Definition)> {
+        let mut documents: Vec<_> = self.documents.iter().collect();
+        documents.sort_by_key(|(other, _)| (other.as_str() != uri, other.as_str()));
+        documents.into_iter().find_map(|(uri, document)| {
+            let definition = document.definition(name)?;
+            Some((uri.as_str(), document, definition))
+        })
+    }
+
+    fn definition(&self, params: &Value) -> RequestResult {
+        let (uri, document) = self.document(params)?;
+        let offset = document.offset(&params["position"]);
+        let location = document
+            .identifier_at(offset)
+            .and_then(|(name, _)| self.find_definition(uri, &name))
+            .map(|(uri, document, definition)| {
+                json!({ "uri": uri, "range": document.range(definition.name_span) })
+            });
+        Ok(location.unwrap_or(Value::Null))
+    }
+
+    fn hover(&self, params: &Value) -> RequestResult {
+        let (uri, document) = self.document(params)?;
+        let offset = document.offset(&params["position"]);
+        let (name, span) = match document.identifier_at(offset) {
+            Some(identifier) => identifier,
+            None => return Ok(Value::Null),
+        };
+        let contents = match self.find_definition(uri, &name) {
+            Some((_, _, definition)) => {
+                let mut contents = format!("```pocket-lisp\n{}\n```", definition.signature());
+                if let Some(doc) = &definition.doc {
+                    contents += "\n\n";
+                    contents += doc;
+                }
+                contents
+            }
+            None if BUILTINS.contains(&name.as_str()) => {
+                format!("```pocket-lisp\n{}\n```\n\nBuilt-in", name)
+            }
+            None => return Ok(Value::Null),
+        };
+        Ok(json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": document.range(span),
+        }))
+    }
+
+    fn completion(&self, params: &Value) -> RequestResult {
+        let (_, document) = self.document(params)?;
+        let offset = document.offset(&params["position"]);
+        let prefix = document.word_before(offset);
+
+        let mut items: Vec<(String, u32)> = vec![];
+        if prefix.starts_with(':') {
+            for document in self.documents.values() {
+                for form in &document.forms {
+                    items.extend(
+                        form.keywords
+                            .iter()
+                            .map(|k| (k.clone(), COMPLETION_KEYWORD)),
+                    );
+                }
+            }
+        } else {
+            items.extend(
+                BUILTINS
+                    .iter()
+                    .map(|name| (name.to_string(), COMPLETION_FUNCTION)),
+            );
+            items.extend(["true", "false"].map(|name| (name.to_owned(), COMPLETION_KEYWORD)));
+            for document in self.documents.values() {
+                for definition in document.forms.iter().filter_map(|f| f.definition.as_ref()) {
+                    let kind = if definition.is_function {
+                        COMPLETION_FUNCTION
+                    } else {
+                        COMPLETION_VARIABLE
+                    };
+                    items.push((definition.name.clone(), kind));
+                }
+            }
+        }
+        items.retain(|(label, _)| label.starts_with(prefix) && label != prefix);
+        items.sort();
+        items.dedup_by(|a, b| a.0 == b.0);
+        let items: Vec<Value> = items
+            .into_iter()
+            .map(|(label, kind)| json!({ "label": label, "kind": kind }))
+            .collect();
+        Ok(Value::Array(items))
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// An open source file with the summary of its top-level forms.
+///
+/// Edits re-parse the text only from the end of the last top-level form before the edit,
+/// the forms before it are kept. Only closed forms without diagnostics before them are
+/// kept: the parser leaves such a form in a fresh state, and no text after it can change
+/// it, so the result is the same as parsing the whole text again.
+struct Document {
+    text: String,
+    forms: Vec<Form>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+struct Form {
+    /// From the first to the last token of the form
+    span: Span,
+    /// Every delimiter of the form is closed, so the text after it cannot extend it
+    closed: bool,
+    definition: Option<Definition>,
+    keywords: Vec<String>,
+}
+
+struct Definition {
+    /// `def` or `defmacro`
+    form: String,
+    name: String,
+    name_span: Span,
+    /// Parameter vector of functions
+    params: Option<String>,
+    doc: Option<String>,
+    is_function: bool,
+}
+
+impl Definition {
+    fn signature(&self) -> String {
+        match &self.params {
+            Some(params) => format!("({} {} {})", self.form, self.name, params),
+            None => format!("({} {})", self.form, self.name),
+        }
+    }
+}
+
+impl Document {
+    fn new(text: &str) -> Self {
+        let mut document = Document {
+            text: text.to_owned(),
+            forms: vec![],
+            diagnostics: vec![],
+        };
+        document.reparse(0);
+        document
+    }
+
+    /// Replaces the text between the byte offsets and returns the offset where re-parsing started.
+    fn edit(&mut self, start: usize, end: usize, text: &str) -> usize {
+        self.text.replace_range(start..end, text);
+        let first_error = self
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.span.start)
+            .min()
+            .unwrap_or(usize::MAX);
+        let kept = self
+            .forms
+            .iter()
+            .take_while(|form| form.closed && form.span.end < start && form.span.end <= first_error)
+            .count();
+        let from = kept
+            .checked_sub(1)
+            .map_or(0, |last| self.forms[last].span.end);
+        self.forms.truncate(kept);
+        // the kept forms have no diagnostics
+        self.diagnostics.clear();
+        self.reparse(from);
+        from
+    }
+
+    /// Parses the text from the offset.
+    fn reparse(&mut self, from: usize) {
+        let (forms, diagnostics) = parse_forms(&self.text[from..]);
+        let shift = |span: Span| Span::new(span.start + from, span.end + from);
+        self.forms.extend(forms.into_iter().map(|form| Form {
+            span: shift(form.span),
+            definition: form.definition.map(|definition| Definition {
+                name_span: shift(definition.name_span),
+                ..definition
+            }),
+            ..form
+        }));
+        self.diagnostics
+            .extend(diagnostics.into_iter().map(|diagnostic| Diagnostic {
+                span: shift(diagnostic.span),
+                ..diagnostic
+            }));
+    }
+
+    fn definition(&self, name: &str) -> Option<&Definition> {
+        self.forms
+            .iter()
+            .filter_map(|form| form.definition.as_ref())
+            .find(|definition| definition.name == name)
+    }
+
+    /// The identifier containing the offset or ending at it, with its span.
+    fn identifier_at(&self, offset: usize) -> Option<(String, Span)> {
+        let form = self
+            .forms
+            .iter()
+            .find(|form| form.span.start <= offset && offset <= form.span.end)?;
+        let mut scanner = Scanner::new(&self.text[form.span.start..form.span.end]);
+        loop {
+            let token = scanner.scan_token();
+            let span = Span::new(
+                token.span.start + form.span.start,
+                token.span.end + form.span.start,
+            );
+            match token.kind {
+                TokenType::Eof => return None,
+                TokenType::Identifier if span.start <= offset && offset <= span.end => {
+                    return Some((token.src.to_owned(), span));
+                }
+                _ if span.start > offset => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// The identifier or keyword characters right before the offset.
+    fn word_before(&self, offset: usize) -> &str {
+        let before = &self.text[..offset];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|(_, ch)| {
+                !(ch.is_ascii_alphanumeric()
+                    || *ch == ':'
+                    || u8::try_from(*ch).is_ok_and(is_symbol))
+            })
+            .map_or(0, |(pos, ch)| pos + ch.len_utf8());
+        &before[start..]
+    }
+
+    fn symbols(&self) -> Value {
+        let symbols: Vec<Value> = self
+            .forms
+            .iter()
+            .filter_map(|form| {
+                let definition = form.definition.as_ref()?;
+                Some(json!({
+                    "name": definition.name,
+                    "detail": definition.form,
+                    "kind": if definition.is_function { SYMBOL_FUNCTION } else { SYMBOL_VARIABLE },
+                    "range": self.range(form.span),
+                    "selectionRange": self.range(definition.name_span),
+                }))
+            })
+            .collect();
+        Value::Array(symbols)
+    }
+
+    fn lsp_diagnostics(&self) -> Vec<Value> {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let message = match &diagnostic.help {
+                    Some(help) => format!("{}\nhelp: {}", diagnostic.message, help),
+                    None => diagnostic.message.clone(),
+                };
+                json!({
+                    "range": self.range(diagnostic.span),
+                    "severity": match diagnostic.severity {
+                        Severity::Error => 1,
+                    },
+                    "code": diagnostic.code.as_str(),
+                    "source": "pocket-lisp",
+                    "message": message,
+                })
+            })
+            .collect()
+    }
+
+    // LSP positions count the characters of a line in UTF-16 code units
+    fn position(&self, offset: usize) -> Value {
+        let offset = offset.min(self.text.len());
+        let line_start = self.text[..offset].rfind('\n').map_or(0, |pos| pos + 1);
+        let line = self.text[..line_start].matches('\n').count();
+        let character: usize = self.text[line_start..offset]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, span: Span) -> Value {
+        json!({ "start": self.position(span.start), "end": self.position(span.end) })
+    }
+
+    /// Byte offset of an LSP position, positions past the end of a line are moved to its end.
+    fn offset(&self, position: &Value) -> usize {
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let character = position["character"].as_u64().unwrap_or_default() as usize;
+        let line_start = match line {
+            0 => 0,
+            _ => match self.text.match_indices('\n').nth(line - 1) {
+                Some((pos, _)) => pos + 1,
+                None => return self.text.len(),
+            },
+        };
+        let mut units = 0;
+        for (pos, ch) in self.text[line_start..].char_indices() {
+            if units >= character || ch == '\n' {
+                return line_start + pos;
+            }
+            units += ch.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
+/// Forms and syntax errors of the source, located in it.
+fn parse_forms(source: &str) -> (Vec<Form>, Vec<Diagnostic>) {
+    let mut forms = vec![];
+    for element in &cst::parse(source).children {
+        let form = match element {
+            Element::Token(token) if token.kind == TokenType::Eof => continue,
+            Element::Token(token) => Form {
+                span: token.span,
+                closed: is_closed(element),
+                definition: None,
+                keywords: vec![],
+            },
+            Element::Node(node) => Form {
+                span: node.span(),
+                closed: is_closed(element),
+                definition: definition(node),
+                keywords: node
+                    .tokens()
+                    .filter(|token| token.kind == TokenType::Keyword)
+                    .map(|token| token.text.to_owned())
+                    .collect(),
+            },
+        };
+        forms.push(form);
+    }
+
+    let mut scanner = Scanner::new(source);
+    let mut parser = Parser::new(&mut scanner);
+    let diagnostics = match parser.parse() {
+        Ok(_) => vec![],
+        Err(error) => error.diagnostics().to_vec(),
+    };
+    (forms, diagnostics)
+}
+
+/// The element ends in a complete token: a closing delimiter of its own or an atom. A lone
+/// `#` is not complete, the parser reads it together with the token after it.
+fn is_closed(element: &Element) -> bool {
+    match element {
+        Element::Token(token) => !matches!(
+            token.kind,
+            TokenType::Error | TokenType::Eof | TokenType::Dispatch
+        ),
+        Element::Node(node) => match node.kind {
+            NodeKind::Discard | NodeKind::ReaderMacro => {
+                node.children.len() > 1 && node.children.last().is_some_and(is_closed)
+            }
+            NodeKind::Error => false,
+            _ => matches!(
+                node.children.last(),
+                Some(Element::Token(token)) if matches!(
+                    token.kind,
+                    TokenType::RightParen | TokenType::RightSquare | TokenType::RightBrace
+                )
+            ),
+        },
+    }
+}
+
+/// Special forms defining a top-level name, shown as document symbols: `def` and `defmacro`.
+fn is_definition_form(name: &str) -> bool {
+    is_special_form(name) && name.starts_with("def")
+}
+
+/// Summary of a top-level `(def name "doc" value)` or `(defmacro name "doc" [params] body)`
+/// form, with spans relative to the parsed text. The parameters of a `def` are those of
+/// its `(fn [params] body)` value.
+fn definition(node: &Node) -> Option<Definition> {
+    if node.kind != NodeKind::List {
+        return None;
+    }
+    // skip the parentheses
+    let elements: Vec<&Element> = node
+        .children
+        .iter()
+        .skip(1)
+        .filter(
+            |child| !matches!(child, Element::Token(token) if token.kind == TokenType::RightParen),
+        )
+        .collect();
+    let (form, name) = match elements.as_slice() {
+        [Element::Token(form), Element::Token(name), ..]
+            if form.kind == TokenType::Identifier
+                && is_definition_form(form.text)
+                && name.kind == TokenType::Identifier =>
+        {
+            (form, name)
+        }
+        _ => return None,
+    };
+    let mut rest = &elements[2..];
+    let doc = match rest {
+        [Element::Token(doc), _, ..] if doc.kind == TokenType::String => {
+            rest = &rest[1..];
+            unescape(&doc.text[1..doc.text.len() - 1]).ok()
+        }
+        _ => None,
+    };
+    let (params, is_function) = match rest.first() {
+        _ if form.text != "def" => (rest.first().copied(), true),
+        Some(Element::Node(value)) if value.kind == NodeKind::AnonymousFunction => (None, true),
+        Some(Element::Node(value))
+            if value.kind == NodeKind::List
+                && matches!(value.children.get(1), Some(Element::Token(head)) if head.text == "fn") =>
+        {
+            (value.children.get(2), true)
+        }
+        _ => (None, false),
+    };
+    let params = match params {
+        Some(Element::Node(params)) if params.kind == NodeKind::Vector => {
+            Some(params.to_string().trim_start().to_owned())
+        }
+        _ => None,
+    };
+    Some(Definition {
+        form: form.text.to_owned(),
+        name: name.text.to_owned(),
+        name_span: name.span,
+        params,
+        doc,
+        is_function,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lsp::{serve, Document};
+    use crate::token::Span;
+    use serde_json::{json, Value};
+    use std::io::{Cursor, ErrorKind};
+
+    /// Plays the messages of a client and returns the exit code and the messages of the server.
+    fn session(messages: &[Value]) -> (i32, Vec<Value>) {
+        let input: String = messages
+            .iter()
+            .map(|message| {
+                let content = message.to_string();
+                format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+            })
+            .collect();
+        let mut output = vec![];
+        let code = serve(Cursor::new(input), &mut output).unwrap();
+
+        let mut output = String::from_utf8(output).unwrap();
+        let mut messages = vec![];
+        while let Some(rest) = output.strip_prefix("Content-Length: ") {
+            let (length, rest) = rest.split_once("\r\n\r\n").unwrap();
+            let length: usize = length.parse().unwrap();
+            messages.push(serde_json::from_str(&rest[..length]).unwrap());
+            output = rest[length..].to_owned();
+        }
+        assert!(output.is_empty(), "{}", output);
+        (code, messages)
+    }
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///main.pl", "languageId": "pocket-lisp", "version": 1, "text": text } },
+        })
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": "file:///main.pl" },
+                "position": { "line": line, "character": character },
+            },
+        })
+    }
+
+    fn result(messages: &[Value], id: u32) -> &Value {
+        &messages.iter().find(|message| message["id"] == id).unwrap()["result"]
+    }
+
+    const SOURCE: &str = "(def pi \"Ratio of a circle's circumference to its diameter\" 3.14)\n\
+                          (def area (fn [r] (* pi r r)))\n\
+                          (area :small)\n";
+
+    #[test]
+    fn initialize_and_exit() {
+        let (code, messages) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "capabilities": {} } }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert_eq!(code, 0);
+        assert_eq!(messages.len(), 2);
+        let capabilities = &result(&messages, 1)["capabilities"];
+        assert_eq!(capabilities["textDocumentSync"]["change"], 2);
+        assert_eq!(capabilities["hoverProvider"], true);
+        assert_eq!(result(&messages, 2), &Value::Null);
+    }
+
+    #[test]
+    fn exit_without_shutdown() {
+        let (code, _) = session(&[json!({ "jsonrpc": "2.0", "method": "exit" })]);
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn report_unknown_methods_and_invalid_json() {
+        let (_, messages) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "workspace/unknown" }),
+            request(2, "textDocument/hover", 0, 0),
+        ]);
+
+        assert_eq!(messages[0]["error"]["code"], -32601);
+        assert_eq!(messages[1]["error"]["code"], -32602);
+
+        let mut output = vec![];
+        serve(Cursor::new("Content-Length: 3\r\n\r\n{x}"), &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("-32700"));
+    }
+
+    #[test]
+    fn reject_messages_over_the_length_limit() {
+        let input = "Content-Length: 99999999999\r\n\r\n{}";
+        let error = serve(Cursor::new(input), &mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn publish_diagnostics() {
+        let (_, messages) = session(&[open("(def x 1)\n(+ x \"a\\q\")")]);
+
+        assert_eq!(messages[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = &messages[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "E0003");
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 1, "character": 5 }, "end": { "line": 1, "character": 10 } })
+        );
+    }
+
+    #[test]
+    fn report_numbers_out_of_range() {
+        let (_, messages) = session(&[
+            open("(def x 99999999999999999999)"),
+            request(1, "textDocument/documentSymbol", 0, 0),
+        ]);
+
+        let diagnostics = &messages[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["code"], "E0007");
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 0, "character": 7 }, "end": { "line": 0, "character": 27 } })
+        );
+        // the server keeps answering
+        assert_eq!(result(&messages, 1)[0]["name"], "x");
+    }
+
+    #[test]
+    fn list_document_symbols() {
+        let (_, messages) = session(&[
+            open(SOURCE),
+            request(1, "textDocument/documentSymbol", 0, 0),
+        ]);
+
+        let symbols = result(&messages, 1).as_array().unwrap();
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|symbol| {
+                (
+                    symbol["name"].as_str().unwrap(),
+                    symbol["kind"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(names, vec![("pi", 13), ("area", 12)]);
+        assert_eq!(
+            symbols[1]["selectionRange"],
+            json!({ "start": { "line": 1, "character": 5 }, "end": { "line": 1, "character": 9 } })
+        );
+    }
+
+    #[test]
+    fn go_to_definition() {
+        let (_, messages) = session(&[
+            open(SOURCE),
+            request(1, "textDocument/definition", 1, 22),
+            request(2, "textDocument/definition", 2, 3),
+            request(3, "textDocument/definition", 2, 7),
+        ]);
+
+        assert_eq!(
+            result(&messages, 1),
+            &json!({
+                "uri": "file:///main.pl",
+                "range": { "start": { "line": 0, "character": 5 }, "end": { "line": 0, "character": 7 } },
+            })
+        );
+        assert_eq!(result(&messages, 2)["range"]["start"]["line"], 1);
+        assert_eq!(result(&messages, 3), &Value::Null);
+    }
+
+    #[test]
+    fn hover_with_docstring() {
+        let (_, messages) = session(&[
+            open(SOURCE),
+            request(1, "textDocument/hover", 1, 23),
+            request(2, "textDocument/hover", 1, 19),
+            request(3, "textDocument/hover", 2, 1),
+        ]);
+
+        assert_eq!(
+            result(&messages, 1)["contents"]["value"],
+            "```pocket-lisp\n(def pi)\n```\n\nRatio of a circle's circumference to its diameter"
+        );
+        assert_eq!(
+            result(&messages, 2)["contents"]["value"],
+            "```pocket-lisp\n*\n```\n\nBuilt-in"
+        );
+        assert_eq!(
+            result(&messages, 3)["contents"]["value"],
+            "```pocket-lisp\n(def area [r])\n```"
+        );
+    }
+
+    #[test]
+    fn complete_identifiers_and_keywords() {
+        let labels = |value: &Value| -> Vec<String> {
+            value
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["label"].as_str().unwrap().to_owned())
+                .collect()
+        };
+        let (_, messages) = session(&[
+            open("(def area 1)\n(def answer :yes)\n(a :y"),
+            request(1, "textDocument/completion", 2, 2),
+            request(2, "textDocument/completion", 2, 5),
+        ]);
+
+        assert_eq!(labels(result(&messages, 1)), vec!["answer", "area"]);
+        assert_eq!(labels(result(&messages, 2)), vec![":yes"]);
+    }
+
+    #[test]
+    fn complete_after_multibyte_characters() {
+        let (_, messages) = session(&[
+            open("(def area 1)\n(+ é\n(é ar"),
+            request(1, "textDocument/completion", 1, 4),
+            request(2, "textDocument/completion", 2, 5),
+        ]);
+
+        let items = result(&messages, 1).as_array().unwrap();
+        assert!(items.iter().any(|item| item["label"] == "area"));
+        let items = result(&messages, 2).as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["label"], "area");
+    }
+
+    #[test]
+    fn apply_incremental_changes() {
+        let change = |range: Value, text: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": "file:///main.pl", "version": 2 },
+                    "contentChanges": [{ "range": range, "text": text }],
+                },
+            })
+        };
+        let (_, messages) = session(&[
+            open("(def x 1)\n(def y (+ x 2)"),
+            change(
+                json!({ "start": { "line": 1, "character": 14 }, "end": { "line": 1, "character": 14 } }),
+                ")",
+            ),
+            request(1, "textDocument/documentSymbol", 0, 0),
+            change(
+                json!({ "start": { "line": 0, "character": 5 }, "end": { "line": 0, "character": 6 } }),
+                "renamed",
+            ),
+            request(2, "textDocument/documentSymbol", 0, 0),
+        ]);
+
+        assert_eq!(messages[0]["params"]["diagnostics"][0]["code"], "E0103");
+        assert_eq!(messages[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(result(&messages, 1).as_array().unwrap().len(), 2);
+        assert_eq!(result(&messages, 2)[0]["name"], "renamed");
+    }
+
+    #[test]
+    fn reparse_only_after_the_last_unchanged_form() {
+        let mut document = Document::new("(def a 1)\n(def b 2) ; note\n(def c 3)");
+
+        assert_eq!(document.edit(17, 18, "22"), 9);
+        assert_eq!(document.text, "(def a 1)\n(def b 22) ; note\n(def c 3)");
+        assert_eq!(document.edit(0, 0, " "), 0);
+        // inside the comment after the second form
+        assert_eq!(document.edit(25, 25, "x"), 21);
+        assert!(document.diagnostics.is_empty());
+
+        // unclosing a form changes everything after it
+        assert_eq!(document.edit(20, 21, ""), 10);
+        assert_eq!(document.forms.len(), 2);
+        assert_eq!(document.diagnostics[0].code.as_str(), "E0103");
+        assert_eq!(document.definition("c").map(|d| d.name.as_str()), None);
+    }
+
+    /// Spans, completeness and defined names of the forms, to compare with a full parse.
+    fn forms(document: &Document) -> Vec<(Span, bool, Option<&str>)> {
+        document
+            .forms
+            .iter()
+            .map(|form| {
+                let name = form.definition.as_ref().map(|d| d.name.as_str());
+                (form.span, form.closed, name)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn incremental_edits_match_a_full_parse() {
+        let mut document = Document::new("(def y (+ x 2) \n");
+        let edits = [
+            (15, 15, ")"),
+            (0, 0, "(def x 1) "),
+            (26, 26, "]"),
+            (26, 27, ""),
+            (10, 10, "# a "),
+            (10, 14, "#_ "),
+            (13, 13, "(def z"),
+            (19, 19, ")"),
+            (0, 0, "\"s"),
+            (0, 2, ""),
+            (21, 21, "'"),
+        ];
+
+        for (start, end, text) in edits {
+            document.edit(start, end, text);
+            let full = Document::new(&document.text);
+            assert_eq!(forms(&document), forms(&full), "{:?}", document.text);
+            assert_eq!(
+                document.diagnostics, full.diagnostics,
+                "{:?}",
+                document.text
+            );
+        }
+    }
+
+    #[test]
+    fn convert_utf16_positions() {
+        let document = Document::new("(é 𝄞 x)\n(y)");
+
+        assert_eq!(document.position(8), json!({ "line": 0, "character": 5 }));
+        assert_eq!(document.offset(&json!({ "line": 0, "character": 5 })), 8);
+        assert_eq!(document.offset(&json!({ "line": 0, "character": 100 })), 11);
+        assert_eq!(document.offset(&json!({ "line": 1, "character": 1 })), 13);
+        assert_eq!(document.offset(&json!({ "line": 5, "character": 0 })), 15);
+    }
+}
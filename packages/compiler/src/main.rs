@@ -1,70 +1,285 @@
-use std::io::BufRead;
-use std::{env, fs, io, result};
-use wasmtime::{Engine, Instance, Module, Store};
+use crate::diagnostic::{Diagnostic, ErrorFormat};
+use crate::emit::EmitStage;
+use crate::highlight::HighlightFormat;
+use crate::parser::{Parser, Program};
+use crate::scanner::Scanner;
+use crate::wat::WatStyle;
+use clap::{Parser as ClapParser, Subcommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 
+mod analyzer;
+mod codegen;
+mod cst;
+mod diagnostic;
+mod disasm;
+mod emit;
 mod emitter;
+mod expander;
+mod formatter;
+mod highlight;
+mod lsp;
 mod parser;
+mod repl;
+mod resolver;
+mod runtime;
 mod scanner;
 mod token;
+mod wat;
+
+const EXIT_FAILURE: i32 = 1;
+// Exit codes, from sysexits.h
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATA_ERROR: i32 = 65;
+const EXIT_NO_INPUT: i32 = 66;
+const EXIT_SOFTWARE: i32 = 70;
+const EXIT_CANNOT_CREATE: i32 = 73;
+
+/// Pocket Lisp compiler
+#[derive(ClapParser)]
+#[clap(name = "compiler", version)]
+struct Cli {
+    /// Format of the reported errors: human or json
+    #[clap(long, global = true, default_value = "human")]
+    error_format: ErrorFormat,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a source file to a WebAssembly module
+    Compile {
+        /// Source file
+        input: PathBuf,
+        /// Output file, defaults to the source file with `.wasm` extension,
+        /// text stages are printed to stdout without it
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Stage to emit: tokens, cst, ast, expanded, wat or wasm
+        #[clap(long, default_value = "wasm")]
+        emit: EmitStage,
+        /// Instruction style of the `wat` stage: linear or folded
+        #[clap(long, default_value = "linear")]
+        wat_style: WatStyle,
+    },
+    /// Compile a source file and execute it
+    Run {
+        /// Source file
+        input: PathBuf,
+    },
+    /// Check a source file for errors without producing output
+    Check {
+        /// Source file
+        input: PathBuf,
+    },
+    /// Format source files in place
+    Fmt {
+        /// Source files
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+        /// Only check the formatting, list the files which would change and exit with 1
+        #[clap(long)]
+        check: bool,
+    },
+    /// Print a source file with syntax highlighting
+    Highlight {
+        /// Source file
+        input: PathBuf,
+        /// Output format: ansi or html
+        #[clap(long, default_value = "ansi")]
+        format: HighlightFormat,
+    },
+    /// Print the sections and instructions of a compiled WebAssembly module
+    Disasm {
+        /// WebAssembly module
+        input: PathBuf,
+    },
+    /// Start the language server, speaking LSP on stdin and stdout
+    Lsp,
+    /// Start the interactive REPL (default)
+    Repl,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::try_parse().unwrap_or_else(|error| {
+        let code = if error.use_stderr() { EXIT_USAGE } else { 0 };
+        let _ = error.print();
+        exit(code);
+    });
 
-    match args.len() {
-        1 => {
-            repl();
+    match cli.command.unwrap_or(Command::Repl) {
+        Command::Compile {
+            input,
+            output,
+            emit,
+            wat_style,
+        } => {
+            let bytes = emit_file(&input, emit, wat_style, cli.error_format);
+            match output {
+                Some(output) => write_output(&output, bytes),
+                None if emit.is_binary() => write_output(&input.with_extension("wasm"), bytes),
+                None => print!("{}", String::from_utf8_lossy(&bytes)),
+            }
         }
-        2 => {
-            run_file(args[1].as_str());
+        Command::Run { input } => {
+            let bytes = compile_file(&input, cli.error_format);
+            match runtime::invoke_wasm_module(&bytes) {
+                Ok(value) => println!("{}", value),
+                Err(error) => {
+                    eprintln!("Error: {}", error);
+                    exit(EXIT_SOFTWARE);
+                }
+            }
         }
-        _ => {
-            println!("Usage: rlox [path]");
-
-            std::process::exit(64);
+        Command::Check { input } => {
+            compile_file(&input, cli.error_format);
         }
+        Command::Fmt { files, check } => {
+            let code = format_files(&files, check, cli.error_format);
+            if code != 0 {
+                exit(code);
+            }
+        }
+        Command::Highlight { input, format } => {
+            let source = read_source(&input);
+            match format {
+                HighlightFormat::Ansi => print!("{}", highlight::to_ansi(&source, None)),
+                HighlightFormat::Html => {
+                    let title = input.display().to_string();
+                    print!("{}", highlight::to_html_document(&source, &title))
+                }
+            }
+        }
+        Command::Disasm { input } => {
+            let bytes = fs::read(&input).unwrap_or_else(|error| {
+                eprintln!("Could not open file '{}': {}", input.display(), error);
+                exit(EXIT_NO_INPUT);
+            });
+            match disasm::disassemble(&bytes) {
+                Ok(listing) => print!("{}", listing),
+                Err(error) => {
+                    eprintln!("Error: {}", error);
+                    exit(EXIT_DATA_ERROR);
+                }
+            }
+        }
+        Command::Lsp => exit(lsp::start()),
+        Command::Repl => repl::start(),
     }
 }
 
-fn repl() {
-    let stdin = io::stdin();
-    let mut lines = stdin.lock().lines();
-    loop {
-        print!("> ");
-        if let Ok(line) = lines.next().unwrap() {
-            println!("echo {}", line);
-        } else {
-            break;
+/// Formats the files in place, or lists the unformatted ones with `check`. The errors of
+/// a file are reported and the next files are still processed, the exit code is the
+/// highest one of the failures.
+fn format_files(files: &[PathBuf], check: bool, error_format: ErrorFormat) -> i32 {
+    let mut code = 0;
+    for path in files {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("Could not open file '{}': {}", path.display(), error);
+                code = code.max(EXIT_NO_INPUT);
+                continue;
+            }
+        };
+        // formatting keeps the tokens, but broken forms would be indented misleadingly
+        let mut scanner = Scanner::new(&source);
+        let mut parser = Parser::new(&mut scanner);
+        if let Err(error) = parser.parse() {
+            let file_name = path.display().to_string();
+            diagnostic::report(error.diagnostics(), &file_name, &source, error_format);
+            code = code.max(EXIT_DATA_ERROR);
+            continue;
         }
+        let formatted = formatter::format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("Would reformat {}", path.display());
+            code = code.max(EXIT_FAILURE);
+        } else if let Err(error) = fs::write(path, formatted) {
+            eprintln!("Could not write file '{}': {}", path.display(), error);
+            code = code.max(EXIT_CANNOT_CREATE);
+        }
+    }
+    code
+}
+
+fn read_source(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("Could not open file '{}': {}", path.display(), error);
+        exit(EXIT_NO_INPUT);
+    })
+}
+
+/// Parses a source file, or reports the syntax errors and exits.
+fn parse_file(path: &Path, source: &str, error_format: ErrorFormat) -> Program {
+    let mut scanner = Scanner::new(source);
+    let mut parser = Parser::new(&mut scanner);
+    match parser.parse() {
+        Ok(program) => program.clone(),
+        Err(error) => report_and_exit(path, source, error.diagnostics(), error_format),
     }
 }
 
-fn run_file(path: &str) {
-    if let Ok(source) = fs::read_to_string(path) {
-        print!("File opened")
-    } else {
-        eprintln!("Could not open file '{}'", path);
-        std::process::exit(64);
+fn report_and_exit(
+    path: &Path,
+    source: &str,
+    diagnostics: &[Diagnostic],
+    error_format: ErrorFormat,
+) -> ! {
+    let file_name = path.display().to_string();
+    diagnostic::report(diagnostics, &file_name, source, error_format);
+    exit(EXIT_DATA_ERROR);
+}
+
+fn write_output(path: &Path, bytes: Vec<u8>) {
+    if let Err(error) = fs::write(path, bytes) {
+        eprintln!("Could not write file '{}': {}", path.display(), error);
+        exit(EXIT_CANNOT_CREATE);
     }
 }
-//
-// fn interpret(source: &str) -> InterpretResult {
-//     let mut scanner = Scanner::new(source);
-//     // let mut parser = Parser::new(&mut scanner, &mut vm.chunks);
-//     // let parse_result = parser.parse();
-//     //
-//     // if !parse_result {
-//     //     return InterpretResult::CompileError;
-//     // }
-//     //
-//     // vm.run()
-// }
 
-fn invoke_wasm_module(module_name: String) -> result::Result<String, anyhow::Error> {
-    let engine = Engine::default();
-    let module = Module::from_file(&engine, module_name)?;
-    let mut store = Store::new(&engine, ());
-    let instance = Instance::new(&mut store, &module, &[])?;
-    let exported_run = instance.get_typed_func::<(), i32, _>(&mut store, "run")?;
-    let res = exported_run.call(&mut store, ())?;
-    Ok(res.to_string())
+/// Compiles a source file to a wasm module, or reports the errors and exits.
+fn compile_file(path: &Path, error_format: ErrorFormat) -> Vec<u8> {
+    emit_file(path, EmitStage::Wasm, WatStyle::default(), error_format)
+}
+
+/// Runs the compiler until the requested stage and returns its output,
+/// or reports the errors and exits.
+fn emit_file(
+    path: &Path,
+    stage: EmitStage,
+    wat_style: WatStyle,
+    error_format: ErrorFormat,
+) -> Vec<u8> {
+    let source = read_source(path);
+    // tokens and syntax trees are printed even for invalid sources, errors are part of the listing
+    match stage {
+        EmitStage::Tokens => return emit::tokens(&source).into_bytes(),
+        EmitStage::Cst => return emit::cst(&source).into_bytes(),
+        _ => {}
+    }
+    let program = parse_file(path, &source, error_format);
+    if stage == EmitStage::Ast {
+        return emit::ast(&program).into_bytes();
+    }
+    let mut program = expander::expand(&program)
+        .unwrap_or_else(|diagnostic| report_and_exit(path, &source, &[diagnostic], error_format));
+    if stage == EmitStage::Expanded {
+        return emit::expanded(&program).into_bytes();
+    }
+    if let Err(diagnostics) = resolver::resolve(&mut program) {
+        report_and_exit(path, &source, &diagnostics, error_format);
+    }
+    let module = codegen::compile_module(&program)
+        .unwrap_or_else(|diagnostics| report_and_exit(path, &source, &diagnostics, error_format));
+    match stage {
+        EmitStage::Wat => wat::print_module(&module, wat_style).into_bytes(),
+        _ => module.build(),
+    }
 }
@@ -1,35 +1,240 @@
-use crate::scanner::Scanner;
-use crate::token::{Token, TokenType};
-use anyhow::{Error, Result};
-use std::thread::{current, park};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::scanner::{unescape, Scanner};
+use crate::token::{Span, Token, TokenType};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
-type Program = Vec<ExpressionList>;
+pub type Program = Vec<ExpressionList>;
 
-type ExpressionList = Vec<ExpressionNode>;
+pub type ExpressionList = Vec<ExpressionNode>;
 
-#[derive(Debug, PartialEq)]
-enum ExpressionNode {
+/// Form read by the parser. The literals end with their location for the diagnostics,
+/// which is not part of their equality, like the span of a [`Symbol`].
+#[derive(Debug, Clone)]
+pub enum ExpressionNode {
     Empty,
-    BooleanLiteral(bool),
-    IntegerNumberLiteral(i64),
-    FloatNumberLiteral(f64),
-    FractionNumberLiteral(i64, i64),
-    StringLiteral(String),
-    Identifier(String),
-    Keyword(String),
+    BooleanLiteral(bool, Span),
+    IntegerNumberLiteral(i64, Span),
+    FloatNumberLiteral(f64, Span),
+    FractionNumberLiteral(i64, i64, Span),
+    StringLiteral(String, Span),
+    Identifier(Symbol),
+    Keyword(String, Span),
     FunctionCall(ExpressionList),
     AnonymousFunction(ExpressionList),
     Array(ExpressionList),
     Map(ExpressionList),
+    /// `#{...}`
+    Set(ExpressionList),
+    /// `#"..."`, a pattern checked by the parser
+    Regex(String, Span),
+    /// `'form`
+    Quote(Box<ExpressionNode>),
+    /// `` `form ``
+    SyntaxQuote(Box<ExpressionNode>),
+    /// `~form`
+    Unquote(Box<ExpressionNode>),
+    /// `~@form`
+    UnquoteSplicing(Box<ExpressionNode>),
+}
+
+/// Name in an expression, with its location for the diagnostics and its binding,
+/// found by the resolver. Like metadata in Clojure, the span and the scope are not
+/// part of the equality of symbols.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub span: Span,
+    pub scope: Scope,
+}
+
+impl Symbol {
+    pub fn new(name: impl Into<String>, span: Span) -> Self {
+        Symbol {
+            name: name.into(),
+            span,
+            scope: Scope::Unresolved,
+        }
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::new(name, Span::default())
+    }
+}
+
+impl PartialEq for ExpressionNode {
+    fn eq(&self, other: &Self) -> bool {
+        use ExpressionNode::*;
+        match (self, other) {
+            (Empty, Empty) => true,
+            (BooleanLiteral(left, _), BooleanLiteral(right, _)) => left == right,
+            (IntegerNumberLiteral(left, _), IntegerNumberLiteral(right, _)) => left == right,
+            (FloatNumberLiteral(left, _), FloatNumberLiteral(right, _)) => left == right,
+            (FractionNumberLiteral(a, b, _), FractionNumberLiteral(c, d, _)) => (a, b) == (c, d),
+            (StringLiteral(left, _), StringLiteral(right, _))
+            | (Keyword(left, _), Keyword(right, _))
+            | (Regex(left, _), Regex(right, _)) => left == right,
+            (Identifier(left), Identifier(right)) => left == right,
+            (FunctionCall(left), FunctionCall(right))
+            | (AnonymousFunction(left), AnonymousFunction(right))
+            | (Array(left), Array(right))
+            | (Map(left), Map(right))
+            | (Set(left), Set(right)) => left == right,
+            (Quote(left), Quote(right))
+            | (SyntaxQuote(left), SyntaxQuote(right))
+            | (Unquote(left), Unquote(right))
+            | (UnquoteSplicing(left), UnquoteSplicing(right)) => left == right,
+            _ => false,
+        }
+    }
+}
+
+impl ExpressionNode {
+    /// Location of the node: of its first symbol or literal for the lists and quoted
+    /// forms, `None` when they have none.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ExpressionNode::Empty => None,
+            ExpressionNode::BooleanLiteral(_, span)
+            | ExpressionNode::IntegerNumberLiteral(_, span)
+            | ExpressionNode::FloatNumberLiteral(_, span)
+            | ExpressionNode::FractionNumberLiteral(_, _, span)
+            | ExpressionNode::StringLiteral(_, span)
+            | ExpressionNode::Keyword(_, span)
+            | ExpressionNode::Regex(_, span) => Some(*span),
+            ExpressionNode::Identifier(symbol) => Some(symbol.span),
+            ExpressionNode::FunctionCall(list)
+            | ExpressionNode::AnonymousFunction(list)
+            | ExpressionNode::Array(list)
+            | ExpressionNode::Map(list)
+            | ExpressionNode::Set(list) => first_span(list),
+            ExpressionNode::Quote(form)
+            | ExpressionNode::SyntaxQuote(form)
+            | ExpressionNode::Unquote(form)
+            | ExpressionNode::UnquoteSplicing(form) => form.span(),
+        }
+    }
+}
+
+/// Location of the first symbol or literal of the nodes.
+pub fn first_span(nodes: &[ExpressionNode]) -> Option<Span> {
+    nodes.iter().find_map(ExpressionNode::span)
+}
+
+/// Binding of a symbol in an expression.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Scope {
+    /// Not resolved yet
+    #[default]
+    Unresolved,
+    /// Parameter or `let` binding of the innermost function
+    Local,
+    /// Parameter or `let` binding of an enclosing function, used by an inner one
+    Captured,
+    /// Top-level `def`
+    Global,
+    /// Special form or core function
+    Builtin,
+}
+
+/// Prints the expression as source code, which reads back as the same expression.
+impl Display for ExpressionNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let list = |f: &mut Formatter<'_>, open: &str, items: &ExpressionList, close: &str| {
+            f.write_str(open)?;
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(" ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            f.write_str(close)
+        };
+        match self {
+            ExpressionNode::Empty => Ok(()),
+            ExpressionNode::BooleanLiteral(value, _) => write!(f, "{}", value),
+            ExpressionNode::IntegerNumberLiteral(value, _) => write!(f, "{}", value),
+            // the scanner reads no exponent, so the digits are written out, with a
+            // fraction for round numbers, like `1.0`. The parser rejects infinite
+            // literals, which would print as `inf`.
+            ExpressionNode::FloatNumberLiteral(value, _) if value.fract() == 0.0 => {
+                write!(f, "{}.0", value)
+            }
+            ExpressionNode::FloatNumberLiteral(value, _) => write!(f, "{}", value),
+            ExpressionNode::FractionNumberLiteral(numerator, denominator, _) => {
+                write!(f, "{}/{}", numerator, denominator)
+            }
+            // debug format uses the same escapes as the scanner
+            ExpressionNode::StringLiteral(value, _) => write!(f, "{:?}", value),
+            ExpressionNode::Identifier(symbol) => f.write_str(&symbol.name),
+            ExpressionNode::Keyword(name, _) => f.write_str(name),
+            ExpressionNode::FunctionCall(items) => list(f, "(", items, ")"),
+            ExpressionNode::AnonymousFunction(items) => list(f, "#(", items, ")"),
+            ExpressionNode::Array(items) => list(f, "[", items, "]"),
+            ExpressionNode::Map(items) => list(f, "{", items, "}"),
+            ExpressionNode::Set(items) => list(f, "#{", items, "}"),
+            ExpressionNode::Regex(pattern, _) => {
+                write!(f, "#\"{}\"", pattern.replace('"', "\\\""))
+            }
+            ExpressionNode::Quote(form) => write!(f, "'{}", form),
+            ExpressionNode::SyntaxQuote(form) => write!(f, "`{}", form),
+            ExpressionNode::Unquote(form) => write!(f, "~{}", form),
+            ExpressionNode::UnquoteSplicing(form) => write!(f, "~@{}", form),
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, Diagnostic>;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The source ends inside an unclosed form or string, so more input could complete it
+    Incomplete(Vec<Diagnostic>),
+    Invalid(Vec<Diagnostic>),
+}
+
+impl ParseError {
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            ParseError::Incomplete(diagnostics) | ParseError::Invalid(diagnostics) => diagnostics,
+        }
+    }
+}
+
+/// Pattern of a regex literal for the regex syntax, only `\"` is unescaped by the reader.
+fn regex_pattern(raw: &str) -> String {
+    raw.replace("\\\"", "\"")
+}
+
+/// Checks the syntax of a regex pattern. The error has a message and a byte range of the pattern.
+fn check_regex(pattern: &str) -> Result<(), (String, usize, usize)> {
+    let error = match regex_syntax::Parser::new().parse(pattern) {
+        Ok(_) => return Ok(()),
+        Err(error) => error,
+    };
+    let (message, span) = match &error {
+        regex_syntax::Error::Parse(error) => (error.kind().to_string(), *error.span()),
+        regex_syntax::Error::Translate(error) => (error.kind().to_string(), *error.span()),
+        _ => return Err((error.to_string(), 0, pattern.len())),
+    };
+    Err((message, span.start.offset, span.end.offset))
 }
 
 pub struct Parser<'a> {
     scanner: &'a mut Scanner<'a>,
     current: Token<'a>,
     program: Program,
-    had_error: bool,
-    panic_mode: bool,
-    last_error: String,
+    /// Nesting level of the brackets consumed so far, used to find the next top-level form
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
@@ -38,95 +243,153 @@ impl<'a> Parser<'a> {
             current: Token::default(),
             scanner,
             program: vec![],
-            had_error: false,
-            panic_mode: false,
-            last_error: "".to_owned(),
+            depth: 0,
+            diagnostics: vec![],
         }
     }
 
-    pub fn parse(&mut self) -> Result<&Program> {
+    /// Parses the whole source. On failure, it returns every diagnostic found:
+    /// after a syntax error the parser skips to the next top-level form and continues.
+    pub fn parse(&mut self) -> Result<&Program, ParseError> {
         self.advance();
         while !self.is_end() {
-            println!("PARSE {}", self.current.src);
-            // top level expression must be lists
-            let result = self.expression_list(TokenType::LeftParen);
-            if self.had_error {
+            if let Err(diagnostic) = self.discard_forms() {
+                self.diagnostics.push(diagnostic);
+                self.synchronize();
+                continue;
+            }
+            if self.is_end() {
                 break;
             }
-            match result {
+            // top level expression must be lists
+            match self.expression_list(TokenType::LeftParen) {
                 Ok(expression) => self.program.push(expression),
-                Err(error) => {
-                    self.error_at_current(error.to_string().as_str());
-                    break;
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.synchronize();
                 }
             }
         }
-        self.consume(TokenType::Eof, "Expect end of expression.");
-        if self.had_error {
-            Err(Error::msg(format!("{}", self.last_error)))
-        } else {
+        let diagnostics = std::mem::take(&mut self.diagnostics);
+        let incomplete = diagnostics.iter().all(|diagnostic| {
+            matches!(
+                diagnostic.code,
+                ErrorCode::UnexpectedEof
+                    | ErrorCode::UnterminatedString
+                    | ErrorCode::UnterminatedComment
+            )
+        });
+        if diagnostics.is_empty() {
             Ok(&self.program)
+        } else if incomplete {
+            Err(ParseError::Incomplete(diagnostics))
+        } else {
+            Err(ParseError::Invalid(diagnostics))
         }
     }
 
-    fn expression(&mut self) -> Result<ExpressionNode> {
+    fn expression(&mut self) -> ParseResult<ExpressionNode> {
         let token = self.current;
-        println!("EXPRESS {}", token.src);
-        return match token.kind {
+        match token.kind {
             TokenType::True => {
                 self.advance();
-                Ok(ExpressionNode::BooleanLiteral(true))
+                Ok(ExpressionNode::BooleanLiteral(true, token.span))
             }
             TokenType::False => {
                 self.advance();
-                Ok(ExpressionNode::BooleanLiteral(false))
+                Ok(ExpressionNode::BooleanLiteral(false, token.span))
             }
             TokenType::String => {
-                let val = token.src.to_owned();
+                let val = unescape(token.src).expect("String token");
                 self.advance();
-                Ok(ExpressionNode::StringLiteral(val))
+                Ok(ExpressionNode::StringLiteral(val, token.span))
             }
             TokenType::IntegerNumber => {
-                let val = token.src.parse::<i64>().expect("Integer number token");
+                let val = self.number(token.src)?;
                 self.advance();
-                Ok(ExpressionNode::IntegerNumberLiteral(val))
+                Ok(ExpressionNode::IntegerNumberLiteral(val, token.span))
             }
             TokenType::FloatNumber => {
-                let val = token.src.parse::<f64>().expect("Float number token");
+                let val = self.float(token.src)?;
                 self.advance();
-                Ok(ExpressionNode::FloatNumberLiteral(val))
+                Ok(ExpressionNode::FloatNumberLiteral(val, token.span))
             }
             TokenType::FractionNumber => {
-                let val: Vec<i64> = token
+                let val = token
                     .src
-                    .split("/")
-                    .map(|num| num.parse::<i64>().expect("Integer number token "))
-                    .collect();
+                    .split('/')
+                    .map(|num| self.number(num))
+                    .collect::<ParseResult<Vec<i64>>>()?;
                 self.advance();
-                Ok(ExpressionNode::FractionNumberLiteral(val[0], val[1]))
+                Ok(ExpressionNode::FractionNumberLiteral(
+                    val[0], val[1], token.span,
+                ))
             }
             TokenType::Identifier => {
-                let val = token.src.to_owned();
+                let symbol = Symbol::new(token.src, token.span);
                 self.advance();
-                Ok(ExpressionNode::Identifier(val))
+                Ok(ExpressionNode::Identifier(symbol))
             }
             TokenType::Keyword => {
                 let val = token.src.to_owned();
                 self.advance();
-                Ok(ExpressionNode::Keyword(val))
+                Ok(ExpressionNode::Keyword(val, token.span))
+            }
+            TokenType::Regex => {
+                let pattern = regex_pattern(token.src);
+                if let Err((message, start, end)) = check_regex(&pattern) {
+                    // the offsets of the pattern shift by one at every escaped quote
+                    let offset = |position: usize| {
+                        let quotes = pattern[..position].matches('"').count();
+                        token.span.start + 2 + position + quotes
+                    };
+                    return Err(Diagnostic::error(
+                        ErrorCode::InvalidRegex,
+                        Span::new(offset(start), offset(end)),
+                        format!("Invalid regex: {}", message),
+                    ));
+                }
+                self.advance();
+                Ok(ExpressionNode::Regex(pattern, token.span))
             }
             TokenType::Dispatch => {
-                println!("DIS 0");
                 self.advance();
-                println!("DIS 1 {}", self.current.src);
                 match self.peek().kind {
                     TokenType::LeftParen => {
                         let exp = self.expression_list(TokenType::LeftParen)?;
                         Ok(ExpressionNode::AnonymousFunction(exp))
                     }
-                    _ => self.error_unexpected_token(),
+                    TokenType::LeftBrace => {
+                        let exp = self.expression_list(TokenType::LeftBrace)?;
+                        Ok(ExpressionNode::Set(exp))
+                    }
+                    _ => self.error_unexpected_token().map_err(|error| {
+                        error.with_help(
+                            "only anonymous functions `#(...)`, sets `#{...}` and regexes `#\"...\"` can follow `#`",
+                        )
+                    }),
                 }
             }
+            TokenType::Quote => {
+                self.advance();
+                let form = self.form_after("'", "`'` quotes the form after it")?;
+                Ok(ExpressionNode::Quote(Box::new(form)))
+            }
+            TokenType::SyntaxQuote => {
+                self.advance();
+                let form = self.form_after("`", "`` ` `` quotes the form after it")?;
+                Ok(ExpressionNode::SyntaxQuote(Box::new(form)))
+            }
+            TokenType::Unquote => {
+                self.advance();
+                let form = self.form_after("~", "`~` unquotes the form after it")?;
+                Ok(ExpressionNode::Unquote(Box::new(form)))
+            }
+            TokenType::UnquoteSplicing => {
+                self.advance();
+                let form = self.form_after("~@", "`~@` splices the form after it")?;
+                Ok(ExpressionNode::UnquoteSplicing(Box::new(form)))
+            }
             TokenType::LeftParen => {
                 let list = self.expression_list(token.kind)?;
                 Ok(ExpressionNode::FunctionCall(list))
@@ -141,64 +404,106 @@ impl<'a> Parser<'a> {
             }
             TokenType::Eof => Ok(ExpressionNode::Empty),
             _ => self.error_unexpected_token(),
-        };
+        }
+    }
+
+    /// Skips the `#_` reader macros at the current token with the forms they discard.
+    /// In `#_ #_ a b` both `a` and `b` are discarded.
+    fn discard_forms(&mut self) -> ParseResult<()> {
+        while self.current.kind == TokenType::Discard {
+            self.advance();
+            self.form_after("#_", "`#_` discards the form after it")?;
+        }
+        Ok(())
+    }
+
+    /// Parses the form following a reader macro, skipping the discarded forms before it.
+    fn form_after(&mut self, prefix: &str, help: &str) -> ParseResult<ExpressionNode> {
+        self.discard_forms()?;
+        if self.is_end() {
+            return Err(self
+                .error_at_current(
+                    ErrorCode::UnexpectedEof,
+                    format!("Expected a form after {}, but get Eof", prefix),
+                )
+                .with_help(help));
+        }
+        self.expression().map_err(|error| match error.help {
+            Some(_) => error,
+            None => error.with_help(help),
+        })
     }
 
     fn advance(&mut self) {
+        match self.current.kind {
+            TokenType::LeftParen | TokenType::LeftSquare | TokenType::LeftBrace => self.depth += 1,
+            TokenType::RightParen | TokenType::RightSquare | TokenType::RightBrace => {
+                self.depth = self.depth.saturating_sub(1)
+            }
+            _ => {}
+        }
         loop {
             self.current = self.scanner.scan_token();
             if self.current.kind == TokenType::Error {
-                self.error_at_current(self.current.src);
+                // scanner errors are independent of each other, so all of them are reported
+                self.diagnostics
+                    .append(&mut self.scanner.take_diagnostics());
             } else {
                 break;
             }
         }
     }
 
-    fn expression_list(&mut self, start_token: TokenType) -> Result<ExpressionList> {
-        let end_token = match start_token {
-            TokenType::LeftParen => TokenType::RightParen,
-            TokenType::LeftBrace => TokenType::RightBrace,
-            TokenType::LeftSquare => TokenType::RightSquare,
+    /// Skips tokens until the start of the next top-level form.
+    fn synchronize(&mut self) {
+        while !self.is_end() {
+            if self.depth == 0 && self.current.kind == TokenType::LeftParen {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    fn expression_list(&mut self, start_token: TokenType) -> ParseResult<ExpressionList> {
+        let (end_token, closing) = match start_token {
+            TokenType::LeftParen => (TokenType::RightParen, ')'),
+            TokenType::LeftBrace => (TokenType::RightBrace, '}'),
+            TokenType::LeftSquare => (TokenType::RightSquare, ']'),
             _ => panic!("Invalid start token for advance until: {}", start_token),
         };
         let mut items = vec![];
         if self.current.kind != start_token {
-            self.error_at_current(
-                format!("Expected {}, but get {}", start_token, self.current.kind).as_str(),
+            let error = self.error_at_current(
+                ErrorCode::ExpectedToken,
+                format!("Expected {}, but get {}", start_token, self.current.kind),
             );
-            return Ok(items);
+            return Err(if self.depth == 0 {
+                error.with_help("top-level forms must be lists, like `(def x 1)`")
+            } else {
+                error
+            });
         }
         self.advance();
-        println!("HELLO {}", self.current.src);
         loop {
+            self.discard_forms()?;
             if self.current.kind == end_token || self.is_end() {
                 break;
-            } else {
-                println!("EXP");
-                let exp = self.expression()?;
-                items.push(exp)
             }
+            items.push(self.expression()?);
         }
-        if self.current.kind != end_token {
-            self.error_at_current(
-                format!("Expected {}, but get {}", end_token, self.current.kind).as_str(),
-            )
-        } else {
-            self.advance();
+        if self.is_end() {
+            return Err(self
+                .error_at_current(
+                    ErrorCode::UnexpectedEof,
+                    format!("Expected {}, but get {}", end_token, self.current.kind),
+                )
+                .with_help(format!("add the missing `{}`", closing)));
         }
+        self.advance();
         Ok(items)
     }
 
-    fn consume(&mut self, kind: TokenType, message: &str) {
-        if self.current.kind == kind {
-            self.advance()
-        } else if !self.had_error {
-            self.error_at_current(message)
-        }
-    }
-
-    fn peek(&self) -> Token {
+    fn peek(&self) -> Token<'_> {
         self.current
     }
 
@@ -206,45 +511,57 @@ impl<'a> Parser<'a> {
         self.peek().kind == TokenType::Eof
     }
 
-    fn error_at_current(&mut self, message: &str) {
-        self.error_at(self.current, message)
+    /// Value of a number in the current token. The scanner checks the syntax of the
+    /// numbers, but not their range.
+    fn number<T: FromStr>(&self, src: &str) -> ParseResult<T> {
+        src.parse().map_err(|_| self.error_out_of_range())
     }
 
-    fn error_unexpected_token(&mut self) -> Result<ExpressionNode> {
-        Err(Error::msg(format!(
-            "Unexpected token {}",
-            self.current.kind
-        )))
+    /// Floats too large for an f64 parse as infinity, which no literal can print back.
+    fn float(&self, src: &str) -> ParseResult<f64> {
+        let value: f64 = self.number(src)?;
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err(self.error_out_of_range())
+        }
     }
 
-    fn error_at(&mut self, token: Token, message: &str) {
-        if self.panic_mode {
-            return;
-        }
+    fn error_out_of_range(&self) -> Diagnostic {
+        let help = match self.current.kind {
+            TokenType::FractionNumber => {
+                "the numerator and denominator of a fraction must fit in 64 bits"
+            }
+            TokenType::FloatNumber => "floats must fit in a 64-bit float",
+            _ => "integers must fit in 64 bits",
+        };
+        self.error_at_current(
+            ErrorCode::InvalidNumber,
+            format!("Number {} is out of range", self.current.src),
+        )
+        .with_help(help)
+    }
 
-        self.panic_mode = true;
-        let line_prefix = format!("[line {}] Error", token.line);
+    fn error_at_current(&self, code: ErrorCode, message: String) -> Diagnostic {
+        Diagnostic::error(code, self.current.span, message)
+    }
 
-        let token = if token.kind == TokenType::Eof {
-            " at end".to_owned()
-        } else if token.kind == TokenType::Error {
-            format!("{}", token.src)
+    fn error_unexpected_token(&self) -> ParseResult<ExpressionNode> {
+        let code = if self.is_end() {
+            ErrorCode::UnexpectedEof
         } else {
-            format!(" at '{}'", token.src)
+            ErrorCode::UnexpectedToken
         };
-
-        self.had_error = true;
-        self.last_error = format!("{}{}: {}", line_prefix, token, message);
-
-        eprintln!("{}", self.last_error);
+        Err(self.error_at_current(code, format!("Unexpected token {}", self.current.kind)))
     }
 }
 
 #[cfg(test)]
 pub mod tests {
-    use crate::parser::{ExpressionNode, Parser, Program};
+    use crate::diagnostic::{Diagnostic, ErrorCode};
+    use crate::parser::{ExpressionNode, ParseError, Parser};
     use crate::scanner::Scanner;
-    use anyhow::Result;
+    use crate::token::Span;
 
     #[test]
     fn parse_empty_program() {
@@ -259,16 +576,157 @@ pub mod tests {
         let mut scanner = Scanner::new("true false");
         let mut parser = Parser::new(&mut scanner);
 
-        if let Err(error) = parser.parse() {
+        if let Err(ParseError::Invalid(errors)) = parser.parse() {
             assert_eq!(
-                error.to_string(),
-                "[line 1] Error at 'true': Expected LeftParen, but get True"
+                errors,
+                vec![Diagnostic::error(
+                    ErrorCode::ExpectedToken,
+                    Span::new(0, 4),
+                    "Expected LeftParen, but get True"
+                )
+                .with_help("top-level forms must be lists, like `(def x 1)`")]
             );
         } else {
             panic!("Parser must fail");
         };
     }
 
+    #[test]
+    fn parse_reports_every_top_level_error() {
+        let mut scanner = Scanner::new("(a ]) (b) (c #x) (d");
+        let mut parser = Parser::new(&mut scanner);
+
+        let errors = parser.parse().unwrap_err().diagnostics().to_vec();
+
+        assert_eq!(
+            errors,
+            vec![
+                Diagnostic::error(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(3, 4),
+                    "Unexpected token RightSquare"
+                ),
+                Diagnostic::error(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(14, 15),
+                    "Unexpected token Identifier"
+                )
+                .with_help(
+                    "only anonymous functions `#(...)`, sets `#{...}` and regexes `#\"...\"` can follow `#`"
+                ),
+                Diagnostic::error(
+                    ErrorCode::UnexpectedEof,
+                    Span::new(19, 19),
+                    "Expected RightParen, but get Eof"
+                )
+                .with_help("add the missing `)`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_reports_scanner_errors() {
+        let mut scanner = Scanner::new("(a \"\\x\" 1/) (b)");
+        let mut parser = Parser::new(&mut scanner);
+
+        let errors = parser.parse().unwrap_err().diagnostics().to_vec();
+
+        assert_eq!(
+            errors,
+            vec![
+                Diagnostic::error(
+                    ErrorCode::InvalidEscape,
+                    Span::new(3, 7),
+                    "Invalid escape sequence"
+                )
+                .with_help("supported escapes are \\\" \\\\ \\n \\t \\r \\0 and \\u{...}"),
+                Diagnostic::error(
+                    ErrorCode::UnterminatedFraction,
+                    Span::new(8, 9),
+                    "Unterminated fraction number"
+                )
+                .with_help("a fraction needs a denominator, like `1/2`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_incomplete_input() {
+        let cases = [
+            "(",
+            "(+ 1 [2",
+            "(a {",
+            "(a #",
+            "(a \"unterminated",
+            "(a #_",
+            "(a ;# comment",
+        ];
+        for source in cases {
+            let mut scanner = Scanner::new(source);
+            let mut parser = Parser::new(&mut scanner);
+
+            let result = parser.parse();
+            assert!(
+                matches!(result, Err(ParseError::Incomplete(_))),
+                "{} {:?}",
+                source,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn parse_invalid_input_before_end_of_input() {
+        let mut scanner = Scanner::new("(a ]) (b");
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse();
+
+        assert!(matches!(result, Err(ParseError::Invalid(_))));
+    }
+
+    #[test]
+    fn report_numbers_out_of_range() {
+        let source = format!(
+            "(+ 99999999999999999999 1) (def x 1/99999999999999999999) (+ 1{}.0 1)",
+            "0".repeat(309)
+        );
+        let mut scanner = Scanner::new(&source);
+        let mut parser = Parser::new(&mut scanner);
+
+        let codes: Vec<(ErrorCode, Span, Option<String>)> = match parser.parse() {
+            Err(ParseError::Invalid(diagnostics)) => diagnostics
+                .into_iter()
+                .map(|diagnostic| (diagnostic.code, diagnostic.span, diagnostic.help))
+                .collect(),
+            result => panic!("{:?}", result),
+        };
+
+        assert_eq!(
+            codes,
+            vec![
+                (
+                    ErrorCode::InvalidNumber,
+                    Span::new(3, 23),
+                    Some("integers must fit in 64 bits".to_string())
+                ),
+                (
+                    ErrorCode::InvalidNumber,
+                    Span::new(34, 56),
+                    Some(
+                        "the numerator and denominator of a fraction must fit in 64 bits"
+                            .to_string()
+                    )
+                ),
+                (
+                    ErrorCode::InvalidNumber,
+                    Span::new(61, 373),
+                    Some("floats must fit in a 64-bit float".to_string())
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn parse_empty_list() {
         let mut scanner = Scanner::new("()");
@@ -290,8 +748,8 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::BooleanLiteral(true),
-                ExpressionNode::BooleanLiteral(false)
+                ExpressionNode::BooleanLiteral(true, Span::default()),
+                ExpressionNode::BooleanLiteral(false, Span::default())
             ]]
         );
     }
@@ -306,13 +764,13 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::IntegerNumberLiteral(-10),
-                ExpressionNode::IntegerNumberLiteral(-1),
-                ExpressionNode::IntegerNumberLiteral(0),
-                ExpressionNode::IntegerNumberLiteral(1),
-                ExpressionNode::IntegerNumberLiteral(2),
-                ExpressionNode::IntegerNumberLiteral(42),
-                ExpressionNode::IntegerNumberLiteral(1000),
+                ExpressionNode::IntegerNumberLiteral(-10, Span::default()),
+                ExpressionNode::IntegerNumberLiteral(-1, Span::default()),
+                ExpressionNode::IntegerNumberLiteral(0, Span::default()),
+                ExpressionNode::IntegerNumberLiteral(1, Span::default()),
+                ExpressionNode::IntegerNumberLiteral(2, Span::default()),
+                ExpressionNode::IntegerNumberLiteral(42, Span::default()),
+                ExpressionNode::IntegerNumberLiteral(1000, Span::default()),
             ]]
         );
     }
@@ -327,13 +785,13 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::FloatNumberLiteral(-10.0),
-                ExpressionNode::FloatNumberLiteral(-1.1),
-                ExpressionNode::FloatNumberLiteral(0.0),
-                ExpressionNode::FloatNumberLiteral(1.0),
-                ExpressionNode::FloatNumberLiteral(2.5),
-                ExpressionNode::FloatNumberLiteral(42.9999),
-                ExpressionNode::FloatNumberLiteral(1000.110111),
+                ExpressionNode::FloatNumberLiteral(-10.0, Span::default()),
+                ExpressionNode::FloatNumberLiteral(-1.1, Span::default()),
+                ExpressionNode::FloatNumberLiteral(0.0, Span::default()),
+                ExpressionNode::FloatNumberLiteral(1.0, Span::default()),
+                ExpressionNode::FloatNumberLiteral(2.5, Span::default()),
+                ExpressionNode::FloatNumberLiteral(42.9999, Span::default()),
+                ExpressionNode::FloatNumberLiteral(1000.110111, Span::default()),
             ]]
         );
     }
@@ -348,10 +806,10 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::FractionNumberLiteral(-1, 2),
-                ExpressionNode::FractionNumberLiteral(1, 2),
-                ExpressionNode::FractionNumberLiteral(0, 1),
-                ExpressionNode::FractionNumberLiteral(1, 33),
+                ExpressionNode::FractionNumberLiteral(-1, 2, Span::default()),
+                ExpressionNode::FractionNumberLiteral(1, 2, Span::default()),
+                ExpressionNode::FractionNumberLiteral(0, 1, Span::default()),
+                ExpressionNode::FractionNumberLiteral(1, 33, Span::default()),
             ]]
         );
     }
@@ -366,16 +824,33 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::StringLiteral("".to_owned()),
-                ExpressionNode::StringLiteral("Hello world".to_owned()),
-                ExpressionNode::StringLiteral("Meh".to_owned()),
+                ExpressionNode::StringLiteral("".to_owned(), Span::default()),
+                ExpressionNode::StringLiteral("Hello world".to_owned(), Span::default()),
+                ExpressionNode::StringLiteral("Meh".to_owned(), Span::default()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn parse_string_escapes() {
+        let mut scanner = Scanner::new(r#"( "say \"hi\"" "a\nb" "\u{1F600}" )"#);
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse().unwrap();
+
+        assert_eq!(
+            *result,
+            vec![vec![
+                ExpressionNode::StringLiteral("say \"hi\"".to_owned(), Span::default()),
+                ExpressionNode::StringLiteral("a\nb".to_owned(), Span::default()),
+                ExpressionNode::StringLiteral("\u{1F600}".to_owned(), Span::default()),
             ]]
         );
     }
 
     #[test]
     fn parse_identifier() {
-        let mut scanner = Scanner::new("( x _x 'x x2 ?when do * / )");
+        let mut scanner = Scanner::new("( x _x x2 ?when do * / )");
         let mut parser = Parser::new(&mut scanner);
 
         let result = parser.parse().unwrap();
@@ -383,14 +858,13 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::Identifier("x".to_owned()),
-                ExpressionNode::Identifier("_x".to_owned()),
-                ExpressionNode::Identifier("'x".to_owned()),
-                ExpressionNode::Identifier("x2".to_owned()),
-                ExpressionNode::Identifier("?when".to_owned()),
-                ExpressionNode::Identifier("do".to_owned()),
-                ExpressionNode::Identifier("*".to_owned()),
-                ExpressionNode::Identifier("/".to_owned()),
+                ExpressionNode::Identifier("x".into()),
+                ExpressionNode::Identifier("_x".into()),
+                ExpressionNode::Identifier("x2".into()),
+                ExpressionNode::Identifier("?when".into()),
+                ExpressionNode::Identifier("do".into()),
+                ExpressionNode::Identifier("*".into()),
+                ExpressionNode::Identifier("/".into()),
             ]]
         );
     }
@@ -405,10 +879,10 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![
-                ExpressionNode::Keyword(":hello".to_owned()),
-                ExpressionNode::Keyword(":12".to_owned()),
-                ExpressionNode::Keyword(":x1".to_owned()),
-                ExpressionNode::Keyword(":when".to_owned()),
+                ExpressionNode::Keyword(":hello".to_owned(), Span::default()),
+                ExpressionNode::Keyword(":12".to_owned(), Span::default()),
+                ExpressionNode::Keyword(":x1".to_owned(), Span::default()),
+                ExpressionNode::Keyword(":when".to_owned(), Span::default()),
             ]]
         );
     }
@@ -443,13 +917,212 @@ pub mod tests {
         assert_eq!(
             *result,
             vec![vec![ExpressionNode::AnonymousFunction(vec![
-                ExpressionNode::Identifier("+".to_owned()),
-                ExpressionNode::Identifier("%1".to_owned()),
-                ExpressionNode::IntegerNumberLiteral(2)
+                ExpressionNode::Identifier("+".into()),
+                ExpressionNode::Identifier("%1".into()),
+                ExpressionNode::IntegerNumberLiteral(2, Span::default())
             ]),]]
         );
     }
 
+    #[test]
+    fn parse_discarded_forms() {
+        let mut scanner = Scanner::new("#_(a) (b #_c #_[1 #_2] d #_ #_ e f) #_g");
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse().unwrap();
+
+        assert_eq!(
+            *result,
+            vec![vec![
+                ExpressionNode::Identifier("b".into()),
+                ExpressionNode::Identifier("d".into()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn parse_discard_without_form() {
+        let mut scanner = Scanner::new("(a #_) (b #_");
+        let mut parser = Parser::new(&mut scanner);
+
+        let errors = parser.parse().unwrap_err().diagnostics().to_vec();
+
+        assert_eq!(
+            errors,
+            vec![
+                Diagnostic::error(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(5, 6),
+                    "Unexpected token RightParen"
+                )
+                .with_help("`#_` discards the form after it"),
+                Diagnostic::error(
+                    ErrorCode::UnexpectedEof,
+                    Span::new(12, 12),
+                    "Expected a form after #_, but get Eof"
+                )
+                .with_help("`#_` discards the form after it"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_quoted_forms() {
+        let mut scanner = Scanner::new("('x `(a ~b ~@[c]) '#_y z)");
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse().unwrap();
+
+        let identifier = |name: &str| ExpressionNode::Identifier(name.into());
+        assert_eq!(
+            *result,
+            vec![vec![
+                ExpressionNode::Quote(Box::new(identifier("x"))),
+                ExpressionNode::SyntaxQuote(Box::new(ExpressionNode::FunctionCall(vec![
+                    identifier("a"),
+                    ExpressionNode::Unquote(Box::new(identifier("b"))),
+                    ExpressionNode::UnquoteSplicing(Box::new(ExpressionNode::Array(vec![
+                        identifier("c")
+                    ]))),
+                ]))),
+                ExpressionNode::Quote(Box::new(identifier("z"))),
+            ]]
+        );
+    }
+
+    #[test]
+    fn parse_quote_without_form() {
+        let mut scanner = Scanner::new("(a ') (b ~@");
+        let mut parser = Parser::new(&mut scanner);
+
+        let errors = parser.parse().unwrap_err().diagnostics().to_vec();
+
+        assert_eq!(
+            errors,
+            vec![
+                Diagnostic::error(
+                    ErrorCode::UnexpectedToken,
+                    Span::new(4, 5),
+                    "Unexpected token RightParen"
+                )
+                .with_help("`'` quotes the form after it"),
+                Diagnostic::error(
+                    ErrorCode::UnexpectedEof,
+                    Span::new(11, 11),
+                    "Expected a form after ~@, but get Eof"
+                )
+                .with_help("`~@` splices the form after it"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_set() {
+        let mut scanner = Scanner::new("(#{} #{1 :a #{x}})");
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse().unwrap();
+
+        assert_eq!(
+            *result,
+            vec![vec![
+                ExpressionNode::Set(vec![]),
+                ExpressionNode::Set(vec![
+                    ExpressionNode::IntegerNumberLiteral(1, Span::default()),
+                    ExpressionNode::Keyword(":a".to_owned(), Span::default()),
+                    ExpressionNode::Set(vec![ExpressionNode::Identifier("x".into())]),
+                ]),
+            ]]
+        );
+    }
+
+    #[test]
+    fn parse_regex() {
+        let mut scanner = Scanner::new(r#"(#"\d+" #"say \"(\w+)\"")"#);
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse().unwrap();
+
+        assert_eq!(
+            *result,
+            vec![vec![
+                ExpressionNode::Regex(r"\d+".to_owned(), Span::default()),
+                ExpressionNode::Regex(r#"say "(\w+)""#.to_owned(), Span::default()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_regex() {
+        let mut scanner = Scanner::new(r#"(a #"\"[a-") (b #"x{2,1}")"#);
+        let mut parser = Parser::new(&mut scanner);
+
+        let errors = parser.parse().unwrap_err().diagnostics().to_vec();
+
+        assert_eq!(
+            errors,
+            vec![
+                Diagnostic::error(
+                    ErrorCode::InvalidRegex,
+                    Span::new(7, 8),
+                    "Invalid regex: unclosed character class"
+                ),
+                Diagnostic::error(
+                    ErrorCode::InvalidRegex,
+                    Span::new(19, 24),
+                    "Invalid regex: invalid repetition count range, the start must be <= the end"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn print_expressions() {
+        let source =
+            r#"(f true -1 1.0 1/2 "a\n\"b\"" x :k #(g %) [1] {:a 1} #{2} #"\d\"" '(q `(~a ~@b)))"#;
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+
+        let result = parser.parse().unwrap();
+
+        let printed = ExpressionNode::FunctionCall(result[0].clone()).to_string();
+        assert_eq!(printed, source);
+    }
+
+    #[test]
+    fn print_floats_without_exponent() {
+        for value in [
+            1e20,
+            1.5e-7,
+            -2.5e16,
+            0.1,
+            f64::MAX,
+            f64::MIN,
+            f64::MIN_POSITIVE,
+        ] {
+            let printed = ExpressionNode::FloatNumberLiteral(value, Span::default()).to_string();
+            assert!(!printed.contains('e'), "{}", printed);
+            let source = format!("(+ {})", printed);
+            let mut scanner = Scanner::new(&source);
+            let mut parser = Parser::new(&mut scanner);
+            assert_eq!(
+                parser.parse().unwrap()[0][1],
+                ExpressionNode::FloatNumberLiteral(value, Span::default())
+            );
+        }
+        assert_eq!(
+            ExpressionNode::FloatNumberLiteral(1e20, Span::default()).to_string(),
+            "100000000000000000000.0"
+        );
+
+        // one digit more than the largest float does not read back
+        let largest = ExpressionNode::FloatNumberLiteral(f64::MAX, Span::default()).to_string();
+        let overflow = format!("(+ {})", largest.replacen('.', "0.", 1));
+        let mut scanner = Scanner::new(&overflow);
+        let mut parser = Parser::new(&mut scanner);
+        assert!(parser.parse().is_err());
+    }
+
     #[test]
     fn parse_list_in_list() {
         let mut scanner = Scanner::new("(() ())");
@@ -0,0 +1,460 @@
+use crate::analyzer;
+use crate::codegen;
+use crate::diagnostic::{Diagnostic, DiagnosticRenderer};
+use crate::expander;
+use crate::highlight;
+use crate::parser::{ExpressionList, ExpressionNode, ParseError, Parser, Program};
+use crate::resolver;
+use crate::runtime::invoke_wasm_module;
+use crate::scanner::{is_symbol, Scanner};
+use crate::token::TokenType;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::borrow::Cow;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+
+const REPL_FILE_NAME: &str = "<repl>";
+const HISTORY_FILE_NAME: &str = ".pocket_lisp_history";
+
+pub enum EvalError {
+    /// The input has unclosed forms, the REPL waits for more lines
+    Incomplete,
+    /// Syntax and semantic errors of the input
+    Invalid(Vec<Diagnostic>),
+    Runtime(anyhow::Error),
+}
+
+/// State of a REPL session. Every input is compiled into a fresh module together with
+/// the macros and the definitions of the earlier inputs, so they stay visible for the
+/// later ones. A module cannot reuse the values computed by an earlier one, so every
+/// input runs the earlier definitions again: the work of an input grows with the
+/// number of definitions in the session.
+#[derive(Default)]
+pub struct Session {
+    /// `defmacro` forms, expanded together with every input
+    macros: Program,
+    /// Expanded top-level forms defining globals
+    definitions: Program,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    /// Evaluates the forms of the input and returns the value of the last one.
+    pub fn eval(&mut self, input: &str) -> Result<f32, EvalError> {
+        let mut scanner = Scanner::new(input);
+        let mut parser = Parser::new(&mut scanner);
+        let forms = match parser.parse() {
+            Ok(forms) => forms.clone(),
+            Err(ParseError::Incomplete(_)) => return Err(EvalError::Incomplete),
+            Err(ParseError::Invalid(diagnostics)) => return Err(EvalError::Invalid(diagnostics)),
+        };
+
+        let program: Program = self.macros.iter().chain(&forms).cloned().collect();
+        let expanded = expander::expand(&program)
+            .map_err(|diagnostic| EvalError::Invalid(vec![diagnostic]))?;
+        let mut program: Program = self.definitions.iter().chain(&expanded).cloned().collect();
+        // the definitions of the earlier inputs compiled before, so the errors are in this
+        // input, and the symbols produced by their macros are located at the calls in it
+        resolver::resolve(&mut program).map_err(EvalError::Invalid)?;
+        let bytes = codegen::compile(&program).map_err(EvalError::Invalid)?;
+        let value = invoke_wasm_module(&bytes).map_err(EvalError::Runtime)?;
+
+        // keep the definitions only when the whole input succeeded
+        self.macros.extend(
+            forms
+                .into_iter()
+                .filter(|form| expander::is_macro_definition(form)),
+        );
+        for form in expanded {
+            self.keep_definitions(form);
+        }
+        Ok(value)
+    }
+
+    /// Keeps an expanded form if it defines globals, the forms of a top-level `do` one
+    /// by one. A `def` nested in another form is kept with that form, as its value may
+    /// depend on it.
+    fn keep_definitions(&mut self, form: ExpressionList) {
+        if matches!(form.first(), Some(ExpressionNode::Identifier(symbol)) if symbol.name == "do") {
+            for node in form.into_iter().skip(1) {
+                if let ExpressionNode::FunctionCall(list) = node {
+                    self.keep_definitions(list);
+                }
+            }
+        } else {
+            let mut names = vec![];
+            defined_names(&form, &mut names);
+            if !names.is_empty() {
+                self.definitions.push(form);
+            }
+        }
+    }
+
+    /// Names defined by `def` and `defmacro` forms in the session
+    pub fn defined_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .macros
+            .iter()
+            .filter_map(|form| match form.get(1) {
+                Some(ExpressionNode::Identifier(symbol)) => Some(symbol.name.clone()),
+                _ => None,
+            })
+            .collect();
+        for form in &self.definitions {
+            defined_names(form, &mut names);
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// Adds the names of the `def` forms in the expanded form and its nested forms.
+fn defined_names(form: &[ExpressionNode], names: &mut Vec<String>) {
+    if let [ExpressionNode::Identifier(head), ExpressionNode::Identifier(name), ..] = form {
+        if head.name == "def" {
+            names.push(name.name.clone());
+        }
+    }
+    for node in form {
+        match node {
+            ExpressionNode::FunctionCall(list)
+            | ExpressionNode::AnonymousFunction(list)
+            | ExpressionNode::Array(list)
+            | ExpressionNode::Map(list)
+            | ExpressionNode::Set(list) => defined_names(list, names),
+            _ => {}
+        }
+    }
+}
+
+/// Line editor helper: completes identifiers, colours the syntax and emphasizes the
+/// bracket matching the one next to the cursor.
+#[derive(Default)]
+struct ReplHelper {
+    names: Vec<String>,
+}
+
+impl ReplHelper {
+    fn candidates(&self, prefix: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = analyzer::BUILTINS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.names.iter().cloned())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        Ok((start, self.candidates(&line[start..pos])))
+    }
+}
+
+/// Byte offset of the identifier characters right before the cursor.
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .char_indices()
+        .rev()
+        .find(|(_, ch)| !(ch.is_ascii_alphanumeric() || u8::try_from(*ch).is_ok_and(is_symbol)))
+        .map_or(0, |(index, ch)| index + ch.len_utf8())
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        Cow::Owned(highlight::to_ansi(line, matching_bracket(line, pos)))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        // every change can affect the colours of the line
+        true
+    }
+}
+
+/// Byte offset of the bracket matching the one before or under the cursor. Brackets
+/// in strings, regexes and comments are not counted.
+fn matching_bracket(line: &str, pos: usize) -> Option<usize> {
+    let mut scanner = Scanner::new(line);
+    let mut brackets = vec![];
+    loop {
+        let token = scanner.scan_token();
+        match token.kind {
+            TokenType::Eof => break,
+            TokenType::LeftParen
+            | TokenType::RightParen
+            | TokenType::LeftSquare
+            | TokenType::RightSquare
+            | TokenType::LeftBrace
+            | TokenType::RightBrace => brackets.push((token.span.start, token.kind)),
+            _ => {}
+        }
+    }
+    let index = [pos.checked_sub(1), Some(pos)]
+        .into_iter()
+        .flatten()
+        .find_map(|at| brackets.iter().position(|(start, _)| *start == at))?;
+    let (open, close, forward) = match brackets[index].1 {
+        TokenType::LeftParen => (TokenType::LeftParen, TokenType::RightParen, true),
+        TokenType::LeftSquare => (TokenType::LeftSquare, TokenType::RightSquare, true),
+        TokenType::LeftBrace => (TokenType::LeftBrace, TokenType::RightBrace, true),
+        TokenType::RightParen => (TokenType::LeftParen, TokenType::RightParen, false),
+        TokenType::RightSquare => (TokenType::LeftSquare, TokenType::RightSquare, false),
+        _ => (TokenType::LeftBrace, TokenType::RightBrace, false),
+    };
+    let mut depth = 0;
+    let mut check = |index: &usize| {
+        match brackets[*index].1 {
+            kind if kind == open => depth += 1,
+            kind if kind == close => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    };
+    let partner = if forward {
+        (index..brackets.len()).find(&mut check)
+    } else {
+        (0..=index).rev().find(&mut check)
+    };
+    partner.map(|partner| brackets[partner].0)
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::home_dir().map(|home| home.join(HISTORY_FILE_NAME))
+}
+
+pub fn start() {
+    let mut editor = Editor::<ReplHelper>::new();
+    editor.set_helper(Some(ReplHelper::default()));
+    let history = history_path();
+    if let Some(path) = &history {
+        // there is no history before the first session
+        let _ = editor.load_history(path);
+    }
+
+    let mut session = Session::new();
+    let mut input = String::new();
+    loop {
+        // continuation prompt while the forms of the input are not closed
+        let prompt = if input.is_empty() { "> " } else { ".. " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(_) => break,
+        };
+        if input.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+        input.push_str(&line);
+        input.push('\n');
+        match session.eval(&input) {
+            Ok(value) => println!("{}", value),
+            Err(EvalError::Incomplete) => continue,
+            Err(EvalError::Invalid(diagnostics)) => {
+                let renderer =
+                    DiagnosticRenderer::new(REPL_FILE_NAME, &input, io::stderr().is_terminal());
+                for diagnostic in diagnostics {
+                    eprintln!("{}", renderer.render(&diagnostic));
+                }
+            }
+            Err(EvalError::Runtime(error)) => eprintln!("Error: {}", error),
+        }
+        input.clear();
+        if let Some(helper) = editor.helper_mut() {
+            helper.names = session.defined_names();
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(error) = editor.save_history(path) {
+            eprintln!("Could not save history: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repl::{matching_bracket, word_start, EvalError, ReplHelper, Session};
+    use crate::token::Span;
+
+    fn eval(session: &mut Session, input: &str) -> f32 {
+        match session.eval(input) {
+            Ok(value) => value,
+            Err(EvalError::Incomplete) => panic!("Incomplete input"),
+            Err(EvalError::Invalid(diagnostics)) => panic!("{:?}", diagnostics),
+            Err(EvalError::Runtime(error)) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    fn evaluate_expression() {
+        let mut session = Session::new();
+        assert_eq!(eval(&mut session, "(+ 1 2)"), 3.0);
+        assert_eq!(eval(&mut session, "(* 2 3) (- 10 1)"), 9.0);
+    }
+
+    #[test]
+    fn keep_definitions_between_inputs() {
+        let mut session = Session::new();
+        assert_eq!(eval(&mut session, "(def x 20)"), 20.0);
+        assert_eq!(eval(&mut session, "(def y (+ x 1))"), 21.0);
+        assert_eq!(eval(&mut session, "(+ x y)"), 41.0);
+        assert_eq!(eval(&mut session, "(def x 1)"), 1.0);
+        assert_eq!(eval(&mut session, "(+ x y)"), 22.0);
+    }
+
+    #[test]
+    fn redefine_functions_between_inputs() {
+        let mut session = Session::new();
+        eval(&mut session, "(def g (fn [x] 1)) (def f (fn [x] (g x)))");
+        assert_eq!(eval(&mut session, "(f 0)"), 1.0);
+        eval(&mut session, "(def g (fn [x] (+ x 2)))");
+        assert_eq!(eval(&mut session, "(f 1)"), 3.0);
+        eval(&mut session, "(def g (fn [] 4))");
+        assert!(matches!(session.eval("(f 1)"), Err(EvalError::Runtime(_))));
+        assert_eq!(eval(&mut session, "(g)"), 4.0);
+    }
+
+    #[test]
+    fn keep_macros_between_inputs() {
+        let mut session = Session::new();
+        assert_eq!(
+            eval(&mut session, "(defmacro double [x] `(* 2 ~x)) (def x 3)"),
+            3.0
+        );
+        assert_eq!(eval(&mut session, "(double (+ x 1))"), 8.0);
+    }
+
+    #[test]
+    fn keep_definitions_produced_by_macros_and_do() {
+        let mut session = Session::new();
+        eval(&mut session, "(defmacro defn [n a b] `(def ~n (fn ~a ~b)))");
+        eval(&mut session, "(defn sq [x] (* x x))");
+        assert_eq!(eval(&mut session, "(sq 3)"), 9.0);
+        eval(&mut session, "(do (def y 4) (sq 5))");
+        assert_eq!(eval(&mut session, "(+ y 0)"), 4.0);
+        eval(&mut session, "(let [a 2] (def z (* a 3)))");
+        assert_eq!(eval(&mut session, "(+ z 0)"), 6.0);
+        assert_eq!(session.defined_names(), vec!["defn", "sq", "y", "z"]);
+    }
+
+    #[test]
+    fn drop_definitions_of_failed_inputs() {
+        let mut session = Session::new();
+        assert!(matches!(
+            session.eval("(def x 1) (let [y x] (y))"),
+            Err(EvalError::Runtime(_))
+        ));
+        assert!(matches!(session.eval("(+ x)"), Err(EvalError::Invalid(_))));
+        assert!(matches!(session.eval("(+ 1 ]"), Err(EvalError::Invalid(_))));
+    }
+
+    #[test]
+    fn locate_errors_in_expansions_of_earlier_macros() {
+        let mut session = Session::new();
+        eval(&mut session, "(defmacro m [] `(+ 1 zzzzzz))");
+        match session.eval("(m)") {
+            Err(EvalError::Invalid(diagnostics)) => {
+                assert_eq!(diagnostics[0].span, Span::new(1, 2))
+            }
+            _ => panic!("Expected an unknown identifier"),
+        }
+    }
+
+    #[test]
+    fn call_functions_defined_later_in_the_input() {
+        let mut session = Session::new();
+        eval(
+            &mut session,
+            "(def f (fn [x] (g x))) (def g (fn [x] (* x 2)))",
+        );
+        assert_eq!(eval(&mut session, "(f 4)"), 8.0);
+    }
+
+    #[test]
+    fn wait_for_unclosed_forms() {
+        let mut session = Session::new();
+        assert!(matches!(
+            session.eval("(def x (+ 1\n"),
+            Err(EvalError::Incomplete)
+        ));
+        assert!(matches!(
+            session.eval("(def x (+ 1\n 2\n"),
+            Err(EvalError::Incomplete)
+        ));
+        assert_eq!(eval(&mut session, "(def x (+ 1\n 2\n ))\n"), 3.0);
+    }
+
+    #[test]
+    fn list_defined_names() {
+        let mut session = Session::new();
+        eval(&mut session, "(def y 1) (def x 2)");
+        eval(&mut session, "(def y 3)");
+        assert_eq!(session.defined_names(), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn find_matching_bracket() {
+        assert_eq!(matching_bracket("(+ (f) 1)", 9), Some(0));
+        assert_eq!(matching_bracket("(+ (f) 1)", 3), Some(5));
+        assert_eq!(matching_bracket("[1 {:a 2}]", 0), Some(9));
+        assert_eq!(matching_bracket("(+ 1", 1), None);
+        assert_eq!(matching_bracket("(+ 1)", 2), None);
+        // brackets in strings, regexes and comments are not counted
+        assert_eq!(matching_bracket("(print \")\")", 11), Some(0));
+        assert_eq!(matching_bracket("(print \"(\")", 0), Some(10));
+        assert_eq!(matching_bracket("(f #\"[(]\" 1)", 12), Some(0));
+        assert_eq!(matching_bracket("(f ; (\n1)", 9), Some(0));
+    }
+
+    #[test]
+    fn complete_names() {
+        let helper = ReplHelper {
+            names: vec!["define-me".to_owned(), "x".to_owned()],
+        };
+        assert_eq!(helper.candidates("de"), vec!["def", "define-me"]);
+        assert_eq!(helper.candidates("<"), vec!["<", "<="]);
+        assert_eq!(helper.candidates("z"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn find_word_start() {
+        assert_eq!(word_start("(+ de", 5), 3);
+        assert_eq!(word_start("de", 2), 0);
+        // the word starts after the multibyte character
+        assert_eq!(word_start("(+ é", 5), 5);
+        assert_eq!(word_start("(é+x", 5), 3);
+        assert_eq!(word_start("(é x", 4), 4);
+    }
+}
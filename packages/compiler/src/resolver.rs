@@ -0,0 +1,397 @@
+use crate::analyzer::{is_special_form, BUILTINS};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::parser::{ExpressionNode, Program, Scope, Symbol};
+
+/// Resolves the identifiers of an expanded program and stores their scope in their
+/// symbol. Parameters and `let` and `loop` bindings are visible in the rest of their
+/// form, every `def` is visible in the whole program, so definitions can refer to the
+/// later ones. Both shadow the core functions of the same name, but not the special
+/// forms, which are always special at the head of a list. Quoted forms are data and are
+/// not resolved.
+///
+/// On failure, it returns a diagnostic for every unbound symbol, with the most similar
+/// visible name as a suggestion.
+pub fn resolve(program: &mut Program) -> Result<(), Vec<Diagnostic>> {
+    let mut resolver = Resolver {
+        // the top level is the body of the `run` function
+        frames: vec![Frame::default()],
+        ..Resolver::default()
+    };
+    for form in program.iter() {
+        resolver.collect_globals(form);
+    }
+    for form in program.iter_mut() {
+        resolver.list(form);
+    }
+    if resolver.diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(resolver.diagnostics)
+    }
+}
+
+/// Names bound by a function, in the order of their bindings.
+#[derive(Default)]
+struct Frame {
+    names: Vec<String>,
+    /// `#(...)`, where `%`, `%1`, `%2`, ... and `%&` are the parameters
+    anonymous: bool,
+}
+
+#[derive(Default)]
+struct Resolver {
+    globals: Vec<String>,
+    /// The top level and the enclosing functions, the innermost last
+    frames: Vec<Frame>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn collect_globals(&mut self, form: &[ExpressionNode]) {
+        if let [ExpressionNode::Identifier(head), ExpressionNode::Identifier(name), ..] = form {
+            if head.name == "def" && !self.globals.contains(&name.name) {
+                self.globals.push(name.name.clone());
+            }
+        }
+        for node in form {
+            match node {
+                ExpressionNode::FunctionCall(list)
+                | ExpressionNode::AnonymousFunction(list)
+                | ExpressionNode::Array(list)
+                | ExpressionNode::Map(list)
+                | ExpressionNode::Set(list) => self.collect_globals(list),
+                _ => {}
+            }
+        }
+    }
+
+    fn expression(&mut self, node: &mut ExpressionNode) {
+        match node {
+            ExpressionNode::Identifier(symbol) => self.symbol(symbol),
+            ExpressionNode::FunctionCall(list) => self.list(list),
+            ExpressionNode::AnonymousFunction(list) => {
+                self.frames.push(Frame {
+                    anonymous: true,
+                    ..Frame::default()
+                });
+                self.list(list);
+                self.frames.pop();
+            }
+            ExpressionNode::Array(list) | ExpressionNode::Map(list) | ExpressionNode::Set(list) => {
+                self.expressions(list)
+            }
+            // literals and quoted data
+            _ => {}
+        }
+    }
+
+    fn expressions(&mut self, nodes: &mut [ExpressionNode]) {
+        for node in nodes {
+            self.expression(node);
+        }
+    }
+
+    /// Binds the names of the special forms, the shape of the forms is checked by the analyzer.
+    fn list(&mut self, list: &mut [ExpressionNode]) {
+        let name = match list.first() {
+            Some(ExpressionNode::Identifier(symbol)) => symbol.name.clone(),
+            _ => return self.expressions(list),
+        };
+        match (name.as_str(), &mut list[1..]) {
+            ("def", [ExpressionNode::Identifier(symbol), rest @ ..]) => {
+                symbol.scope = Scope::Global;
+                self.expressions(rest);
+            }
+            ("fn", [ExpressionNode::Array(params), body @ ..]) => {
+                self.frames.push(Frame::default());
+                for param in params.iter_mut() {
+                    self.bind(param);
+                }
+                self.expressions(body);
+                self.frames.pop();
+            }
+            ("let" | "loop", [ExpressionNode::Array(bindings), body @ ..]) => {
+                let scope = self.innermost().names.len();
+                for pair in bindings.chunks_mut(2) {
+                    if let [name, value] = pair {
+                        self.expression(value);
+                        self.bind(name);
+                    }
+                }
+                self.expressions(body);
+                self.innermost().names.truncate(scope);
+            }
+            // the analyzer reports the special forms of an invalid shape
+            (name, rest) if is_special_form(name) => self.expressions(rest),
+            // the head of a call is resolved like any other symbol, so a local or a
+            // `def` with the name of a core function shadows it
+            _ => return self.expressions(list),
+        }
+        if let ExpressionNode::Identifier(symbol) = &mut list[0] {
+            symbol.scope = Scope::Builtin;
+        }
+    }
+
+    /// Adds a parameter or binding name to the innermost function.
+    fn bind(&mut self, node: &mut ExpressionNode) {
+        if let ExpressionNode::Identifier(symbol) = node {
+            symbol.scope = Scope::Local;
+            self.innermost().names.push(symbol.name.clone());
+        }
+    }
+
+    fn innermost(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("Top-level frame")
+    }
+
+    /// Scope of a parameter or binding, if the name has one.
+    fn local(&self, name: &str) -> Option<Scope> {
+        let innermost = self.frames.len() - 1;
+        self.frames
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, frame)| {
+                let bound = frame.names.iter().any(|bound| bound == name)
+                    || frame.anonymous && is_anonymous_param(name);
+                match bound {
+                    true if index == innermost => Some(Scope::Local),
+                    true => Some(Scope::Captured),
+                    false => None,
+                }
+            })
+    }
+
+    fn symbol(&mut self, symbol: &mut Symbol) {
+        symbol.scope = if let Some(scope) = self.local(&symbol.name) {
+            scope
+        } else if self.globals.contains(&symbol.name) {
+            Scope::Global
+        } else if BUILTINS.contains(&symbol.name.as_str()) {
+            Scope::Builtin
+        } else {
+            self.unbound(symbol);
+            Scope::Unresolved
+        };
+    }
+
+    fn unbound(&mut self, symbol: &Symbol) {
+        let visible = self
+            .frames
+            .iter()
+            .flat_map(|frame| &frame.names)
+            .chain(&self.globals)
+            .map(String::as_str)
+            .chain(BUILTINS.iter().copied());
+        let mut diagnostic = Diagnostic::error(
+            ErrorCode::UnboundSymbol,
+            symbol.span,
+            format!("Unbound symbol '{}'", symbol.name),
+        );
+        if let Some(suggestion) = suggestion(&symbol.name, visible) {
+            diagnostic = diagnostic.with_help(format!("did you mean '{}'?", suggestion));
+        }
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+/// The closest name by edit distance, if it is close enough to be a typo: one edit in
+/// names of 2 to 4 characters, two in names of 5 to 7, and so on. A single character
+/// is never a typo of another name.
+fn suggestion<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = (name.chars().count() + 1) / 3;
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Number of inserted, deleted or replaced characters and swapped neighbours between
+/// the names, the optimal string alignment distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first i characters of a and j of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    distances[0] = (0..=b.len()).collect();
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let replace = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = replace
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// Tells whether the name is a parameter of `#(...)`: `%`, `%&` or `%` followed by a
+/// positive index without leading zeros.
+fn is_anonymous_param(name: &str) -> bool {
+    match name.strip_prefix('%') {
+        Some("") | Some("&") => true,
+        Some(index) => !index.starts_with('0') && index.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::ErrorCode;
+    use crate::parser::{ExpressionNode, Parser, Program, Scope};
+    use crate::resolver::{edit_distance, resolve};
+    use crate::scanner::Scanner;
+    use crate::token::Span;
+
+    fn parse(source: &str) -> Program {
+        let mut scanner = Scanner::new(source);
+        let mut parser = Parser::new(&mut scanner);
+        parser.parse().unwrap().clone()
+    }
+
+    /// Name and scope of every identifier, in source order.
+    fn scopes(source: &str) -> Vec<(String, Scope)> {
+        fn collect(nodes: &[ExpressionNode], out: &mut Vec<(String, Scope)>) {
+            for node in nodes {
+                match node {
+                    ExpressionNode::Identifier(symbol) => {
+                        out.push((symbol.name.clone(), symbol.scope))
+                    }
+                    ExpressionNode::FunctionCall(list)
+                    | ExpressionNode::AnonymousFunction(list)
+                    | ExpressionNode::Array(list)
+                    | ExpressionNode::Map(list)
+                    | ExpressionNode::Set(list) => collect(list, out),
+                    _ => {}
+                }
+            }
+        }
+        let mut program = parse(source);
+        resolve(&mut program).unwrap();
+        let mut out = vec![];
+        for form in &program {
+            collect(form, &mut out);
+        }
+        out
+    }
+
+    fn scope_names(source: &str) -> String {
+        scopes(source)
+            .iter()
+            .map(|(name, scope)| format!("{}:{:?}", name, scope))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn resolve_scopes() {
+        assert_eq!(
+            scope_names("(def x 1) (let [y x] (+ x y))"),
+            "def:Builtin x:Global let:Builtin y:Local x:Global +:Builtin x:Global y:Local"
+        );
+        assert_eq!(
+            scope_names("(def f (fn [a] (fn [b] (+ a b))))"),
+            "def:Builtin f:Global fn:Builtin a:Local fn:Builtin b:Local +:Builtin a:Captured b:Local"
+        );
+        assert_eq!(
+            scope_names("(let [x 1 x (+ x 1)] #(+ % x))"),
+            "let:Builtin x:Local x:Local +:Builtin x:Local +:Builtin %:Local x:Captured"
+        );
+    }
+
+    #[test]
+    fn shadow_core_functions() {
+        assert_eq!(
+            scope_names("(let [+ 1] (+ 2))"),
+            "let:Builtin +:Local +:Local"
+        );
+        assert_eq!(
+            scope_names("(def * 1) (* 2) (let [if 1] (if if 2))"),
+            "def:Builtin *:Global *:Global let:Builtin if:Local if:Builtin if:Local"
+        );
+    }
+
+    #[test]
+    fn resolve_later_definitions() {
+        assert_eq!(
+            scope_names("(def f (fn [] (g))) (def g (fn [] 1))"),
+            "def:Builtin f:Global fn:Builtin g:Global def:Builtin g:Global fn:Builtin"
+        );
+    }
+
+    #[test]
+    fn skip_quoted_forms() {
+        assert_eq!(scope_names("(+ 'x `(y ~z))"), "+:Builtin");
+    }
+
+    #[test]
+    fn report_unbound_symbols() {
+        let mut program = parse("(def count 1) (let [total 2] (+ totl cont (foo) y))");
+        let diagnostics = resolve(&mut program).unwrap_err();
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.code, d.span, d.message.as_str(), d.help.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    ErrorCode::UnboundSymbol,
+                    Span::new(32, 36),
+                    "Unbound symbol 'totl'",
+                    Some("did you mean 'total'?")
+                ),
+                (
+                    ErrorCode::UnboundSymbol,
+                    Span::new(37, 41),
+                    "Unbound symbol 'cont'",
+                    Some("did you mean 'count'?")
+                ),
+                (
+                    ErrorCode::UnboundSymbol,
+                    Span::new(43, 46),
+                    "Unbound symbol 'foo'",
+                    None
+                ),
+                (
+                    ErrorCode::UnboundSymbol,
+                    Span::new(48, 49),
+                    "Unbound symbol 'y'",
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_unbound_anonymous_params() {
+        assert_eq!(
+            scope_names("(def f #(+ % %1 %12 %&))"),
+            "def:Builtin f:Global +:Builtin %:Local %1:Local %12:Local %&:Local"
+        );
+        for name in ["%foo", "%0", "%01", "%+1", "%1a"] {
+            let mut program = parse(&format!("(def f #(+ {} 1))", name));
+            let diagnostics = resolve(&mut program).unwrap_err();
+            assert_eq!(diagnostics[0].code, ErrorCode::UnboundSymbol, "{}", name);
+            assert_eq!(diagnostics[0].message, format!("Unbound symbol '{}'", name));
+        }
+    }
+
+    #[test]
+    fn measure_edit_distance() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("totl", "total"), 1);
+        assert_eq!(edit_distance("coutn", "count"), 1);
+        assert_eq!(edit_distance("lett", "let"), 1);
+        assert_eq!(edit_distance("x", "x"), 0);
+    }
+}
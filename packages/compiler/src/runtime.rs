@@ -0,0 +1,28 @@
+use crate::codegen::{RuntimeError, ERROR_EXPORT, RUN_EXPORT};
+use anyhow::{bail, Result};
+use wasmtime::{Engine, Instance, Module, Store, TrapCode};
+
+/// Instantiates a compiled module and returns the result of its `run` export.
+/// A trap raised by a check of the generated code or by a stack overflow is reported with
+/// its cause, other traps with their reason only, without the backtrace.
+pub fn invoke_wasm_module(bytes: &[u8]) -> Result<f32> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, bytes)?;
+    let mut store = Store::new(&engine, ());
+    let instance = Instance::new(&mut store, &module, &[])?;
+    let exported_run = instance.get_typed_func::<(), f32, _>(&mut store, RUN_EXPORT)?;
+    let trap = match exported_run.call(&mut store, ()) {
+        Ok(value) => return Ok(value),
+        Err(trap) => trap,
+    };
+    let code = instance
+        .get_global(&mut store, ERROR_EXPORT)
+        .and_then(|global| global.get(&mut store).i32());
+    if let Some(error) = code.and_then(RuntimeError::from_code) {
+        bail!("{}", error.message());
+    }
+    if trap.trap_code() == Some(TrapCode::StackOverflow) {
+        bail!("{}", RuntimeError::StackOverflow.message());
+    }
+    bail!("{}", trap.display_reason())
+}
@@ -1,39 +1,105 @@
-use crate::token::TokenType::String;
-use crate::token::{Token, TokenType};
+use crate::diagnostic::{Diagnostic, ErrorCode};
+use crate::token::{Span, Token, TokenType, Trivia, TriviaKind};
 
+/// Splits the source into tokens. It works directly on the UTF-8 bytes of the source,
+/// so every token span is a valid byte range of the original text.
 pub struct Scanner<'a> {
     source: &'a str,
-    source_len: usize,
-    chars: Vec<char>,
+    bytes: &'a [u8],
     start: usize,
     current: usize,
     line: usize,
+    /// Column of the character at `current`, counted in characters
+    column: usize,
+    start_line: usize,
+    start_column: usize,
+    diagnostics: Vec<Diagnostic>,
+    /// Skipped whitespace, commas and comments, collected only in trivia mode
+    trivia: Option<Vec<Trivia<'a>>>,
 }
 
-fn is_symbol(ch: char) -> bool {
-    matches!(
-        ch,
-        '=' | '+' | '-' | '*' | '/' | '\\' | '&' | '%' | '$' | '_' | '!' | '<' | '>' | '?' | '\''
-    )
+pub fn is_symbol(ch: u8) -> bool {
+    b"=+-*/\\&%$_!<>?".contains(&ch)
+}
+
+/// Decodes the escape sequences of a string literal body (without the quotes).
+///
+/// Supported escapes: `\"`, `\\`, `\n`, `\t`, `\r`, `\0` and `\u{XXXX}` with 1-6 hex digits.
+pub fn unescape(raw: &str) -> Result<String, &'static str> {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('u') => result.push(unescape_unicode(&mut chars)?),
+            Some(_) => return Err("Invalid escape sequence"),
+            None => return Err("Unterminated escape sequence"),
+        }
+    }
+    Ok(result)
+}
+
+fn unescape_unicode(chars: &mut std::str::Chars) -> Result<char, &'static str> {
+    if chars.next() != Some('{') {
+        return Err("Invalid unicode escape, expected '{'");
+    }
+    let mut hex = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(ch) if ch.is_ascii_hexdigit() && hex.len() < 6 => hex.push(ch),
+            _ => return Err("Invalid unicode escape"),
+        }
+    }
+    u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(char::from_u32)
+        .ok_or("Invalid unicode escape")
 }
 
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
-        let chars = source.chars().collect::<Vec<char>>();
-        let source_len = chars.len();
         Scanner {
             source,
-            chars,
-            source_len,
+            bytes: source.as_bytes(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+            diagnostics: vec![],
+            trivia: None,
+        }
+    }
+
+    /// Creates a scanner which keeps the skipped whitespace, commas and comments,
+    /// see [`Scanner::take_trivia`].
+    pub fn with_trivia(source: &'a str) -> Self {
+        Scanner {
+            trivia: Some(vec![]),
+            ..Scanner::new(source)
         }
     }
 
     pub fn scan_token(&mut self) -> Token<'a> {
-        self.skip_whitespace();
-        self.start = self.current;
+        if !self.skip_whitespace() {
+            return self.error_token(
+                ErrorCode::UnterminatedComment,
+                "Unterminated block comment",
+                Some("close the comment with `#;`, nested comments need their own `#;`"),
+            );
+        }
+        self.mark_start();
 
         if self.is_at_end() {
             return self.make_token(TokenType::Eof);
@@ -41,112 +107,236 @@ impl<'a> Scanner<'a> {
 
         let c = self.advance();
 
-        return match c {
-            ':' => self.keyword(),
-            c if c.is_digit(10) || c == '-' && self.peek().is_digit(10) => self.number(),
+        match c {
+            b':' => self.keyword(),
+            c if c.is_ascii_digit() || c == b'-' && self.peek().is_ascii_digit() => self.number(),
             c if c.is_ascii_alphanumeric() || is_symbol(c) => self.identifier(),
-            '"' => self.string(),
-            '(' => self.make_token(TokenType::LeftParen),
-            ')' => self.make_token(TokenType::RightParen),
-            '{' => self.make_token(TokenType::LeftBrace),
-            '}' => self.make_token(TokenType::RightBrace),
-            '[' => self.make_token(TokenType::LeftSquare),
-            ']' => self.make_token(TokenType::RightSquare),
-            '#' => self.make_token(TokenType::Dispatch),
-
+            b'"' => self.string(),
+            b'(' => self.make_token(TokenType::LeftParen),
+            b')' => self.make_token(TokenType::RightParen),
+            b'{' => self.make_token(TokenType::LeftBrace),
+            b'}' => self.make_token(TokenType::RightBrace),
+            b'[' => self.make_token(TokenType::LeftSquare),
+            b']' => self.make_token(TokenType::RightSquare),
+            b'#' if self.peek() == b'"' => {
+                self.advance();
+                self.regex()
+            }
+            b'#' if self.peek() == b'_' => {
+                self.advance();
+                self.make_token(TokenType::Discard)
+            }
+            b'#' => self.make_token(TokenType::Dispatch),
+            b'\'' => self.make_token(TokenType::Quote),
+            b'`' => self.make_token(TokenType::SyntaxQuote),
+            b'~' if self.peek() == b'@' => {
+                self.advance();
+                self.make_token(TokenType::UnquoteSplicing)
+            }
+            b'~' => self.make_token(TokenType::Unquote),
             _ => {
-                println!("SCANNER {}", c);
-                self.error_token("Unexpected character.")
+                // consume the rest of a multi-byte character to keep the span on char boundaries
+                while !self.source.is_char_boundary(self.current) {
+                    self.advance();
+                }
+                self.error_token(
+                    ErrorCode::UnexpectedCharacter,
+                    "Unexpected character.",
+                    None,
+                )
             }
-        };
+        }
     }
 
-    fn advance(&mut self) -> char {
+    fn advance(&mut self) -> u8 {
+        let ch = self.bytes[self.current];
         self.current += 1;
-        self.chars[self.current - 1]
+        if ch == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if ch & 0xC0 != 0x80 {
+            // continuation bytes of a multi-byte character do not start a new column
+            self.column += 1;
+        }
+        ch
     }
 
     fn advance_while_digits(&mut self) {
-        while !self.is_at_end() && self.peek().is_digit(10) {
+        while !self.is_at_end() && self.peek().is_ascii_digit() {
             self.advance();
         }
     }
 
-    fn peek(&mut self) -> char {
-        self.chars[self.current]
+    fn peek(&self) -> u8 {
+        self.bytes.get(self.current).copied().unwrap_or(b'\0')
     }
 
-    fn peek_next(&mut self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.chars[self.current + 1]
-        }
+    fn peek_next(&self) -> u8 {
+        self.bytes.get(self.current + 1).copied().unwrap_or(b'\0')
+    }
+
+    /// Sets the start of the next token or trivia to the current position.
+    fn mark_start(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace, commas and comments. Returns false at an unterminated block
+    /// comment, which is then between `start` and the end of the source.
+    fn skip_whitespace(&mut self) -> bool {
         while !self.is_at_end() {
-            match self.peek() {
-                '\n' => {
-                    self.line += 1;
-                    self.advance();
+            self.mark_start();
+            let kind = match self.peek() {
+                b' ' | b'\n' | b'\r' | b'\t' => {
+                    while matches!(self.peek(), b' ' | b'\n' | b'\r' | b'\t') {
+                        self.advance();
+                    }
+                    TriviaKind::Whitespace
                 }
-                ' ' | '\r' | '\t' | ',' => {
+                b',' => {
                     self.advance();
+                    TriviaKind::Comma
+                }
+                b';' if self.peek_next() == b'#' => {
+                    if !self.block_comment() {
+                        return false;
+                    }
+                    TriviaKind::BlockComment
                 }
-                ';' => {
-                    if self.peek_next() == '#' {
-                        self.advance(); // ;
-                        self.advance(); // #
-                        while !self.is_at_end() && self.peek() != '#' && self.peek_next() != ';' {
-                            self.advance();
-                        }
-                        self.advance(); // #
-                        self.advance(); // ;
-                    } else {
-                        while !self.is_at_end() && self.peek() != '\n' {
-                            self.advance();
-                        }
+                b';' => {
+                    while !self.is_at_end() && self.peek() != b'\n' {
+                        self.advance();
                     }
+                    TriviaKind::LineComment
+                }
+                _ => return true,
+            };
+            if let Some(trivia) = &mut self.trivia {
+                trivia.push(Trivia {
+                    kind,
+                    span: Span::new(self.start, self.current),
+                    text: &self.source[self.start..self.current],
+                });
+            }
+        }
+        true
+    }
+
+    /// Skips a `;# ... #;` comment, which ends at the `#;` matching its `;#`.
+    /// Returns false when the source ends before that.
+    fn block_comment(&mut self) -> bool {
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match (self.peek(), self.peek_next()) {
+                (b';', b'#') => depth += 1,
+                (b'#', b';') => depth -= 1,
+                _ => {
+                    self.advance();
+                    continue;
                 }
-                _ => return,
+            }
+            self.advance();
+            self.advance();
+            if depth == 0 {
+                return true;
             }
         }
+        false
+    }
+
+    /// Returns and clears the trivia skipped since the last call. In trivia mode it is
+    /// called after each token to get the trivia preceding it, otherwise it is empty.
+    pub fn take_trivia(&mut self) -> Vec<Trivia<'a>> {
+        self.trivia.as_mut().map(std::mem::take).unwrap_or_default()
     }
 
     #[inline]
     fn is_at_end(&self) -> bool {
-        self.current == self.source_len
+        self.current >= self.bytes.len()
     }
 
     fn make_token(&self, token_type: TokenType) -> Token<'a> {
+        self.make_token_with_src(token_type, &self.source[self.start..self.current])
+    }
+
+    fn make_token_with_src(&self, token_type: TokenType, src: &'a str) -> Token<'a> {
         Token::new(
             token_type,
-            self.start,
-            &self.source[self.start..self.current],
-            self.line,
+            Span::new(self.start, self.current),
+            src,
+            self.start_line,
+            self.start_column,
         )
     }
 
-    fn error_token(&self, msg: &'static str) -> Token<'a> {
-        Token::new(TokenType::Error, 0, msg, self.line)
+    /// Returns and clears the diagnostics of the error tokens scanned so far.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
     }
 
-    fn string(&mut self) -> Token<'a> {
-        self.start = self.current;
-        while !self.is_at_end() && self.peek() != '"' {
-            // todo add string escape?
-            if self.peek() == '\n' {
-                self.line += 1
+    fn error_token(
+        &mut self,
+        code: ErrorCode,
+        msg: &'static str,
+        help: Option<&'static str>,
+    ) -> Token<'a> {
+        let token = self.make_token_with_src(TokenType::Error, msg);
+        let diagnostic = Diagnostic::error(code, token.span, msg);
+        self.diagnostics.push(match help {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        });
+        token
+    }
+
+    /// Skips the rest of a string or regex literal with its closing quote.
+    /// Returns false when the source ends before the closing quote.
+    fn skip_string_body(&mut self) -> bool {
+        while !self.is_at_end() && self.peek() != b'"' {
+            // skip the backslash so an escaped `\"` does not terminate the string
+            if self.peek() == b'\\' && self.current + 1 < self.bytes.len() {
+                self.advance();
             }
             self.advance();
         }
         if self.is_at_end() {
-            self.error_token("Unterminated string")
-        } else {
-            let token = self.make_token(TokenType::String);
-            self.advance();
-            token
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    /// Regex literal, its escapes are left for the regex syntax, checked by the parser.
+    fn regex(&mut self) -> Token<'a> {
+        if !self.skip_string_body() {
+            return self.error_token(
+                ErrorCode::UnterminatedString,
+                "Unterminated regex",
+                Some("add a closing `\"` to the end of the regex"),
+            );
+        }
+        let pattern = &self.source[self.start + 2..self.current - 1];
+        self.make_token_with_src(TokenType::Regex, pattern)
+    }
+
+    fn string(&mut self) -> Token<'a> {
+        if !self.skip_string_body() {
+            return self.error_token(
+                ErrorCode::UnterminatedString,
+                "Unterminated string",
+                Some("add a closing `\"` to the end of the string"),
+            );
+        }
+        // the token span covers the quotes, but its source is only the string body
+        let body = &self.source[self.start + 1..self.current - 1];
+        match unescape(body) {
+            Ok(_) => self.make_token_with_src(TokenType::String, body),
+            Err(msg) => self.error_token(
+                ErrorCode::InvalidEscape,
+                msg,
+                Some("supported escapes are \\\" \\\\ \\n \\t \\r \\0 and \\u{...}"),
+            ),
         }
     }
 
@@ -155,14 +345,18 @@ impl<'a> Scanner<'a> {
 
         if !self.is_at_end() {
             return match self.peek() {
-                '.' => {
+                b'.' => {
                     self.advance();
                     self.advance_while_digits();
                     self.make_token(TokenType::FloatNumber)
                 }
-                '/' => {
-                    if !self.peek_next().is_digit(10) {
-                        return self.error_token("Unterminated fraction number");
+                b'/' => {
+                    if !self.peek_next().is_ascii_digit() {
+                        return self.error_token(
+                            ErrorCode::UnterminatedFraction,
+                            "Unterminated fraction number",
+                            Some("a fraction needs a denominator, like `1/2`"),
+                        );
                     }
                     self.advance();
                     self.advance_while_digits();
@@ -176,7 +370,12 @@ impl<'a> Scanner<'a> {
     }
 
     fn identifier(&mut self) -> Token<'a> {
-        while !self.is_at_end() && (self.peek().is_ascii_alphanumeric() || is_symbol(self.peek())) {
+        // `#` is allowed after the first character, like in the auto-gensym `x#` of macros
+        while !self.is_at_end()
+            && (self.peek().is_ascii_alphanumeric()
+                || is_symbol(self.peek())
+                || self.peek() == b'#')
+        {
             self.advance();
         }
         let token = self.identifier_type();
@@ -184,34 +383,28 @@ impl<'a> Scanner<'a> {
     }
 
     fn keyword(&mut self) -> Token<'a> {
-        let peek = self.peek();
-        if self.is_at_end() || !peek.is_ascii_alphanumeric() {
+        if self.is_at_end() || !self.peek().is_ascii_alphanumeric() {
             return self.make_token(TokenType::Identifier);
         }
         while !self.is_at_end() && self.peek().is_ascii_alphanumeric() {
             self.advance();
         }
-        return self.make_token(TokenType::Keyword);
+        self.make_token(TokenType::Keyword)
     }
 
     fn identifier_type(&mut self) -> TokenType {
-        match self.chars[self.start] {
-            _ if self.check_keyword(0, 5, "false") => TokenType::False,
-            _ if self.check_keyword(0, 4, "true") => TokenType::True,
+        match &self.source[self.start..self.current] {
+            "false" => TokenType::False,
+            "true" => TokenType::True,
             _ => TokenType::Identifier,
         }
     }
-
-    fn check_keyword(&self, start: usize, len: usize, rest: &str) -> bool {
-        self.current - self.start == start + len
-            && self.source[self.start + start..self.current] == *rest
-    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::scanner::Scanner;
-    use crate::token::TokenType;
+    use crate::scanner::{unescape, Scanner};
+    use crate::token::{Span, TokenType, TriviaKind};
 
     fn test_tokens(scanner: &mut Scanner, values: Vec<&str>, tokens: Vec<TokenType>) {
         for i in 0..values.len() {
@@ -231,7 +424,7 @@ mod tests {
 
     #[test]
     fn scan_unexpected_character() {
-        let source = "~";
+        let source = "@";
         let mut scanner = Scanner::new(source);
 
         let result = scanner.scan_token();
@@ -258,15 +451,71 @@ mod tests {
         assert_eq!(result.kind, TokenType::Eof);
     }
 
+    #[test]
+    fn scan_nested_multiline_comment() {
+        let source = ";# outer ;# inner #; # ; still outer #;x";
+        let mut scanner = Scanner::with_trivia(source);
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Identifier);
+        assert_eq!(result.src, "x");
+        assert_eq!(scanner.take_trivia()[0].span, Span::new(0, 39));
+    }
+
+    #[test]
+    fn scan_unterminated_multiline_comment() {
+        for source in ["(a) ;#", "(a) ;# ;# nested #;", "(a) ;# #"] {
+            let mut scanner = Scanner::new(source);
+            for _ in 0..3 {
+                scanner.scan_token();
+            }
+
+            let result = scanner.scan_token();
+            assert_eq!(result.kind, TokenType::Error, "{}", source);
+            assert_eq!(result.src, "Unterminated block comment");
+            assert_eq!(result.span, Span::new(4, source.len()));
+
+            let result = scanner.scan_token();
+            assert_eq!(result.kind, TokenType::Eof);
+        }
+    }
+
+    #[test]
+    fn scan_trivia() {
+        let source = " ,; line\n;# block #;\tx";
+        let mut scanner = Scanner::with_trivia(source);
+
+        let token = scanner.scan_token();
+        let trivia: Vec<_> = scanner
+            .take_trivia()
+            .into_iter()
+            .map(|trivia| (trivia.kind, trivia.text))
+            .collect();
+
+        assert_eq!(token.src, "x");
+        assert_eq!(
+            trivia,
+            vec![
+                (TriviaKind::Whitespace, " "),
+                (TriviaKind::Comma, ","),
+                (TriviaKind::LineComment, "; line"),
+                (TriviaKind::Whitespace, "\n"),
+                (TriviaKind::BlockComment, ";# block #;"),
+                (TriviaKind::Whitespace, "\t"),
+            ]
+        );
+        assert!(scanner.take_trivia().is_empty());
+        assert!(Scanner::new(source).take_trivia().is_empty());
+    }
+
     #[test]
     fn scan_identifier() {
         let ids = vec![
             "x1", "_", "_a", "hello", "=", "+", "-", "*", "/", "\\", "&", "%", "$", "_", "!", "<",
-            ">", "?", "'",
+            ">", "?", "x#", "a#b",
         ];
-        let tokens: Vec<TokenType> = std::iter::repeat(TokenType::Identifier)
-            .take(ids.len())
-            .collect();
+        let tokens: Vec<TokenType> =
+            std::iter::repeat_n(TokenType::Identifier, ids.len()).collect();
         let source = ids.join(" ");
         let mut scanner = Scanner::new(source.as_str());
 
@@ -275,12 +524,35 @@ mod tests {
         assert_eq!(result.kind, TokenType::Eof);
     }
 
+    #[test]
+    fn scan_quoted_identifier() {
+        use TokenType::*;
+        let mut scanner = Scanner::new("'x `(a ~b ~@c)");
+
+        test_tokens(
+            &mut scanner,
+            vec!["'", "x", "`", "(", "a", "~", "b", "~@", "c", ")"],
+            vec![
+                Quote,
+                Identifier,
+                SyntaxQuote,
+                LeftParen,
+                Identifier,
+                Unquote,
+                Identifier,
+                UnquoteSplicing,
+                Identifier,
+                RightParen,
+            ],
+        );
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Eof);
+    }
+
     #[test]
     fn scan_keyword() {
         let ids = vec![":keyword", ":120", ":0Hello"];
-        let tokens: Vec<TokenType> = std::iter::repeat(TokenType::Keyword)
-            .take(ids.len())
-            .collect();
+        let tokens: Vec<TokenType> = std::iter::repeat_n(TokenType::Keyword, ids.len()).collect();
         let source = ids.join(" ");
         let mut scanner = Scanner::new(source.as_str());
 
@@ -305,7 +577,7 @@ mod tests {
     #[test]
     fn scan_symbols() {
         use TokenType::*;
-        let ids = vec!["(", ")", "{", "}", "[", "]", "#"];
+        let ids = vec!["(", ")", "{", "}", "[", "]", "#", "#_", "'", "`", "~", "~@"];
         let tokens = vec![
             LeftParen,
             RightParen,
@@ -314,6 +586,11 @@ mod tests {
             LeftSquare,
             RightSquare,
             Dispatch,
+            Discard,
+            Quote,
+            SyntaxQuote,
+            Unquote,
+            UnquoteSplicing,
         ];
         let source = ids.join(" ");
         let mut scanner = Scanner::new(source.as_str());
@@ -344,8 +621,8 @@ mod tests {
 
     #[test]
     fn scan_string() {
-        let cases = vec!["\"\"", "\"hello world\"", "\"multi\nline\nstring\n\""];
-        let tokens = vec![TokenType::String, TokenType::String, TokenType::String];
+        let cases = ["\"\"", "\"hello world\"", "\"multi\nline\nstring\n\""];
+        let tokens = [TokenType::String, TokenType::String, TokenType::String];
         let source = cases.join(" ");
         let mut scanner = Scanner::new(source.as_str());
 
@@ -360,9 +637,27 @@ mod tests {
         assert_eq!(result.kind, TokenType::Eof);
     }
 
+    #[test]
+    fn scan_regex() {
+        let source = r#"#"\d+\"" #"[a-z"#;
+        let mut scanner = Scanner::new(source);
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Regex);
+        assert_eq!(result.src, r#"\d+\""#);
+        assert_eq!(result.span, Span::new(0, 8));
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Error);
+        assert_eq!(result.src, "Unterminated regex");
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Eof);
+    }
+
     #[test]
     fn scan_invalid_string() {
-        let cases = vec!["\"Invalid string"];
+        let cases = ["\"Invalid string"];
         let source = cases.join(" ");
         let mut scanner = Scanner::new(source.as_str());
 
@@ -374,6 +669,53 @@ mod tests {
         assert_eq!(result.kind, TokenType::Eof);
     }
 
+    #[test]
+    fn scan_string_escapes() {
+        let cases = ["\"\\\"quoted\\\"\"", "\"a\\nb\\tc\\\\\"", "\"\\u{1F600}\""];
+        let source = cases.join(" ");
+        let mut scanner = Scanner::new(source.as_str());
+
+        for case in cases {
+            let result = scanner.scan_token();
+            assert_eq!(result.kind, TokenType::String);
+            assert_eq!(*result.src, case[1..case.len() - 1]); // raw source, escapes kept
+        }
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Eof);
+    }
+
+    #[test]
+    fn scan_invalid_escape() {
+        let cases = [
+            ("\"\\x\"", "Invalid escape sequence"),
+            ("\"\\u1234\"", "Invalid unicode escape, expected '{'"),
+            ("\"\\u{110000}\"", "Invalid unicode escape"),
+            ("\"\\u{}\"", "Invalid unicode escape"),
+            ("\"\\u{1234567}\"", "Invalid unicode escape"),
+            ("\"unterminated\\\"", "Unterminated string"),
+        ];
+
+        for (source, message) in cases {
+            let mut scanner = Scanner::new(source);
+            let result = scanner.scan_token();
+            assert_eq!(result.kind, TokenType::Error, "{}", source);
+            assert_eq!(result.src, message);
+
+            let result = scanner.scan_token();
+            assert_eq!(result.kind, TokenType::Eof);
+        }
+    }
+
+    #[test]
+    fn unescape_string() {
+        assert_eq!(unescape("plain").unwrap(), "plain");
+        assert_eq!(unescape("\\\"\\\\").unwrap(), "\"\\");
+        assert_eq!(unescape("a\\nb\\tc\\r\\0").unwrap(), "a\nb\tc\r\0");
+        assert_eq!(unescape("\\u{41}\\u{1F600}").unwrap(), "A\u{1F600}");
+        assert_eq!(unescape("\\").unwrap_err(), "Unterminated escape sequence");
+    }
+
     #[test]
     fn scan_lines() {
         let source = "\"multi\nline\nstring\n\"";
@@ -382,10 +724,57 @@ mod tests {
         let result = scanner.scan_token();
         assert_eq!(result.kind, TokenType::String);
         assert_eq!(result.src, "multi\nline\nstring\n");
-        assert_eq!(result.line, 4);
+        // tokens are positioned at their first character
+        assert_eq!(result.line, 1);
+        assert_eq!(result.column, 1);
 
         let result = scanner.scan_token();
         assert_eq!(result.kind, TokenType::Eof);
         assert_eq!(result.line, 4);
     }
+
+    #[test]
+    fn scan_spans_and_columns() {
+        let source = "(a\n  :key \"text\")";
+        let mut scanner = Scanner::new(source);
+        let expected = [
+            (TokenType::LeftParen, Span::new(0, 1), 1, 1),
+            (TokenType::Identifier, Span::new(1, 2), 1, 2),
+            (TokenType::Keyword, Span::new(5, 9), 2, 3),
+            (TokenType::String, Span::new(10, 16), 2, 8),
+            (TokenType::RightParen, Span::new(16, 17), 2, 14),
+            (TokenType::Eof, Span::new(17, 17), 2, 15),
+        ];
+
+        for (kind, span, line, column) in expected {
+            let result = scanner.scan_token();
+            assert_eq!(result.kind, kind);
+            assert_eq!(result.span, span);
+            assert_eq!((result.line, result.column), (line, column));
+        }
+    }
+
+    #[test]
+    fn scan_utf8_source() {
+        let source = "(\"héllo 😀\" λ x)";
+        let mut scanner = Scanner::new(source);
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::LeftParen);
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::String);
+        assert_eq!(result.src, "héllo 😀");
+        assert_eq!(&source[result.span.start..result.span.end], "\"héllo 😀\"");
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Error);
+        assert_eq!(&source[result.span.start..result.span.end], "λ");
+        assert_eq!(result.column, 12);
+
+        let result = scanner.scan_token();
+        assert_eq!(result.kind, TokenType::Identifier);
+        assert_eq!(result.src, "x");
+        assert_eq!(result.column, 14);
+    }
 }
@@ -10,11 +10,23 @@ pub enum TokenType {
     LeftSquare,
     RightSquare,
     Dispatch,
+    /// `#_`, discards the next form
+    Discard,
+    /// `'`
+    Quote,
+    /// `` ` ``
+    SyntaxQuote,
+    /// `~`
+    Unquote,
+    /// `~@`
+    UnquoteSplicing,
     True,
     False,
     Identifier,
     Keyword,
     String,
+    /// `#"..."`, its source is the pattern without the delimiters
+    Regex,
     IntegerNumber,
     FloatNumber,
     FractionNumber,
@@ -33,11 +45,17 @@ impl Display for TokenType {
             TokenType::LeftSquare => "LeftSquare",
             TokenType::RightSquare => "RightSquare",
             TokenType::Dispatch => "Dispatch",
+            TokenType::Discard => "Discard",
+            TokenType::Quote => "Quote",
+            TokenType::SyntaxQuote => "SyntaxQuote",
+            TokenType::Unquote => "Unquote",
+            TokenType::UnquoteSplicing => "UnquoteSplicing",
             TokenType::True => "True",
             TokenType::False => "False",
             TokenType::Identifier => "Identifier",
             TokenType::Keyword => "Keyword",
             TokenType::String => "String",
+            TokenType::Regex => "Regex",
             TokenType::IntegerNumber => "IntegerNumber",
             TokenType::FloatNumber => "FloatNumber",
             TokenType::FractionNumber => "FractionNumber",
@@ -47,21 +65,59 @@ impl Display for TokenType {
     }
 }
 
+/// Byte range of a token or syntax node in the source text.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+/// Source text between tokens, which does not change the meaning of the program.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum TriviaKind {
+    /// Consecutive spaces, tabs and line breaks
+    Whitespace,
+    Comma,
+    /// `; ...` until the end of the line, without the line break
+    LineComment,
+    /// `;# ... #;`, with the block comments nested in it
+    BlockComment,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Trivia<'a> {
+    pub kind: TriviaKind,
+    pub span: Span,
+    pub text: &'a str,
+}
+
 #[derive(Copy, Clone)]
 pub struct Token<'a> {
     pub kind: TokenType,
-    pub start: usize,
+    pub span: Span,
+    /// Source text of the token. For strings it is the body without quotes,
+    /// for error tokens it is the error message.
     pub src: &'a str,
+    /// 1-based line of the first character of the token
     pub line: usize,
+    /// 1-based column, counted in characters, of the first character of the token
+    pub column: usize,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(kind: TokenType, start: usize, src: &'a str, line: usize) -> Self {
+    pub fn new(kind: TokenType, span: Span, src: &'a str, line: usize, column: usize) -> Self {
         Token {
             kind,
-            start,
+            span,
             src,
             line,
+            column,
         }
     }
 }
@@ -70,19 +126,20 @@ impl Default for Token<'static> {
     fn default() -> Token<'static> {
         Token {
             kind: TokenType::Init,
-            start: 0,
+            span: Span::default(),
             src: "",
             line: 0,
+            column: 0,
         }
     }
 }
 
-impl Display for TokenType {
+impl Display for Token<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
+            f,
             "[{}] \"{}\" {}:{}",
-            self.kind,
-            self.src, self.line, self.start
+            self.kind, self.src, self.line, self.column
         )
     }
 }
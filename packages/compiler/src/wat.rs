@@ -0,0 +1,458 @@
+use crate::emitter::{
+    Blocktype, ExportType, Function, FunctionType, Global, Immediate, Instruction, ModuleBuilder,
+    Opcodes, Valtype,
+};
+use std::str::FromStr;
+
+/// Layout of the function bodies in the text format.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum WatStyle {
+    /// One instruction per line, in the order of the binary
+    #[default]
+    Linear,
+    /// Operands nested into the instruction using them, like `(f32.add (f32.const 1) (f32.const 2))`
+    Folded,
+}
+
+impl FromStr for WatStyle {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "linear" => Ok(WatStyle::Linear),
+            "folded" => Ok(WatStyle::Folded),
+            _ => Err(format!(
+                "Unknown WAT style '{}', expected 'linear' or 'folded'",
+                value
+            )),
+        }
+    }
+}
+
+// https://webassembly.github.io/spec/core/text/index.html
+/// Prints the module in the WebAssembly text format. Indices are written as numbers
+/// with a `(;N;)` comment at the definitions, so the output is stable and diffs
+/// between compiler versions stay small.
+pub fn print_module(module: &ModuleBuilder, style: WatStyle) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+        style,
+        module,
+    };
+    printer.line("(module");
+    printer.indent += 1;
+    for (index, function_type) in module.types().iter().enumerate() {
+        printer.line(&format!(
+            "(type (;{};) (func{}))",
+            index,
+            signature(function_type)
+        ));
+    }
+    if let Some(min_size) = module.table() {
+        printer.line(&format!("(table (;0;) {} funcref)", min_size));
+    }
+    if let Some(min_pages) = module.memory() {
+        printer.line(&format!("(memory (;0;) {})", min_pages));
+    }
+    for (index, global) in module.globals().iter().enumerate() {
+        printer.global(index, global);
+    }
+    for (index, function) in module.functions().iter().enumerate() {
+        printer.function(index, function);
+    }
+    for export in module.exports() {
+        printer.line(&format!(
+            "(export \"{}\" ({} {}))",
+            export.name.escape_default(),
+            export_kind(export.kind),
+            export.index
+        ));
+    }
+    for (index, element) in module.elements().iter().enumerate() {
+        printer.line(&format!(
+            "(elem (;{};) (i32.const {}) func{})",
+            index,
+            element.offset,
+            function_indices(&element.functions)
+        ));
+    }
+    for (index, data) in module.data().iter().enumerate() {
+        printer.line(&format!(
+            "(data (;{};) (i32.const {}) {})",
+            index,
+            data.offset,
+            data_string(&data.bytes)
+        ));
+    }
+    printer.close();
+    printer.out
+}
+
+struct Printer<'a> {
+    out: String,
+    indent: usize,
+    style: WatStyle,
+    module: &'a ModuleBuilder,
+}
+
+impl Printer<'_> {
+    fn line(&mut self, text: &str) {
+        self.out += &"  ".repeat(self.indent);
+        self.out += text;
+        self.out.push('\n');
+    }
+
+    /// Closes the last open form at the end of the previous line.
+    fn close(&mut self) {
+        if self.out.ends_with('\n') {
+            self.out.pop();
+        }
+        self.out += ")\n";
+        self.indent = self.indent.saturating_sub(1);
+    }
+
+    fn global(&mut self, index: usize, global: &Global) {
+        let valtype = if global.mutable {
+            format!("(mut {})", valtype(global.valtype))
+        } else {
+            valtype(global.valtype).to_owned()
+        };
+        let init: Vec<String> = global
+            .init
+            .iter()
+            .map(|instruction| format!("({})", instruction_text(instruction)))
+            .collect();
+        self.line(&format!(
+            "(global (;{};) {} {})",
+            index,
+            valtype,
+            init.join(" ")
+        ));
+    }
+
+    fn function(&mut self, index: usize, function: &Function) {
+        let signature = self
+            .module
+            .types()
+            .get(function.type_index as usize)
+            .map(signature)
+            .unwrap_or_default();
+        self.line(&format!(
+            "(func (;{};) (type {}){}",
+            index, function.type_index, signature
+        ));
+        self.indent += 1;
+        if !function.locals.is_empty() {
+            self.line(&format!("(local{})", valtypes(&function.locals)));
+        }
+        // folded operands waiting for the instruction which consumes them
+        let mut operands: Vec<String> = vec![];
+        for instruction in &function.body {
+            let effect = match self.style {
+                WatStyle::Linear => None,
+                WatStyle::Folded => self.stack_effect(instruction),
+            };
+            match effect {
+                Some((pops, pushes)) if pops <= operands.len() => {
+                    let args = operands.split_off(operands.len() - pops);
+                    let text = format!(
+                        "({})",
+                        [vec![instruction_text(instruction)], args]
+                            .concat()
+                            .join(" ")
+                    );
+                    if pushes == 1 {
+                        operands.push(text);
+                    } else {
+                        // values left below are evaluated before this instruction
+                        self.flush(&mut operands);
+                        self.line(&text);
+                    }
+                }
+                _ => {
+                    self.flush(&mut operands);
+                    self.instruction(instruction);
+                }
+            }
+        }
+        self.flush(&mut operands);
+        self.close();
+    }
+
+    fn stack_effect(&self, instruction: &Instruction) -> Option<(usize, usize)> {
+        match (instruction.opcode, instruction.immediate) {
+            (Opcodes::Call, Immediate::Index(index)) => {
+                let function = self.module.functions().get(index as usize)?;
+                let function_type = self.module.types().get(function.type_index as usize)?;
+                Some((function_type.params.len(), function_type.results.len()))
+            }
+            (Opcodes::CallIndirect, Immediate::Indirect(type_index)) => {
+                let function_type = self.module.types().get(type_index as usize)?;
+                // the table index is the last operand
+                Some((function_type.params.len() + 1, function_type.results.len()))
+            }
+            (opcode, _) => opcode.stack_effect(),
+        }
+    }
+
+    fn flush(&mut self, operands: &mut Vec<String>) {
+        for operand in operands.drain(..) {
+            self.line(&operand);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) {
+        match instruction.opcode {
+            Opcodes::End => {
+                self.indent = self.indent.saturating_sub(1);
+                self.line("end");
+            }
+            Opcodes::Block | Opcodes::Loop | Opcodes::If => {
+                self.line(&instruction_text(instruction));
+                self.indent += 1;
+            }
+            Opcodes::Else => {
+                self.indent = self.indent.saturating_sub(1);
+                self.line("else");
+                self.indent += 1;
+            }
+            _ => self.line(&instruction_text(instruction)),
+        }
+    }
+}
+
+/// Text form of a single instruction, like `global.get 0`.
+pub fn instruction_text(instruction: &Instruction) -> String {
+    let name = instruction.opcode.name();
+    match instruction.immediate {
+        Immediate::None | Immediate::Block(Blocktype::Void) => name.to_owned(),
+        Immediate::Block(Blocktype::F32) => format!("{} (result f32)", name),
+        Immediate::Index(index) => format!("{} {}", name, index),
+        Immediate::Indirect(type_index) => format!("{} (type {})", name, type_index),
+        Immediate::I32(value) => format!("{} {}", name, value),
+        Immediate::F32(value) => format!("{} {}", name, float(value)),
+        Immediate::Memory { align, offset } => {
+            format!("{} offset={} align={}", name, offset, 1u32 << align)
+        }
+    }
+}
+
+// https://webassembly.github.io/spec/core/text/values.html#floating-point
+fn float(value: f32) -> String {
+    if value.is_nan() {
+        "nan".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+// https://webassembly.github.io/spec/core/text/values.html#strings
+/// Bytes of a data segment as a string, printable ASCII as it is and the rest as `\hh`.
+pub fn data_string(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => out += &format!("\\{}", *byte as char),
+            0x20..=0x7e => out.push(*byte as char),
+            _ => out += &format!("\\{:02x}", byte),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Function indices of a table element segment, each after a space.
+pub fn function_indices(functions: &[u32]) -> String {
+    functions
+        .iter()
+        .map(|function| format!(" {}", function))
+        .collect()
+}
+
+pub fn signature(function_type: &FunctionType) -> String {
+    let mut out = String::new();
+    if !function_type.params.is_empty() {
+        out += &format!(" (param{})", valtypes(&function_type.params));
+    }
+    if !function_type.results.is_empty() {
+        out += &format!(" (result{})", valtypes(&function_type.results));
+    }
+    out
+}
+
+pub fn valtypes(types: &[Valtype]) -> String {
+    types.iter().map(|t| format!(" {}", valtype(*t))).collect()
+}
+
+pub fn valtype(valtype: Valtype) -> &'static str {
+    match valtype {
+        Valtype::I32 => "i32",
+        Valtype::F32 => "f32",
+    }
+}
+
+pub fn export_kind(kind: ExportType) -> &'static str {
+    match kind {
+        ExportType::Func => "func",
+        ExportType::Table => "table",
+        ExportType::Mem => "memory",
+        ExportType::Global => "global",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::compile_module;
+    use crate::emitter::{ExportType, FunctionType, Instruction, ModuleBuilder, Opcodes, Valtype};
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::wat::{print_module, WatStyle};
+    use std::fs;
+    use std::path::Path;
+
+    fn example_module() -> ModuleBuilder {
+        let mut builder = ModuleBuilder::new();
+        let type_index =
+            builder.add_type(FunctionType::new(vec![Valtype::F32], vec![Valtype::F32]));
+        let global = builder.add_global(Valtype::F32, true, vec![Instruction::f32_const(0.0)]);
+        let function = builder.add_function(
+            type_index,
+            vec![Valtype::I32, Valtype::I32],
+            vec![
+                Instruction::with_index(Opcodes::GetLocal, 0),
+                Instruction::f32_const(1.5),
+                Instruction::new(Opcodes::F32Add),
+                Instruction::with_index(Opcodes::SetGlobal, global),
+                Instruction::with_index(Opcodes::GetGlobal, global),
+            ],
+        );
+        builder.add_export("inc", ExportType::Func, function);
+        builder
+    }
+
+    #[test]
+    fn print_empty_module() {
+        assert_eq!(
+            print_module(&ModuleBuilder::new(), WatStyle::Linear),
+            "(module)\n"
+        );
+    }
+
+    #[test]
+    fn print_linear_module() {
+        assert_eq!(
+            print_module(&example_module(), WatStyle::Linear),
+            "(module
+  (type (;0;) (func (param f32) (result f32)))
+  (global (;0;) (mut f32) (f32.const 0))
+  (func (;0;) (type 0) (param f32) (result f32)
+    (local i32 i32)
+    local.get 0
+    f32.const 1.5
+    f32.add
+    global.set 0
+    global.get 0)
+  (export \"inc\" (func 0)))
+"
+        );
+    }
+
+    #[test]
+    fn print_folded_module() {
+        assert_eq!(
+            print_module(&example_module(), WatStyle::Folded),
+            "(module
+  (type (;0;) (func (param f32) (result f32)))
+  (global (;0;) (mut f32) (f32.const 0))
+  (func (;0;) (type 0) (param f32) (result f32)
+    (local i32 i32)
+    (global.set 0 (f32.add (local.get 0) (f32.const 1.5)))
+    (global.get 0))
+  (export \"inc\" (func 0)))
+"
+        );
+    }
+
+    #[test]
+    fn fold_calls_and_keep_evaluation_order() {
+        let mut builder = ModuleBuilder::new();
+        let unary = builder.add_type(FunctionType::new(vec![Valtype::F32], vec![Valtype::F32]));
+        let negate = builder.add_function(
+            unary,
+            vec![],
+            vec![
+                Instruction::with_index(Opcodes::GetLocal, 0),
+                Instruction::new(Opcodes::F32Neg),
+            ],
+        );
+        builder.add_function(
+            unary,
+            vec![],
+            vec![
+                Instruction::f32_const(1.0),
+                Instruction::f32_const(2.0),
+                Instruction::with_index(Opcodes::SetLocal, 0),
+                Instruction::with_index(Opcodes::Call, negate),
+                Instruction::f32_const(3.0),
+                Instruction::with_index(Opcodes::Call, negate),
+                Instruction::new(Opcodes::F32Add),
+            ],
+        );
+
+        let wat = print_module(&builder, WatStyle::Folded);
+
+        assert!(
+            wat.contains(
+                "  (func (;1;) (type 0) (param f32) (result f32)
+    (f32.const 1)
+    (local.set 0 (f32.const 2))
+    call 0
+    (call 0 (f32.const 3))
+    f32.add)"
+            ),
+            "{}",
+            wat
+        );
+    }
+
+    #[test]
+    fn parse_wat_style() {
+        assert_eq!("linear".parse(), Ok(WatStyle::Linear));
+        assert_eq!("folded".parse(), Ok(WatStyle::Folded));
+        assert!("nested".parse::<WatStyle>().is_err());
+    }
+
+    /// Compares the compiled form of every `golden/*.pl` with the `.wat` file next to it.
+    /// Run the tests with `UPDATE_GOLDEN=1` to accept the new output.
+    #[test]
+    fn golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden");
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+        let mut sources: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pl"))
+            .collect();
+        sources.sort();
+        assert!(!sources.is_empty());
+
+        for source_path in sources {
+            let source = fs::read_to_string(&source_path).unwrap();
+            let mut scanner = Scanner::new(&source);
+            let mut parser = Parser::new(&mut scanner);
+            let module = compile_module(parser.parse().unwrap()).unwrap();
+            let wat = print_module(&module, WatStyle::Folded);
+
+            let wat_path = source_path.with_extension("wat");
+            if update {
+                fs::write(&wat_path, &wat).unwrap();
+            } else {
+                let expected = fs::read_to_string(&wat_path).unwrap_or_default();
+                assert_eq!(wat, expected, "{}", wat_path.display());
+            }
+        }
+    }
+}